@@ -0,0 +1,730 @@
+//! ウィンドウを作らずにROMを実行するランナー。CIでのテストROMの自動実行用。
+//!
+//! Usage: `fami-headless <rom> [--frames <N>] [--region <ntsc|pal|dendy>] [--model <front-loader|top-loader>] [--blargg] [--trace] [--nestest-log <log> [--nestest-log-ppu]] [--start-pc <addr>]`
+//!        `fami-headless <rom> --trace-file <file> [--trace-fields <list>] [--trace-pc <start>-<end>] [--trace-bank <list>] [--trace-start <addr|frame:N>] [--trace-stop <addr|frame:N>] [--trace-ring <N>]`
+//!        `fami-headless <rom> --disasm [--origin <addr>] [--symbols <file>]`
+//!        `fami-headless <rom> --debug [--symbols <file>]`
+//!        `fami-headless <rom> --gdb <port>`
+//!        `fami-headless <rom> --cdl <file> [--frames <N>]`
+//!        `fami-headless <rom> --profile <file> [--profile-collapsed <file>] [--frames <N>]`
+//!        `fami-headless <rom> --dump-ppu <dir> [--pattern-palette <0-7>] [--frames <N>]`
+//!        `fami-headless <rom> --events <file> [--events-list <file>] [--frames <N>]`
+//!
+//! * `--frames <N>`: 最大で N フレーム実行する。(既定値: 3600 = 約60秒)
+//! * `--region <ntsc|pal|dendy>`: ROMのヘッダの指定に関わらず、指定した地域として実行する。
+//! * `--model <front-loader|top-loader>`: 本体の型式。上面挿入型(とファミコン)では、
+//!   リセット時にPPUがリセットされない。(既定値: front-loader)
+//! * `--blargg`: blargg氏のテストROMが $6000 以降に書き込む結果を監視し、
+//!   テストが完了した時点で、ROMの結果コードを終了コードとして終了する。
+//! * `--trace`: 1命令ごとに、nestest.log形式のトレースを標準出力に出力する。
+//!   `--debug` や `--blargg` など、他のモードと組み合わせられる。
+//! * `--trace-file <file>`: トレースを標準出力ではなくファイルに出力する。(`--trace` を含む)
+//! * `--trace-fields <list>`: トレースに出力する項目をカンマ区切りで指定する。
+//!   `regs`, `ppu`, `cycles`, `bank` または `none`。(既定値: regs,ppu,cycles)
+//! * `--trace-pc <start>-<end>`: PCがこの範囲にある命令だけを記録する。
+//! * `--trace-bank <list>`: PCがこのPRG-ROMのバンク(16KB単位、16進数、カンマ区切り)にある命令だけを記録する。
+//! * `--trace-start <addr|frame:N>`: PCがアドレスに来るか、N フレーム目に達したら記録を始める。
+//! * `--trace-stop <addr|frame:N>`: PCがアドレスに来るか、N フレーム目に達したら記録を終える。
+//! * `--trace-ring <N>`: 直近の N 行だけをメモリ上に保持し、デバッガーがブレークポイントや
+//!   ウォッチポイントで止まったときと、エミュレーターがパニックしたときに出力する。(`--trace` を含む)
+//! * `--nestest-log <log>`: 1命令ごとにトレースをリファレンスのログと比較し、
+//!   最初に一致しなかった行を報告して終了する。
+//! * `--nestest-log-ppu`: `--nestest-log` での比較に、PPUの位置も含める。
+//! * `--start-pc <addr>`: リセット後、PCを指定したアドレス(16進数)に書き換えてから実行する。
+//!   nestestを画面なしで実行する場合は `--start-pc C000` を指定する。
+//! * `--disasm`: 実行せずに、PRG-ROMを逆アセンブルして標準出力に出力する。
+//!   iNES形式でないファイルは、PRG-ROMをそのまま吸い出したものとして扱う。
+//!   32KBを超えるPRG-ROMは、16KBのバンクごとに $8000 に配置して出力する。
+//! * `--debug`: 対話式のデバッガーを起動する。標準入力からコマンドを読み、`help` で一覧を表示する。
+//! * `--gdb <port>`: 127.0.0.1 の port で GDB のリモートプロトコルの接続を待ち受け、
+//!   GDB互換のフロントエンドから操作する(`target remote localhost:<port>`)。
+//! * `--origin <addr>`: `--disasm` で、PRG-ROMの先頭を配置するアドレス(16進数)。
+//!   (既定値: アドレス空間の末尾に詰めた位置。16KBなら $C000、32KBなら $8000)
+//! * `--symbols <file>`: シンボルファイル(ca65 の .dbg, FCEUX の .nl, Mesen の .mlb)を読み込み、
+//!   トレースや逆アセンブル結果にラベルを表示する。デバッガーではアドレスにラベルを使える。
+//!   複数指定できる。ROMと同じ場所にある `game.dbg`, `game.mlb`, `game.nes.*.nl` は自動で読み込む。
+//! * `--cdl <file>`: 実行中にPRG-ROMとCHR-ROMの使われ方を記録し、終了時にFCEUX互換の .cdl 形式で
+//!   書き出す。集計を標準エラー出力に表示する。他のモードと組み合わせられる。
+//! * `--profile <file>`: サブルーチンごとのCPUサイクル数(1フレームあたりの平均と最大を含む)を集計し、
+//!   終了時に表形式でファイルに書き出す。`-` なら標準出力に出力する。他のモードと組み合わせられる。
+//! * `--profile-collapsed <file>`: 同じ集計を collapsed stack 形式で書き出す。
+//!   flamegraph.pl や inferno でフレームグラフにできる。
+//! * `--dump-ppu <dir>`: 終了時のVRAMとOAMの内容を、dir にPNG画像として書き出す。
+//!   パターンテーブル(`pattern_table_0.png`, `pattern_table_1.png`)、
+//!   スクロール位置の枠付きのネームテーブル(`nametables.png`)、スプライトのプレビュー(`oam.png`)と
+//!   一覧(`oam.txt`)、パレット(`palette.png`)。
+//! * `--pattern-palette <0-7>`: `--dump-ppu` でパターンテーブルの着色に使うパレット番号。
+//!   0-3 がBG、4-7 がスプライト。(既定値: 0)
+//! * `--events <file>`: PPU、OAM DMA、APU、マッパーのレジスタへの書き込みを記録し、
+//!   最後に描画を完了したフレームの記録を、341ドット x 全スキャンラインの格子に重ねたPNG画像で書き出す。
+//! * `--events-list <file>`: 同じ記録を、書き込んだ位置(スキャンライン, ドット)付きの一覧で書き出す。
+//!   `-` なら標準出力に出力する。
+//!
+//! 終了コード:
+//! * `--blargg` 指定時は、ROMの結果コード(0 = 成功)。
+//! * `--nestest-log` 指定時は、ログの最後まで一致すれば 0、一致しなければ 1。
+//! * `--blargg` 未指定時は、N フレーム実行したら 0。
+//! * 結果が出る前に N フレームに達した場合は 124。
+//! * エミュレーターがパニックした場合は 101。
+//! * 引数やROMの読み込みに問題があった場合と、ROMのマッパーが未実装の場合は 255。
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use fami_rust::nes::cpu::disasm;
+use fami_rust::nes::debugger::Debugger;
+use fami_rust::nes::debugger::command::Command;
+use fami_rust::nes::debugger::gdb::{self, GdbStub};
+use fami_rust::nes::debugger::symbols::{BankedLabels, SymbolTable};
+use fami_rust::nes::debugger::tracer::{Tracer, Trigger};
+use fami_rust::nes::debugger::cdl::CodeDataLogger;
+use fami_rust::nes::debugger::profiler::Profiler;
+use fami_rust::nes::debugger::events::{self, GRID_WIDTH};
+use fami_rust::nes::ppu::viewer::{self, IndexedImage, NAMETABLES_WIDTH};
+use fami_rust::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use fami_rust::Palette;
+use fami_rust::nes::cpu::trace::{Comparison, LogComparator, TraceFields};
+use fami_rust::nes::rom;
+use fami_rust::nes::region::Region;
+use fami_rust::nes::console::ConsoleModel;
+use fami_rust::Console;
+
+/// 既定の最大実行フレーム数
+const DEFAULT_MAX_FRAMES: u64 = 3600;
+/// 結果が出る前にタイムアウトした場合の終了コード
+const EXIT_TIMEOUT: i32 = 124;
+/// リファレンスのログと一致しなかった場合の終了コード
+const EXIT_DIVERGED: i32 = 1;
+/// PRG-ROMのバンクのサイズ(バイト)
+const PRG_BANK_SIZE: usize = 0x4000;
+/// PRG-ROMが配置されるCPUのアドレス空間の大きさ(バイト)
+const PRG_WINDOW_SIZE: usize = 0x8000;
+/// ランナー自体のエラーによる終了コード
+const EXIT_ERROR: i32 = 255;
+/// エミュレーターがパニックした場合の終了コード(Rustのパニック時と同じ)
+const EXIT_PANIC: i32 = 101;
+
+// blargg氏のテストROMの出力プロトコル
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+/// テストの状態。$80 = 実行中、$81 = リセット要求、$00-$7F = 結果コード。
+const ADDR_BLARGG_STATUS: u16 = 0x6000;
+/// $DE $B0 $61 が書き込まれていれば、$6000 以降の値は有効。
+const ADDR_BLARGG_SIGNATURE: u16 = 0x6001;
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// 結果の説明文(NULL終端)
+const ADDR_BLARGG_TEXT: u16 = 0x6004;
+const BLARGG_STATUS_RUNNING: u8 = 0x80;
+const BLARGG_STATUS_NEED_RESET: u8 = 0x81;
+/// リセット要求から実際にリセットするまでの待ち時間。
+/// 少なくとも100msec待つ必要がある。
+const BLARGG_RESET_DELAY_FRAMES: u64 = 10;
+
+struct Options {
+    rom_path: String,
+    max_frames: u64,
+    blargg: bool,
+    trace: bool,
+    trace_file: Option<String>,
+    trace_fields: TraceFields,
+    trace_pc: Option<String>,
+    trace_banks: Option<Vec<usize>>,
+    trace_start: Option<String>,
+    trace_stop: Option<String>,
+    trace_ring: Option<usize>,
+    nestest_log: Option<String>,
+    nestest_log_ppu: bool,
+    start_pc: Option<u16>,
+    region: Option<Region>,
+    model: ConsoleModel,
+    disasm: bool,
+    origin: Option<u16>,
+    debug: bool,
+    gdb_port: Option<u16>,
+    symbol_files: Vec<String>,
+    cdl_file: Option<String>,
+    profile_file: Option<String>,
+    profile_collapsed_file: Option<String>,
+    dump_ppu_dir: Option<String>,
+    pattern_palette: usize,
+    events_file: Option<String>,
+    events_list_file: Option<String>,
+}
+
+fn main() {
+    env_logger::builder()
+        .format_timestamp(None)
+        .init();
+
+    let opts = parse_args(std::env::args().skip(1).collect());
+    let symbols = load_symbols(&opts);
+
+    if opts.disasm {
+        print_disasm(&opts, &symbols);
+    }
+
+    let rom = match rom::load_from_file(&opts.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => err_exit(&err.to_string()),
+    };
+    if let Err(err) = rom.check_mapper_supported() {
+        err_exit(&err.to_string());
+    }
+
+    let region = opts.region.unwrap_or_else(|| Region::from_rom(&rom));
+    let mut console = Console::with_region(&rom, region);
+    console.set_model(opts.model);
+    console.set_symbols(symbols);
+    let tracer = create_tracer(&opts, &console);
+    console.set_tracer(tracer);
+    if opts.cdl_file.is_some() {
+        console.set_cdl(Some(CodeDataLogger::new(&rom)));
+    }
+    if opts.profile_file.is_some() || opts.profile_collapsed_file.is_some() {
+        console.set_profiler(Some(Profiler::new()));
+    }
+    if opts.events_file.is_some() || opts.events_list_file.is_some() {
+        console.set_event_logging(true);
+    }
+    console.power_on();
+
+    // パニックした場合も、トレースのリングバッファに残っている直前の実行履歴を書き出す。
+    let code = match panic::catch_unwind(AssertUnwindSafe(|| run(&mut console, &opts))) {
+        Ok(code) => code,
+        Err(_) => {
+            if let Some(tracer) = console.tracer_mut() {
+                tracer.dump_ring();
+            }
+            EXIT_PANIC
+        },
+    };
+    if let Some(mut tracer) = console.take_tracer() {
+        if let Err(err) = tracer.finish() {
+            eprintln!("Failed to write the trace: {}", err);
+        }
+    }
+    if let (Some(path), Some(cdl)) = (&opts.cdl_file, console.take_cdl()) {
+        match cdl.save(Path::new(path)) {
+            Ok(()) => eprintln!("{}", cdl.summary()),
+            Err(err) => eprintln!("Failed to write the CDL file: {}", err),
+        }
+    }
+    if let Some(profiler) = console.profiler() {
+        if let Some(path) = &opts.profile_file {
+            write_output(path, &profiler.report(&console), "profile");
+        }
+        if let Some(path) = &opts.profile_collapsed_file {
+            write_output(path, &profiler.collapsed(&console), "collapsed profile");
+        }
+    }
+    if let Some(dir) = &opts.dump_ppu_dir {
+        if let Err(err) = dump_ppu(&console, Path::new(dir), opts.pattern_palette) {
+            eprintln!("Failed to write the PPU views: {}", err);
+        }
+    }
+    if let Some(path) = &opts.events_file {
+        let scanlines = console.region().scanlines_per_frame() as usize;
+        let mut picture = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        console.frame_rgba(&Palette::ntsc_2c02(), &mut picture);
+        let rgba = events::render_grid(console.event_log().last_frame(), scanlines, Some(&picture));
+        if let Err(err) = fs::write(path, viewer::encode_png(GRID_WIDTH, scanlines, &rgba)) {
+            eprintln!("Failed to write the event view: {}", err);
+        }
+    }
+    if let Some(path) = &opts.events_list_file {
+        write_output(path, &events::format_list(console.event_log().last_frame()), "event list");
+    }
+    std::process::exit(code);
+}
+
+/// ネームテーブルの画像に重ねる、スクロール位置の枠の色
+const OVERLAY_COLOR: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+/// VRAMとOAMの内容を dir にPNG画像で書き出す。
+fn dump_ppu(console: &Console, dir: &Path, pattern_palette: usize) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let palette = Palette::ntsc_2c02();
+    let save = |name: &str, image: &IndexedImage, rgba: Option<Vec<u8>>| {
+        let rgba = rgba.unwrap_or_else(|| image.to_rgba(&palette));
+        fs::write(dir.join(name), viewer::encode_png(image.width, image.height, &rgba))
+    };
+    let ppu = console.ppu().borrow();
+    for table in 0..2 {
+        save(&format!("pattern_table_{}.png", table), &ppu.pattern_table_view(table, pattern_palette), None)?;
+    }
+    let nametables = ppu.nametable_view();
+    let mut rgba = nametables.to_rgba(&palette);
+    let (x, y) = ppu.scroll_window();
+    viewer::draw_wrapping_rect(&mut rgba, NAMETABLES_WIDTH, (x, y, SCREEN_WIDTH, SCREEN_HEIGHT), OVERLAY_COLOR);
+    save("nametables.png", &nametables, Some(rgba))?;
+    save("oam.png", &ppu.oam_view(), None)?;
+    save("palette.png", &ppu.palette_view(), None)?;
+
+    let mut list = String::from("  #   X   Y  tile  attr  pal  flip  priority\n");
+    for sprite in ppu.sprites() {
+        let flip = match (sprite.flip_h(), sprite.flip_v()) {
+            (false, false) => "-",
+            (true, false) => "H",
+            (false, true) => "V",
+            (true, true) => "HV",
+        };
+        let priority = if sprite.behind_bg() { "back" } else { "front" };
+        list += &format!("{:>3} {:>3} {:>3}   ${:02X}   ${:02X}  {:>3}  {:>4}  {}\n",
+            sprite.index, sprite.x, sprite.y, sprite.tile, sprite.attr, sprite.palette(), flip, priority);
+    }
+    fs::write(dir.join("oam.txt"), list)
+}
+
+/// path にテキストを書き出す。path が `-` なら標準出力に出力する。
+fn write_output(path: &str, text: &str, what: &str) {
+    let result = if path == "-" {
+        std::io::stdout().write_all(text.as_bytes())
+    } else {
+        fs::write(path, text)
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to write the {}: {}", what, err);
+    }
+}
+
+/// 指定されたモードで実行し、終了コードを返す。
+fn run(console: &mut Console, opts: &Options) -> i32 {
+    if opts.debug {
+        return run_debugger(console);
+    }
+
+    if let Some(port) = opts.gdb_port {
+        return run_gdb_stub(console, port);
+    }
+
+    if opts.nestest_log.is_some() || opts.start_pc.is_some() {
+        return run_by_instruction(console, opts);
+    }
+
+    // リセット要求を受け付けたフレーム
+    let mut reset_requested_at: Option<u64> = None;
+
+    while console.frame_count() < opts.max_frames {
+        console.step_frame();
+
+        if !opts.blargg || !has_blargg_signature(console) {
+            continue;
+        }
+
+        match console.peek(ADDR_BLARGG_STATUS) {
+            BLARGG_STATUS_RUNNING => (),
+            BLARGG_STATUS_NEED_RESET => {
+                let requested_at = *reset_requested_at.get_or_insert(console.frame_count());
+                if console.frame_count() - requested_at >= BLARGG_RESET_DELAY_FRAMES {
+                    console.reset();
+                    reset_requested_at = None;
+                }
+            },
+            result if result < BLARGG_STATUS_RUNNING => {
+                print!("{}", read_blargg_text(console));
+                println!("result: {:#04X} (frame {})", result, console.frame_count());
+                return result as i32;
+            },
+            _ => (),
+        }
+    }
+
+    if opts.blargg {
+        eprintln!("Timed out after {} frames.", opts.max_frames);
+        if has_blargg_signature(console) {
+            eprint!("{}", read_blargg_text(console));
+        }
+        return EXIT_TIMEOUT;
+    }
+    0
+}
+
+/// --trace 系のオプションからトレーサーを作る。--trace, --trace-file, --trace-ring のいずれも
+/// 指定されていなければ None。
+fn create_tracer(opts: &Options, console: &Console) -> Option<Tracer> {
+    if !opts.trace && opts.trace_file.is_none() && opts.trace_ring.is_none() {
+        return None;
+    }
+    let output: Box<dyn Write> = match &opts.trace_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)
+            .unwrap_or_else(|e| err_exit(&format!("Failed to create {}: {}", path, e))))),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+    let resolve_addr = |text: &str| console.resolve_addr(text);
+    let mut tracer = Tracer::new(output);
+    tracer.set_fields(opts.trace_fields);
+    if let Some(range) = &opts.trace_pc {
+        let (start, end) = range.split_once('-')
+            .and_then(|(start, end)| Some((resolve_addr(start)?, resolve_addr(end)?)))
+            .filter(|(start, end)| start <= end)
+            .unwrap_or_else(|| err_exit("--trace-pc requires an address range: <start>-<end>"));
+        tracer.set_pc_range(Some(start..=end));
+    }
+    tracer.set_banks(opts.trace_banks.clone());
+    let trigger = |name: &str, text: &Option<String>| text.as_ref().map(|text| {
+        Trigger::parse(text, &resolve_addr)
+            .unwrap_or_else(|| err_exit(&format!("{} requires an address or frame:<N>", name)))
+    });
+    tracer.set_start(trigger("--trace-start", &opts.trace_start));
+    tracer.set_stop(trigger("--trace-stop", &opts.trace_stop));
+    tracer.set_ring(opts.trace_ring);
+    Some(tracer)
+}
+
+/// ROMと同じ場所にあるシンボルファイルと、--symbols で指定したシンボルファイルを読み込む。
+fn load_symbols(opts: &Options) -> SymbolTable {
+    let mut symbols = SymbolTable::load_beside_rom(Path::new(&opts.rom_path))
+        .unwrap_or_else(|err| err_exit(&err.to_string()));
+    for path in &opts.symbol_files {
+        symbols.extend(SymbolTable::load_from_file(Path::new(path))
+            .unwrap_or_else(|err| err_exit(&err.to_string())));
+    }
+    symbols
+}
+
+/// PRG-ROMを逆アセンブルして出力する。
+fn print_disasm(opts: &Options, symbols: &SymbolTable) -> ! {
+    let bin = fs::read(&opts.rom_path)
+        .unwrap_or_else(|e| err_exit(&format!("Failed to open {}: {}", opts.rom_path, e)));
+    let rom = rom::parse(&bin).ok();
+    let prg = match rom.as_ref() {
+        Some(rom) => rom.prg_rom(),
+        None => &bin[..],
+    };
+
+    // (バンク番号, 配置するアドレス, 内容)
+    let banks: Vec<(Option<usize>, u16, &[u8])> = if prg.len() <= PRG_WINDOW_SIZE || opts.origin.is_some() {
+        // PRG-ROMはアドレス空間の末尾に詰めて配置される(16KBなら $C000)。
+        let origin = opts.origin.unwrap_or((0x10000 - prg.len().max(1)) as u16);
+        vec![(None, origin, prg)]
+    } else {
+        prg.chunks(PRG_BANK_SIZE)
+            .enumerate()
+            .map(|(bank, bin)| (Some(bank), 0x8000, bin))
+            .collect()
+    };
+
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for (bank, origin, bin) in banks {
+        if let Some(bank) = bank {
+            writeln!(out, "; bank {}", bank).unwrap_or_else(|e| err_exit(&e.to_string()));
+        }
+        // 出力中のバンクが見えているものとして、ラベルを引く。
+        let base = bank.unwrap_or(0) * PRG_BANK_SIZE;
+        let prg_offset_of = |addr: u16| {
+            let pos = addr.wrapping_sub(origin) as usize;
+            (pos < bin.len()).then_some(base + pos)
+        };
+        let labels = BankedLabels::new(symbols, prg_offset_of);
+        for line in disasm::disasm_bytes_with(bin, origin, &labels) {
+            if let Some(label) = symbols.label_at(line.addr, prg_offset_of(line.addr)) {
+                writeln!(out, "{}:", label).unwrap_or_else(|e| err_exit(&e.to_string()));
+            }
+            writeln!(out, "{}", line).unwrap_or_else(|e| err_exit(&e.to_string()));
+        }
+    }
+    let _ = out.flush();
+    std::process::exit(0);
+}
+
+/// 標準入力からコマンドを読み、デバッガーを操作する。
+fn run_debugger(console: &mut Console) -> i32 {
+    // リセットシーケンスを終え、最初の命令の直前で止める。
+    console.step_instruction();
+    let mut debugger = Debugger::new(console);
+    println!("{}", console.trace_line());
+
+    let stdin = std::io::stdin();
+    let mut last_command: Option<Command> = None;
+    loop {
+        print!("(fami) ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        // 空行は直前のコマンドを繰り返す。
+        let command = if line.trim().is_empty() {
+            match last_command.clone() {
+                Some(command) => command,
+                None => continue,
+            }
+        } else {
+            match Command::parse_with(&line, &|text| console.resolve_addr(text)) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                },
+            }
+        };
+        if command == Command::Quit {
+            break;
+        }
+        println!("{}", debugger.execute(console, &command));
+        last_command = Some(command);
+    }
+    0
+}
+
+/// GDBからの接続を1つ受け付け、切断されるまで操作を受け付ける。
+fn run_gdb_stub(console: &mut Console, port: u16) -> i32 {
+    // リセットシーケンスを終え、最初の命令の直前で止める。
+    console.step_instruction();
+    eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+    let mut stream = gdb::accept(port)
+        .unwrap_or_else(|err| err_exit(&format!("Failed to listen on port {}: {}", port, err)));
+    let mut stub = GdbStub::new(console);
+    if let Err(err) = stub.serve(console, &mut stream) {
+        err_exit(&format!("gdb connection error: {}", err));
+    }
+    0
+}
+
+/// 1命令ずつ実行し、リファレンスのログとの比較を行う。
+fn run_by_instruction(console: &mut Console, opts: &Options) -> i32 {
+    // リセットシーケンスを終え、最初の命令の直前まで進める。
+    console.step_instruction();
+    if let Some(pc) = opts.start_pc {
+        console.cpu_mut().regs_mut().pc = pc;
+    }
+
+    let mut comparator = opts.nestest_log.as_ref().map(|path| {
+        let file = File::open(path)
+            .unwrap_or_else(|e| err_exit(&format!("Failed to open {}: {}", path, e)));
+        LogComparator::new(BufReader::new(file), opts.nestest_log_ppu)
+    });
+    let mut matched_lines: u64 = 0;
+
+    while console.frame_count() < opts.max_frames {
+        if let Some(comparator) = comparator.as_mut() {
+            let result = comparator.compare(&console.trace_line());
+            match result {
+                Comparison::Matched => matched_lines += 1,
+                Comparison::Finished => {
+                    flush_trace(console);
+                    println!("Matched all {} lines.", matched_lines);
+                    return 0;
+                },
+                Comparison::Diverged(divergence) => {
+                    flush_trace(console);
+                    println!("{}", divergence);
+                    return EXIT_DIVERGED;
+                },
+            }
+        }
+        console.step_instruction();
+    }
+
+    if comparator.is_some() {
+        eprintln!("Timed out after {} frames.", opts.max_frames);
+        return EXIT_TIMEOUT;
+    }
+    0
+}
+
+/// 標準出力に書いているトレースが、後に続く出力と前後しないようにする。
+fn flush_trace(console: &mut Console) {
+    if let Some(tracer) = console.tracer_mut() {
+        tracer.flush();
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Options {
+    let mut rom_path: Option<String> = None;
+    let mut max_frames = DEFAULT_MAX_FRAMES;
+    let mut blargg = false;
+    let mut trace = false;
+    let mut trace_file: Option<String> = None;
+    let mut trace_fields = TraceFields::NESTEST;
+    let mut trace_pc: Option<String> = None;
+    let mut trace_banks: Option<Vec<usize>> = None;
+    let mut trace_start: Option<String> = None;
+    let mut trace_stop: Option<String> = None;
+    let mut trace_ring: Option<usize> = None;
+    let mut nestest_log: Option<String> = None;
+    let mut nestest_log_ppu = false;
+    let mut start_pc: Option<u16> = None;
+    let mut region: Option<Region> = None;
+    let mut model = ConsoleModel::default();
+    let mut disasm = false;
+    let mut origin: Option<u16> = None;
+    let mut debug = false;
+    let mut gdb_port: Option<u16> = None;
+    let mut symbol_files: Vec<String> = Vec::new();
+    let mut cdl_file: Option<String> = None;
+    let mut profile_file: Option<String> = None;
+    let mut profile_collapsed_file: Option<String> = None;
+    let mut dump_ppu_dir: Option<String> = None;
+    let mut pattern_palette = 0;
+    let mut events_file: Option<String> = None;
+    let mut events_list_file: Option<String> = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                max_frames = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| err_exit("--frames requires a number."));
+            },
+            "--region" => {
+                region = Some(args.next()
+                    .and_then(|name| Region::from_name(&name))
+                    .unwrap_or_else(|| err_exit("--region requires one of: ntsc, pal, dendy")));
+            },
+            "--model" => {
+                model = args.next()
+                    .and_then(|name| ConsoleModel::from_name(&name))
+                    .unwrap_or_else(|| err_exit("--model requires one of: front-loader, top-loader"));
+            },
+            "--blargg" => blargg = true,
+            "--trace" => trace = true,
+            "--trace-file" => {
+                trace_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--trace-file requires a file.")));
+            },
+            "--trace-fields" => {
+                trace_fields = args.next()
+                    .and_then(|names| TraceFields::from_names(&names))
+                    .unwrap_or_else(|| err_exit("--trace-fields requires a list of: regs, ppu, cycles, bank, none"));
+            },
+            "--trace-pc" => {
+                trace_pc = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--trace-pc requires an address range: <start>-<end>")));
+            },
+            "--trace-bank" => {
+                trace_banks = Some(args.next()
+                    .and_then(|banks| banks.split(',')
+                        .map(|bank| usize::from_str_radix(bank.trim(), 16).ok())
+                        .collect())
+                    .unwrap_or_else(|| err_exit("--trace-bank requires a list of hex bank numbers.")));
+            },
+            "--trace-start" => {
+                trace_start = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--trace-start requires an address or frame:<N>")));
+            },
+            "--trace-stop" => {
+                trace_stop = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--trace-stop requires an address or frame:<N>")));
+            },
+            "--trace-ring" => {
+                trace_ring = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| err_exit("--trace-ring requires a number of lines.")));
+            },
+            "--nestest-log" => {
+                nestest_log = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--nestest-log requires a log file.")));
+            },
+            "--nestest-log-ppu" => nestest_log_ppu = true,
+            "--start-pc" => {
+                start_pc = Some(args.next()
+                    .and_then(|addr| u16::from_str_radix(addr.trim_start_matches('$'), 16).ok())
+                    .unwrap_or_else(|| err_exit("--start-pc requires a hex address.")));
+            },
+            "--disasm" => disasm = true,
+            "--debug" => debug = true,
+            "--gdb" => {
+                gdb_port = Some(args.next()
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or_else(|| err_exit("--gdb requires a port number.")));
+            },
+            "--symbols" => {
+                symbol_files.push(args.next()
+                    .unwrap_or_else(|| err_exit("--symbols requires a symbol file.")));
+            },
+            "--cdl" => {
+                cdl_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--cdl requires a file.")));
+            },
+            "--profile" => {
+                profile_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--profile requires a file.")));
+            },
+            "--profile-collapsed" => {
+                profile_collapsed_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--profile-collapsed requires a file.")));
+            },
+            "--dump-ppu" => {
+                dump_ppu_dir = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--dump-ppu requires a directory.")));
+            },
+            "--pattern-palette" => {
+                pattern_palette = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n < 8)
+                    .unwrap_or_else(|| err_exit("--pattern-palette requires a palette number (0-7)."));
+            },
+            "--events" => {
+                events_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--events requires a file.")));
+            },
+            "--events-list" => {
+                events_list_file = Some(args.next()
+                    .unwrap_or_else(|| err_exit("--events-list requires a file.")));
+            },
+            "--origin" => {
+                origin = Some(args.next()
+                    .and_then(|addr| u16::from_str_radix(addr.trim_start_matches('$'), 16).ok())
+                    .unwrap_or_else(|| err_exit("--origin requires a hex address.")));
+            },
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg),
+            _ => err_exit(&format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    Options {
+        rom_path: rom_path.unwrap_or_else(|| err_exit("Require rom image file.")),
+        max_frames,
+        blargg,
+        trace,
+        trace_file,
+        trace_fields,
+        trace_pc,
+        trace_banks,
+        trace_start,
+        trace_stop,
+        trace_ring,
+        nestest_log,
+        nestest_log_ppu,
+        start_pc,
+        region,
+        model,
+        disasm,
+        origin,
+        debug,
+        gdb_port,
+        symbol_files,
+        cdl_file,
+        profile_file,
+        profile_collapsed_file,
+        dump_ppu_dir,
+        pattern_palette,
+        events_file,
+        events_list_file,
+    }
+}
+
+fn has_blargg_signature(console: &Console) -> bool {
+    BLARGG_SIGNATURE.iter().enumerate()
+        .all(|(i, &sig)| console.peek(ADDR_BLARGG_SIGNATURE + i as u16) == sig)
+}
+
+fn read_blargg_text(console: &Console) -> String {
+    // $6004 から $7FFF までの範囲で、NULL終端の文字列を読む。
+    let text: Vec<u8> = (ADDR_BLARGG_TEXT..0x8000)
+        .map(|addr| console.peek(addr))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+fn err_exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(EXIT_ERROR);
+}
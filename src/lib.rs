@@ -0,0 +1,13 @@
+//! fami-rust: Family Computer(NES) emulator core.
+//!
+//! ウィンドウ等のフロントエンドに依存しない、エミュレーター本体のライブラリ。
+//! ROMの読み込み、コンソールの実行、コントローラー入力、フレームバッファの取得と、
+//! その表示方法の計算を提供する。
+
+pub mod nes;
+pub mod presentation;
+
+pub use nes::console::Console;
+pub use nes::joypad::Buttons;
+pub use nes::rom::{load_from_file, NesRom};
+pub use nes::ppu::palette::Palette;
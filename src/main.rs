@@ -1,115 +1,368 @@
-mod nes;
-
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use nes::rom::NesRom;
-use nes::rom;
-use nes::util;
-use nes::cpu::Cpu;
-use nes::ppu::Ppu;
-use nes::mem::MemCon;
+use fami_rust::nes::rom::NesRom;
+use fami_rust::nes::rom;
+use fami_rust::nes::region::Region;
+use fami_rust::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use fami_rust::nes::ppu::viewer::{self, NAMETABLES_WIDTH, PATTERN_TABLE_SIZE};
+use fami_rust::nes::ppu::palette::NtscParams;
+use fami_rust::nes::ppu::ntsc_filter::{self, NtscFilter, NtscPreset};
+use fami_rust::nes::debugger::events::{self, GRID_WIDTH};
+use fami_rust::presentation::{Overscan, Presentation};
+use fami_rust::{Buttons, Console, Palette};
 
 extern crate piston_window;
 extern crate image;
+extern crate glutin;
 
 use piston_window::*;
 
+/// ウィンドウの初期サイズ(表示範囲に対する倍率)
+const DEFAULT_WINDOW_SCALE: f64 = 3.0;
+/// ネームテーブルの表示に重ねる、スクロール位置の枠の色
+const OVERLAY_COLOR: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+/// ゲーム画面の代わりに表示するデバッグ表示。F1-F4とF6で切り替える。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DebugView {
+    /// 2枚のパターンテーブル(F5で着色のパレットを切り替える)
+    PatternTables,
+    /// 4画面分のネームテーブルとスクロール位置
+    Nametables,
+    /// 64個のスプライト
+    Oam,
+    /// パレットRAMの32色
+    Palette,
+    /// 直前のフレームでのレジスタへの書き込み(表示中だけ記録する)
+    Events,
+}
+
+impl DebugView {
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::F1 => Some(DebugView::PatternTables),
+            Key::F2 => Some(DebugView::Nametables),
+            Key::F3 => Some(DebugView::Oam),
+            Key::F4 => Some(DebugView::Palette),
+            Key::F6 => Some(DebugView::Events),
+            _ => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            DebugView::PatternTables => "Pattern tables",
+            DebugView::Nametables => "Nametables",
+            DebugView::Oam => "OAM",
+            DebugView::Palette => "Palette",
+            DebugView::Events => "Events",
+        }
+    }
+}
+
 fn main() {
-    std::env::set_var("RUST_LOG", "debug");
     env_logger::builder()
         .format_timestamp(None)
         .init();
-    
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        util::err_exit("Require rom image file.");
+
+    // Usage: fami-rust [--palette <file.pal>]
+    //                  [--hue <deg>] [--saturation <x>] [--contrast <x>] [--brightness <x>] [--gamma <x>]
+    //                  [--ntsc-filter <composite|svideo|rgb>]
+    //                  [--scale <n>] [--overscan <top,bottom,left,right>]
+    //                  [--no-integer-scaling] [--no-aspect-correction] [--fullscreen]
+    //                  [--region <ntsc|pal|dendy>]
+    //                  <rom>
+    // --hue 等を指定した場合は、NTSC信号からパレットを生成する。
+    // --ntsc-filter を指定した場合は、NTSCの映像信号を模したフィルターを通して表示する。
+    let mut rom_path = None;
+    let mut pal_path = None;
+    let mut ntsc_preset = None;
+    let mut presentation = Presentation::default();
+    let mut window_scale = DEFAULT_WINDOW_SCALE;
+    let mut fullscreen = false;
+    let mut region = None;
+    let mut ntsc = NtscParams::default();
+    let mut generate = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> f32 {
+            args.next().and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| err_exit(&format!("{} requires a number.", name)))
+        };
+        match arg.as_str() {
+            "--palette" => {
+                pal_path = Some(args.next().unwrap_or_else(|| err_exit("--palette requires a file.")));
+            },
+            "--hue" => { ntsc.hue = value(&arg); generate = true; },
+            "--saturation" => { ntsc.saturation = value(&arg); generate = true; },
+            "--contrast" => { ntsc.contrast = value(&arg); generate = true; },
+            "--brightness" => { ntsc.brightness = value(&arg); generate = true; },
+            "--gamma" => { ntsc.gamma = value(&arg); generate = true; },
+            "--ntsc-filter" => {
+                let name = args.next().unwrap_or_default();
+                ntsc_preset = Some(NtscPreset::from_name(&name).unwrap_or_else(||
+                    err_exit("--ntsc-filter requires one of: composite, svideo, rgb")));
+            },
+            "--scale" => window_scale = value(&arg).max(1.0) as f64,
+            "--overscan" => {
+                let spec = args.next().unwrap_or_default();
+                presentation.overscan = parse_overscan(&spec).unwrap_or_else(||
+                    err_exit("--overscan requires <top,bottom,left,right> (e.g. 8,8,0,0)"));
+            },
+            "--no-integer-scaling" => presentation.integer_scaling = false,
+            "--no-aspect-correction" => presentation.aspect_correction = false,
+            "--fullscreen" => fullscreen = true,
+            "--region" => {
+                region = Some(args.next().and_then(|name| Region::from_name(&name))
+                    .unwrap_or_else(|| err_exit("--region requires one of: ntsc, pal, dendy")));
+            },
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg),
+            _ => err_exit(&format!("Unknown argument: {}", arg)),
+        }
     }
+    let rom_path = rom_path.unwrap_or_else(|| err_exit("Require rom image file."));
+    let palette = match pal_path {
+        Some(path) => Palette::load_from_file(&path).unwrap_or_else(|e| err_exit(&e.to_string())),
+        None if generate => Palette::generate(&ntsc),
+        None => Palette::ntsc_2c02(),
+    };
+    let mut ntsc_filter = ntsc_preset.map(|preset| NtscFilter::new(preset, ntsc));
 
     // ROMをロード
-    let rom: Box<NesRom> = load_rom(&args[1]);
-
-    // PPUを初期化
-    // VRAMにROMのCHR-ROM領域をマッピングする。
-    let ppu = Rc::new(RefCell::new(Ppu::new(&rom)));
-    ppu.borrow_mut().power_on();
+    let rom: Box<NesRom> = load_rom(&rom_path);
 
-    // RAMを初期化
-    let ppu_databus = Rc::clone(&ppu);
-    let ram = MemCon::new(ppu_databus);
-
-    // CPUを初期化
-    let mut cpu = Cpu::new(&rom, Box::new(ram));
-    cpu.power_on();
-    
-    const WINDOW_X: u32 = 640;
-    const WINDOW_Y: u32 = 480;
+    // NES本体を組み立てて電源を入れる
+    // 地域はROMのヘッダから決める。オプションで上書きできる。
+    let region = region.unwrap_or_else(|| Region::from_rom(&rom));
+    let mut console = Console::with_region(&rom, region);
+    console.power_on();
 
     // Create window.
-    let mut window: PistonWindow = 
-        WindowSettings::new("Fami-Rust", (WINDOW_X, WINDOW_Y))
+    // 初期サイズは、表示範囲を window_scale 倍したもの。以降はウィンドウに合わせて拡大縮小する。
+    let (base_w, base_h) = presentation.base_size();
+    let window_size = ((base_w * window_scale).round(), (base_h * window_scale).round());
+    let mut window: PistonWindow =
+        WindowSettings::new("Fami-Rust", window_size)
         .exit_on_esc(true)
+        .resizable(true)
+        .fullscreen(fullscreen)
         .build()
         .unwrap_or_else(|e| { panic!("Failed to build PistonWindow: {}", e) });
+    // 地域のフレームレート(NTSC: 約60Hz, PAL/Dendy: 約50Hz)で描画する。
+    window.set_max_fps(region.frame_rate().round() as u64);
     let mut texture_context = TextureContext {
         factory: window.factory.clone(),
         encoder: window.factory.create_command_buffer().into()
     };
-    let mut screen = image::ImageBuffer::new(WINDOW_X, WINDOW_Y);
+    // NTSCフィルターは横方向の解像度が上がる。
+    let (screen_w, screen_h) = match ntsc_filter {
+        Some(_) => (ntsc_filter::OUTPUT_WIDTH, ntsc_filter::OUTPUT_HEIGHT),
+        None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+    };
+    let mut screen = image::RgbaImage::new(screen_w as u32, screen_h as u32);
     let mut texture: G2dTexture = Texture::from_image(
         &mut texture_context,
         &screen,
-        &TextureSettings::new()
+        &TextureSettings::new().filter(Filter::Nearest)
     ).unwrap();
 
-    let mut cpu_counter: u8 = 3;
+    let mut buttons = Buttons::empty();
+    let mut debug_view: Option<DebugView> = None;
+    let mut debug_texture: Option<G2dTexture> = None;
+    // パターンテーブルの着色に使うパレット番号(0-3 = BG, 4-7 = スプライト)
+    let mut pattern_palette = 0;
 
     // Start main loop.
     while let Some(e) = window.next() {
-        if let Some(_) = e.render_args() {
+        if e.render_args().is_some() {
 
-            // 3回に1回、CPUが動作する
-            if cpu_counter >= 3 {
-                // CPUの処理を進める
-                cpu.step();
-                cpu_counter = 0;
-            }
+            // 1フレーム分、NESの処理を進める
+            console.step_frame();
 
-            if ppu.borrow_mut().step() {
-                cpu.trigger_nmi();
+            // デバッグ表示中は、ゲーム画面の代わりにそれを描く。
+            if let Some(view) = debug_view {
+                let image = debug_view_image(&console, view, pattern_palette, &palette);
+                let texture = match debug_texture.as_mut() {
+                    Some(texture) if texture.get_size() == image.dimensions() => {
+                        texture.update(&mut texture_context, &image).unwrap();
+                        texture
+                    },
+                    _ => debug_texture.insert(Texture::from_image(
+                        &mut texture_context,
+                        &image,
+                        &TextureSettings::new().filter(Filter::Nearest)
+                    ).unwrap()),
+                };
+                window.draw_2d(&e, |c, g, device| {
+                    texture_context.encoder.flush(device);
+                    clear([0.0, 0.0, 0.0, 1.0], g);
+                    let [window_w, window_h] = c.get_view_size();
+                    Image::new()
+                        .rect(fit_rect(image.dimensions(), window_w, window_h))
+                        .draw(texture, &c.draw_state, c.transform, g);
+                });
+                continue;
             }
 
-            // 試しに点を打ってみる
-            // screen.put_pixel(100, 100, image::Rgba([255, 127, 127, 255]));
+            // パレットのインデックスをRGBに変換して転送
+            match ntsc_filter.as_mut() {
+                Some(filter) => console.frame_ntsc(filter, &mut screen),
+                None => console.frame_rgba(&palette, &mut screen),
+            }
 
             texture.update(&mut texture_context, &screen).unwrap();
             window.draw_2d(&e, |c, g, device| {
                 texture_context.encoder.flush(device);
-                image(&texture, c.transform, g);
-                // clear([0.5, 1.0, 0.5, 1.0], g);
+                clear([0.0, 0.0, 0.0, 1.0], g);
+                let [window_w, window_h] = c.get_view_size();
+                let layout = presentation.layout(screen_w as u32, window_w, window_h);
+                Image::new()
+                    .src_rect(layout.src)
+                    .rect(layout.dst)
+                    .draw(&texture, &c.draw_state, c.transform, g);
             });
-
-            cpu_counter += 1;
         }
 
         // 以下キーイベント処理。
-        // キーが押されていたら割り込みトリガをONにする。
+        // キーの押下状態をコントローラー(1P)に反映する。
         if let Some(Button::Keyboard(key)) = e.release_args() {
-
+            buttons.remove(key_to_buttons(key));
+            console.set_buttons(0, buttons);
         }
 
         if let Some(Button::Keyboard(key)) = e.press_args() {
+            buttons.insert(key_to_buttons(key));
+            console.set_buttons(0, buttons);
+
+            // F1-F4とF6でデバッグ表示の切り替え。同じキーでゲーム画面に戻る。
+            if let Some(view) = DebugView::from_key(key) {
+                debug_view = if debug_view == Some(view) { None } else { Some(view) };
+                console.set_event_logging(debug_view == Some(DebugView::Events));
+                update_title(&mut window, debug_view, pattern_palette);
+            }
+            // F5でパターンテーブルの着色に使うパレットを切り替え
+            if key == Key::F5 {
+                pattern_palette = (pattern_palette + 1) % 8;
+                update_title(&mut window, debug_view, pattern_palette);
+            }
+
+            // イベントの表示中は、F7で直前のフレームの書き込みの一覧を標準出力に出す。
+            if key == Key::F7 && debug_view == Some(DebugView::Events) {
+                print!("{}", events::format_list(console.event_log().last_frame()));
+            }
 
+            // F11でフルスクリーンの切り替え
+            if key == Key::F11 {
+                fullscreen = !fullscreen;
+                let mode = fullscreen.then(|| glutin::window::Fullscreen::Borderless(None));
+                window.window.ctx.window().set_fullscreen(mode);
+            }
         }
     }
 }
 
+/// デバッグ表示の画像を作る。
+fn debug_view_image(console: &Console, view: DebugView, pattern_palette: usize, palette: &Palette) -> image::RgbaImage {
+    let ppu = console.ppu().borrow();
+    let (width, height, rgba) = match view {
+        DebugView::PatternTables => {
+            // 2枚を横に並べる。
+            let tables = [0, 1].map(|table| ppu.pattern_table_view(table, pattern_palette).to_rgba(palette));
+            let row_bytes = PATTERN_TABLE_SIZE * 4;
+            let mut rgba = Vec::with_capacity(row_bytes * 2 * PATTERN_TABLE_SIZE);
+            for y in 0..PATTERN_TABLE_SIZE {
+                for table in &tables {
+                    rgba.extend_from_slice(&table[y * row_bytes..(y + 1) * row_bytes]);
+                }
+            }
+            (PATTERN_TABLE_SIZE * 2, PATTERN_TABLE_SIZE, rgba)
+        },
+        DebugView::Nametables => {
+            let image = ppu.nametable_view();
+            let mut rgba = image.to_rgba(palette);
+            let (x, y) = ppu.scroll_window();
+            viewer::draw_wrapping_rect(&mut rgba, NAMETABLES_WIDTH, (x, y, SCREEN_WIDTH, SCREEN_HEIGHT), OVERLAY_COLOR);
+            (image.width, image.height, rgba)
+        },
+        DebugView::Oam => {
+            let image = ppu.oam_view();
+            (image.width, image.height, image.to_rgba(palette))
+        },
+        DebugView::Palette => {
+            let image = ppu.palette_view();
+            (image.width, image.height, image.to_rgba(palette))
+        },
+        DebugView::Events => {
+            let scanlines = console.region().scanlines_per_frame() as usize;
+            let mut picture = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+            console.frame_rgba(palette, &mut picture);
+            let rgba = events::render_grid(console.event_log().last_frame(), scanlines, Some(&picture));
+            (GRID_WIDTH, scanlines, rgba)
+        },
+    };
+    image::RgbaImage::from_raw(width as u32, height as u32, rgba).unwrap()
+}
+
+/// 大きさ (w, h) の画像を、縦横比を保ってウィンドウの中央に描く矩形。可能なら整数倍に拡大する。
+fn fit_rect((w, h): (u32, u32), window_w: f64, window_h: f64) -> [f64; 4] {
+    let mut scale = (window_w / w as f64).min(window_h / h as f64);
+    if scale >= 1.0 {
+        scale = scale.floor();
+    }
+    let (dst_w, dst_h) = (w as f64 * scale, h as f64 * scale);
+    [((window_w - dst_w) / 2.0).floor(), ((window_h - dst_h) / 2.0).floor(), dst_w, dst_h]
+}
+
+/// ウィンドウのタイトルに、表示中のデバッグ表示を示す。
+fn update_title(window: &mut PistonWindow, view: Option<DebugView>, pattern_palette: usize) {
+    let title = match view {
+        Some(DebugView::PatternTables) => format!("Fami-Rust - Pattern tables (palette {})", pattern_palette),
+        Some(view) => format!("Fami-Rust - {}", view.title()),
+        None => "Fami-Rust".to_string(),
+    };
+    window.set_title(title);
+}
+
+/// "top,bottom,left,right" 形式のオーバースキャンの指定を解析する。
+fn parse_overscan(spec: &str) -> Option<Overscan> {
+    let values = spec.split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    match values[..] {
+        [top, bottom, left, right] => Some(Overscan { top, bottom, left, right }),
+        _ => None,
+    }
+}
+
+/// キーボードとコントローラーのボタンの対応
+fn key_to_buttons(key: Key) -> Buttons {
+    match key {
+        Key::Z => Buttons::A,
+        Key::X => Buttons::B,
+        Key::RShift => Buttons::SELECT,
+        Key::Return => Buttons::START,
+        Key::Up => Buttons::UP,
+        Key::Down => Buttons::DOWN,
+        Key::Left => Buttons::LEFT,
+        Key::Right => Buttons::RIGHT,
+        _ => Buttons::empty(),
+    }
+}
+
 fn load_rom(path: &str) -> Box<NesRom> {
-    match rom::load_from_file(&path) {
-        Ok(bin) => bin,
+    match rom::load_from_file(path) {
+        Ok(bin) => {
+            if let Err(err) = bin.check_mapper_supported() {
+                err_exit(&err.to_string());
+            }
+            bin
+        },
         Err(err) => {
             // TODO:エラー時のメッセージをユーザーフレンドリーに
-            util::err_exit(&err.to_string());
+            err_exit(&err.to_string());
         },
     }
 }
+
+fn err_exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
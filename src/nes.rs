@@ -1,6 +1,11 @@
 pub mod rom;
+pub mod region;
 pub mod util;
 pub mod cpu;
 pub mod ppu;
 pub mod mem;
-pub mod ppu_databus;
\ No newline at end of file
+pub mod ppu_databus;
+pub mod cpu_bus;
+pub mod joypad;
+pub mod console;
+pub mod debugger;
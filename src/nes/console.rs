@@ -0,0 +1,389 @@
+//! NES本体。CPU、PPU、メモリ、コントローラーを結線し、まとめて駆動する。
+
+use std::cell::{Ref, RefCell};
+use std::rc::Rc;
+
+use crate::nes::rom::NesRom;
+use crate::nes::region::Region;
+use crate::nes::cpu::{Cpu, FlowEvent};
+use crate::nes::cpu::trace::TraceLine;
+use crate::nes::cpu::disasm::{self, DisasmLine};
+use crate::nes::ppu::Ppu;
+use crate::nes::ppu::palette::Palette;
+use crate::nes::ppu::ntsc_filter::NtscFilter;
+use crate::nes::mem::MemCon;
+use crate::nes::joypad::{Buttons, Joypad};
+use crate::nes::debugger::watch::{MemWatch, WatchBus};
+use crate::nes::debugger::symbols::{BankedLabels, SymbolTable};
+use crate::nes::debugger::tracer::Tracer;
+use crate::nes::debugger::cdl::CodeDataLogger;
+use crate::nes::debugger::profiler::Profiler;
+use crate::nes::debugger::events::{EventBus, EventLog};
+
+/// 本体の型式。リセットボタンの結線が異なる。
+/// https://wiki.nesdev.org/w/index.php/PPU_power_up_state
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ConsoleModel {
+    /// NES(前面挿入型、NES-001)。リセットボタンでCPUとPPUの両方がリセットされる。
+    #[default]
+    FrontLoader,
+    /// NES(上面挿入型、NES-101)やファミコン。PPUのリセット端子が繋がっておらず、
+    /// リセットボタンではCPUだけがリセットされる。
+    TopLoader,
+}
+
+impl ConsoleModel {
+    /// "front-loader", "top-loader" から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "front-loader" | "front" => Some(ConsoleModel::FrontLoader),
+            "top-loader" | "top" | "famicom" => Some(ConsoleModel::TopLoader),
+            _ => None,
+        }
+    }
+}
+
+pub struct Console {
+    cpu: Cpu,
+    ppu: Rc<RefCell<Ppu>>,
+    /// $4016(1P)と$4017(2P)に接続されたコントローラー
+    joypads: Rc<RefCell<[Joypad; 2]>>,
+    /// CPUとPPUのクロック比 (分子, 分母)。地域によって異なる。
+    clock_ratio: (u8, u8),
+    /// CPUを動かすタイミングを決めるカウンター。
+    /// PPUの1クロックごとに分母ずつ増え、分子に達するとCPUが1クロック動く。
+    cpu_counter: u8,
+    /// 本体の型式
+    model: ConsoleModel,
+    /// デバッガーのウォッチポイント。CPUのバスへのアクセスを監視する。
+    mem_watch: Rc<RefCell<MemWatch>>,
+    /// トレースや逆アセンブル結果の注釈に使うラベル
+    symbols: SymbolTable,
+    /// 命令単位のトレースログ
+    tracer: Option<Tracer>,
+    /// コード/データロガー
+    cdl: Option<CodeDataLogger>,
+    /// サブルーチン単位のサイクルプロファイラー
+    profiler: Option<Profiler>,
+    /// レジスタへの書き込みの記録。CPUのバスへの書き込みを監視する。
+    events: Rc<RefCell<EventLog>>,
+}
+
+impl Console {
+    /// ROMを挿入した状態のNESを組み立てる。電源はまだ入っていない。
+    /// 地域はROMのヘッダから決める。
+    pub fn new(rom: &NesRom) -> Self {
+        Self::with_region(rom, Region::from_rom(rom))
+    }
+
+    /// 地域を指定してNESを組み立てる。
+    pub fn with_region(rom: &NesRom, region: Region) -> Self {
+        // PPUを初期化
+        // VRAMにROMのCHR-ROM領域をマッピングする。
+        let ppu = Rc::new(RefCell::new(Ppu::new(rom, region)));
+
+        // RAMを初期化し、PRG-ROM を展開する。
+        let joypads = Rc::new(RefCell::new(<[Joypad; 2]>::default()));
+        let ppu_databus = Rc::clone(&ppu);
+        let mut ram = MemCon::new(ppu_databus, Rc::clone(&joypads));
+        ram.load_prg_rom(rom);
+
+        // CPUを初期化
+        // デバッガーのウォッチポイントとイベントビューアーのため、バスへのアクセスを監視できるようにしておく。
+        let mem_watch = Rc::new(RefCell::new(MemWatch::default()));
+        let events = Rc::new(RefCell::new(EventLog::default()));
+        let event_bus = EventBus::new(Box::new(ram), Rc::clone(&events), Rc::clone(&ppu));
+        let cpu = Cpu::new(Box::new(WatchBus::new(Box::new(event_bus), Rc::clone(&mem_watch))));
+
+        Console {
+            cpu,
+            ppu,
+            joypads,
+            clock_ratio: region.ppu_clocks_per_cpu_clock(),
+            cpu_counter: region.ppu_clocks_per_cpu_clock().0,
+            model: Default::default(),
+            mem_watch,
+            symbols: SymbolTable::new(),
+            tracer: None,
+            cdl: None,
+            profiler: None,
+            events,
+        }
+    }
+
+    /// 電源投入
+    pub fn power_on(&mut self) {
+        self.ppu.borrow_mut().power_on();
+        self.cpu.power_on();
+        self.cpu_counter = self.clock_ratio.0;
+    }
+
+    /// PPUを1クロック進める。CPUはPPUの3クロック(PALでは3.2クロック)につき1回動作する。
+    /// フレームの描画が完了した場合はtrueを返す。
+    pub fn step(&mut self) -> bool {
+        let (num, den) = self.clock_ratio;
+        if self.cpu_counter >= num {
+            // 命令をフェッチする直前に、トレースとコード/データを記録する。
+            if self.cpu.is_instruction_boundary() {
+                if self.tracer.is_some() {
+                    self.record_trace();
+                }
+                if self.cdl.is_some() {
+                    self.log_cdl();
+                }
+                let mut events = self.events.borrow_mut();
+                if events.is_enabled() {
+                    events.begin_instruction(self.cpu.regs().pc);
+                }
+            }
+            // CPUの処理を進める
+            self.cpu.step();
+            if let Some(event) = self.cpu.take_flow_event() {
+                if self.profiler.is_some() {
+                    self.record_flow(event);
+                }
+            }
+            self.cpu_counter -= num;
+        }
+        self.cpu_counter += den;
+
+        let mut ppu = self.ppu.borrow_mut();
+        let frame = ppu.frame();
+        if ppu.step() {
+            self.cpu.trigger_nmi();
+        }
+        let frame_done = ppu.frame() != frame;
+        if frame_done {
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log_frame(&ppu);
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.end_frame(self.cpu.clock_counter());
+            }
+            self.events.borrow_mut().end_frame();
+        }
+        frame_done
+    }
+
+    /// 1フレーム分の描画が完了するまで実行する。
+    pub fn step_frame(&mut self) {
+        while !self.step() {}
+    }
+
+    /// CPUが現在の命令(または割り込み処理)を終え、次の命令の直前に来るまで実行する。
+    /// フレームの描画が完了した場合はtrueを返す。
+    pub fn step_instruction(&mut self) -> bool {
+        let mut frame_done = false;
+        loop {
+            frame_done |= self.step();
+            if self.cpu_counter >= self.clock_ratio.0 && self.cpu.is_instruction_boundary() {
+                return frame_done
+            }
+        }
+    }
+
+    /// 地域
+    pub fn region(&self) -> Region {
+        self.ppu.borrow().region()
+    }
+
+    /// 現在のPPUの位置 (スキャンライン, ドット)。
+    pub fn ppu_position(&self) -> (u16, u16) {
+        let ppu = self.ppu.borrow();
+        (ppu.scanline(), ppu.dot())
+    }
+
+    /// 次に実行する命令のトレースを作成する。ラベルがあれば、オペランドをラベルで表示する。
+    pub fn trace_line(&self) -> TraceLine {
+        let (scanline, dot) = self.ppu_position();
+        self.cpu.trace_line_with(scanline, dot, &self.labels())
+    }
+
+    /// リセットボタンを押す。
+    /// 前面挿入型のNESではPPUもリセットされ、しばらくPPUのレジスタへの書き込みが無視される。
+    pub fn reset(&mut self) {
+        self.cpu.trigger_reset();
+        if self.model == ConsoleModel::FrontLoader {
+            self.ppu.borrow_mut().reset();
+        }
+    }
+
+    /// 本体の型式
+    pub fn model(&self) -> ConsoleModel {
+        self.model
+    }
+
+    /// 本体の型式を設定する。
+    pub fn set_model(&mut self, model: ConsoleModel) {
+        self.model = model;
+    }
+
+    /// CPUのメモリ空間を副作用なしで読む。
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.mem().peek(addr)
+    }
+
+    /// CPUのメモリ空間を副作用なしで書き換える。ROM領域も書き換えられる。
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.cpu.mem_mut().poke(addr, data);
+    }
+
+    /// デバッガーのウォッチポイント
+    pub fn mem_watch(&self) -> &Rc<RefCell<MemWatch>> {
+        &self.mem_watch
+    }
+
+    /// CPUのメモリ空間の start から end (end を含む) までを逆アセンブルする。
+    /// ラベルがあれば、オペランドをラベルで表示する。
+    pub fn disassemble(&self, start: u16, end: u16) -> Vec<DisasmLine> {
+        disasm::disasm_range_with(|addr| self.peek(addr), start, end, &self.labels())
+    }
+
+    /// addr に現在マップされているPRG-ROMの、先頭からのオフセット。
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.cpu.mem().prg_rom_offset(addr)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// トレースや逆アセンブル結果の注釈に使うラベルを設定する。
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    /// 現在のバンクの割り当てで、addr に付いているラベル。
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.symbols.label_at(addr, self.prg_rom_offset(addr))
+    }
+
+    /// アドレスの式(`$C000`, `main`, `main+3` など)を、現在のバンクの割り当てで解決する。
+    pub fn resolve_addr(&self, text: &str) -> Option<u16> {
+        self.symbols.resolve_expr(text, |addr| self.prg_rom_offset(addr))
+    }
+
+    /// 命令単位のトレースログを設定する。None なら記録しない。
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// トレースログを取り外す。
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    fn record_trace(&mut self) {
+        // Tracer は Console 全体を参照するため、いったん取り外してから記録する。
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(self);
+            self.tracer = Some(tracer);
+        }
+    }
+
+    /// コード/データロガーを設定する。None なら記録しない。
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLogger>) {
+        self.cdl = cdl;
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    /// コード/データロガーを取り外す。
+    pub fn take_cdl(&mut self) -> Option<CodeDataLogger> {
+        self.cdl.take()
+    }
+
+    fn log_cdl(&mut self) {
+        if let Some(mut cdl) = self.cdl.take() {
+            cdl.log_instruction(self);
+            self.cdl = Some(cdl);
+        }
+    }
+
+    /// サイクルプロファイラーを設定する。None なら集計しない。
+    /// リセットから集計するには、電源投入の前に設定すること。
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// サイクルプロファイラーを取り外す。
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    fn record_flow(&mut self, event: FlowEvent) {
+        if let Some(mut profiler) = self.profiler.take() {
+            profiler.record(event, self);
+            self.profiler = Some(profiler);
+        }
+    }
+
+    /// レジスタへの書き込みの記録
+    pub fn event_log(&self) -> Ref<'_, EventLog> {
+        self.events.borrow()
+    }
+
+    /// レジスタへの書き込みを記録するか設定する。既定では記録しない。
+    pub fn set_event_logging(&mut self, enabled: bool) {
+        self.events.borrow_mut().set_enabled(enabled);
+    }
+
+    fn labels(&self) -> BankedLabels<'_, impl Fn(u16) -> Option<usize> + '_> {
+        BankedLabels::new(&self.symbols, |addr| self.prg_rom_offset(addr))
+    }
+
+    /// 電源投入後に描画を完了したフレーム数。
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.borrow().frame()
+    }
+
+    /// コントローラーの状態を更新する。portは 0 = 1P、1 = 2P。それ以外のportは無視する。
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        if let Some(joypad) = self.joypads.borrow_mut().get_mut(port) {
+            joypad.set_buttons(buttons);
+        }
+    }
+
+    /// 最新のフレームバッファを取得する。
+    /// 1ピクセル1バイト、256x240、各バイトはパレットのインデックス(6bit)。
+    pub fn frame_buffer(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.ppu.borrow(), |ppu| ppu.frame_buffer())
+    }
+
+    /// 最新のフレームバッファを、パレットでRGBA(1ピクセル4バイト、256x240)に変換する。
+    /// PPUMASKのグレースケールと色強調は、現在の値が画面全体に適用される。
+    /// PALとDendyでは、赤と緑の色強調ビットを入れ替えて適用する。
+    pub fn frame_rgba(&self, palette: &Palette, out: &mut [u8]) {
+        let ppu = self.ppu.borrow();
+        palette.to_rgba(ppu.frame_buffer(), ppu.region().ntsc_mask(ppu.mask()), out);
+    }
+
+    /// 最新のフレームバッファを、NTSCフィルターを通してRGBAに変換する。
+    /// 出力のサイズは ntsc_filter::OUTPUT_WIDTH x OUTPUT_HEIGHT。
+    pub fn frame_ntsc(&self, filter: &mut NtscFilter, out: &mut [u8]) {
+        let ppu = self.ppu.borrow();
+        filter.filter(ppu.frame_buffer(), ppu.region().ntsc_mask(ppu.mask()), ppu.frame(), out);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn ppu(&self) -> &Rc<RefCell<Ppu>> {
+        &self.ppu
+    }
+}
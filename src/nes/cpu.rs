@@ -8,18 +8,23 @@ mod exec_core_g2;
 mod exec_core_g3;
 mod is_template;
 mod is_core;
-mod instruction;
+pub(crate) mod instruction;
+pub mod trace;
+pub mod disasm;
+#[cfg(test)]
+mod tests;
 
 use bitflags::bitflags;
 
-use crate::nes::mem;
-use crate::nes::rom;
+use crate::nes::cpu_bus::CpuBus;
 use crate::nes::cpu::cpu_state::*;
 
 /// NTSC版のクロック周波数(Hz)
-const CLOCK_FREQ_NTSC: u32 = 1789773;
+pub const CLOCK_FREQ_NTSC: u32 = 1789773;
 /// PAL版のクロック周波数(Hz)
-const CLOCK_FREQ_PAL: u32 = 1662607;
+pub const CLOCK_FREQ_PAL: u32 = 1662607;
+/// Dendy(互換機)のクロック周波数(Hz)
+pub const CLOCK_FREQ_DENDY: u32 = 1773448;
 
 // スタックポインタの上位アドレス
 const ADDR_STACK_UPPER: u16 = 0x0100;
@@ -52,7 +57,7 @@ bitflags! {
 
 /// 6502 (RICHO 2A03)
 pub struct Cpu {
-    mem: Box<mem::MemCon>,
+    mem: Box<dyn CpuBus>,
     // clock_freq: u32,
     // clock_cycle: f32,
     /// 起動後、リセットまたは電源断まで増加し続けるカウンター
@@ -75,6 +80,8 @@ pub struct Cpu {
     int_requested: Interrupt,
     /// 1つの状態が終わるまでの間、必要な情報を一時的に保持する。
     state: TmpState,
+    /// 直前のクロックで完了した、サブルーチンや割り込みの出入り。プロファイラーが参照する。
+    flow_event: Option<FlowEvent>,
 }
 
 #[derive(Default)]
@@ -102,6 +109,12 @@ impl Registers {
         (self.p & Flags::INT_DISABLE.bits) != 0
     }
 
+    /// スタックから取り出した値をステータスレジスタに設定する。
+    /// Brkフラグは実在しないので 0 に、予約ビットは常に 1 にしておく。
+    pub fn set_p_from_stack(&mut self, val: u8) {
+        self.p = (val & !Flags::BREAK.bits) | Flags::RESERVED.bits;
+    }
+
     pub fn flags_on(&mut self, flags: Flags) {
         self.p |= flags.bits;
     }
@@ -245,6 +258,20 @@ pub enum IntType {
     Brk,
 }
 
+/// サブルーチンや割り込み処理への出入り。
+/// 命令(または割り込みシーケンス)の最後のクロックで発生し、その時点でPCとSは遷移後の値になっている。
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FlowEvent {
+    /// JSRでサブルーチンに入った。
+    Call,
+    /// RTSでサブルーチンから戻った。
+    Return,
+    /// 割り込みハンドラ(BRKを含む)に入った。
+    Interrupt(IntType),
+    /// RTIで割り込みハンドラから戻った。
+    ReturnFromInterrupt,
+}
+
 pub struct Interrupt {
     kind: IntType,
     /// 現在の命令の完了時ではなく、次の命令の完了時まで発生が遅延されている割り込みの場合はtrue。
@@ -263,10 +290,10 @@ impl Default for Interrupt {
 }
 
 impl Cpu {
-    pub fn new(rom: &Box<rom::NesRom>, ram: Box<mem::MemCon>) -> Self {
-
-        let mut my = Cpu {
-            mem: ram,
+    /// バスに接続された状態のCPUを作る。電源はまだ入っていない。
+    pub fn new(bus: Box<dyn CpuBus>) -> Self {
+        Cpu {
+            mem: bus,
             // clock_freq: CLOCK_FREQ_NTSC, // Use NTSC as default.
             // clock_cycle: 1f32 / (CLOCK_FREQ_NTSC as f32),
             clock_counter: 0,
@@ -278,25 +305,8 @@ impl Cpu {
             fn_step: Cpu::int_step,
             int_requested: Default::default(),
             state: TmpState::default(),
-        };
-
-        {
-            // PRG-ROM を RAM に展開
-            let prg_rom = rom.prg_rom();
-            let len = rom::PRG_ROM_UNIT_SIZE;
-            if prg_rom.len() >= len {
-                my.mem.raw_write(0x8000, &prg_rom[0..len]);
-            }
-            if prg_rom.len() >= (len * 2) {
-                my.mem.raw_write(0xC000, &prg_rom[len..len*2]);
-            } else {
-                // PRG-ROMが2枚ない場合は、1枚目をコピーする。
-                // TODO: MMCによってはPRG-ROMが2つ以上載っている可能性あり。
-                my.mem.raw_write(0xC000, &prg_rom[0..len]);
-            }
+            flow_event: None,
         }
-
-        return my
     }
     
     /// 電源投入(リセット割り込み発生)
@@ -308,19 +318,16 @@ impl Cpu {
         self.regs.a = 0;
         self.regs.x = 0;
         self.regs.y = 0;
-        self.regs.s = 0xFD;
-        //self.regs.p = 0x34;
-        self.regs.flags_on(Flags::INT_DISABLE | Flags::BREAK | Flags::RESERVED);
-
-        // 物理RAMの初期化。
-        // 機種によっては起動時のメモリ内容が一定でない場合もあるが、
-        // ここでは0クリアとしておく。
-        self.mem.raw_fill(0x0000..=0x07FF, 0);
+        // 電源投入直後のSは $00 で、続くリセットシーケンスで3減算されて $FD になる。
+        self.regs.s = 0x00;
+        // 資料によっては $34 とされるが、Brkフラグはスタック上にのみ存在するので、
+        // レジスタとしては $24 になる。
+        self.regs.flags_on(Flags::INT_DISABLE | Flags::RESERVED);
+
+        // 物理RAMの初期化はバス(mem::MemCon)の生成時に行っている。
         
         // APU状態のリセット…だが、既にメモリが0クリアされているので不要。
         /*
-        self.mem.raw_fill(0x4000..=0x400F, 0);
-        self.mem.raw_fill(0x4010..=0x4013, 0);
         self.mem.write(0x4015, 0);
         self.mem.write(0x4017, 0);
         */
@@ -328,14 +335,50 @@ impl Cpu {
         // 割り込み状態の初期化
         self.clear_all_int_trigger();
 
-        /*
-        // 電源投入時はReset割り込みから実行開始 
+        // 電源投入時はReset割り込みから実行開始
         self.int_requested.kind = IntType::Reset;
         self.int_requested.is_force_delayed = false;
         self.switch_state_int();
-        */
+    }
+
+    pub fn regs(&self) -> &Registers {
+        &self.regs
+    }
+
+    pub fn regs_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
 
-        self.regs.pc = 0xC000;
+    /// 電源投入後に経過したクロックサイクル数。
+    pub fn clock_counter(&self) -> u64 {
+        self.clock_counter
+    }
+
+    /// 直前のクロックで完了したサブルーチンや割り込みの出入りを取り出す。
+    pub fn take_flow_event(&mut self) -> Option<FlowEvent> {
+        self.flow_event.take()
+    }
+
+    /// 命令や割り込み処理の途中ではなく、次のクロックで新しい命令をフェッチする状態ならtrue。
+    pub fn is_instruction_boundary(&self) -> bool {
+        self.state.counter == 0 && self.state.int == IntType::None
+    }
+
+    /// CPUに接続されているバス。
+    pub fn mem(&self) -> &dyn CpuBus {
+        self.mem.as_ref()
+    }
+
+    /// CPUに接続されているバス。
+    pub fn mem_mut(&mut self) -> &mut dyn CpuBus {
+        self.mem.as_mut()
+    }
+
+    /// PCを書き換え、次のクロックからその位置の命令をフェッチする。
+    /// 実行途中の命令や割り込み処理は破棄される。
+    pub fn jump_to(&mut self, pc: u16) {
+        self.regs.pc = pc;
+        self.int_requested = Default::default();
         self.switch_state_fetch();
     }
 
@@ -344,6 +387,7 @@ impl Cpu {
         self.clock_counter += 1;
         self.state.counter += 1;
         (self.fn_step)(self);
+        self.mem.tick();
 
         // 最後の1クロック目の直前にのみ、例外のチェックを行う。
         if self.int_polling_enabled &&
@@ -352,9 +396,6 @@ impl Cpu {
         {
             self.check_int();
         }
-
-        #[cfg(debug_assertions)]
-        self.print_cpu_state();
     }
 
     /// NMIの発生をCPUに通知。実機での「ピンをhighからlowへ」に相当。
@@ -484,8 +525,7 @@ impl Cpu {
     /// 現在のスタックポインタの指すアドレスから値を取り出す。スタックポインタは操作しない。
     pub fn peek_stack(&mut self) -> u8 {
         let addr = ADDR_STACK_UPPER | (self.regs.s as u16);
-        let data = self.mem.read(addr);
-        data
+        self.mem.read(addr)
     }
 
     /// 割り込みピンの状態を調べ、どの割り込みを発生させるかを決定する。
@@ -514,33 +554,16 @@ impl Cpu {
 
     /// スタックが天井を突き破らないかチェック。
     fn check_stack_overflow(&self) {
-        if self.regs.s <= 0 {
+        if self.regs.s == 0 {
             log::debug!("!!! stack overflow detected.");
         }
     }
 
     /// スタックの底が抜けないかチェック。
     fn check_stack_underflow(&self) {
-        if self.regs.s >= u8::MAX {
+        if self.regs.s == u8::MAX {
             log::debug!("!!! stack underflow detected.");
         }
     }
-
-    fn print_cpu_state(&self) {
-        log::debug!(">>>> CPU STATE: BEGIN");
-        log::debug!("PC = {:#06X}({})", self.regs.pc, self.regs.pc);
-        log::debug!("A = {}, X = {}, Y = {}", self.regs.a, self.regs.x, self.regs.y);
-        log::debug!("S = {:#04X}({}), P = {:#010b}({})", self.regs.s, self.regs.s, self.regs.p, self.regs.p);
-        log::debug!("<<<< END");
-
-        /*
-        let mem02 = self.mem.ram[0x02 as usize];
-        let mem03 = self.mem.ram[0x03 as usize];
-        log::debug!("#### 0x02={:#02X}, 0x03={:#02X}", mem02, mem03);
-        if mem02 != 0 || mem03 != 0  {
-            std::process::exit(0);
-        }
-        */
-    }
 }
 
@@ -1,9 +1,9 @@
 //! CPUの状態遷移
 
-use super::{Cpu, Flags, IntType};
+use super::{Cpu, Flags, FlowEvent, IntType};
 use super::decoder;
 use super::executer::Executer;
-use crate::util::*;
+use crate::nes::util::*;
 
 // 割り込みハンドラのアドレス:
 const ADDR_INT_NMI: u16        = 0xFFFA;
@@ -56,58 +56,56 @@ impl Cpu {
     /// OPコードをフェッチする。
     /// Brkだった場合は割り込み状態へ遷移、それ以外は実行状態へ遷移。
     pub fn fetch_step(&mut self) {
-        log::debug!("[Fetch] counter={}", self.state.counter);
-
         let opcode = self.fetch();
-        log::debug!("[Fetch] opcode={:#04X}", opcode);
         if opcode == OPCODE_BRK {
             // BRKはソフトウェア割り込みなので、物理的なピンは操作しないし、
             // ピンの状態を上げ下げする必要もない。ここで内部的なフラグを直接立てる。
             self.state.int = IntType::Brk;
             self.fn_step = Cpu::int_step;
             self.int_polling_enabled = false;
-            log::debug!("[Fetch] completed. op=BRK");
         } else {
             self.state.executer = decoder::decode(opcode);
             self.fn_step = Cpu::exec_step;
             self.int_polling_enabled = true;
-            log::debug!("[Fetch] completed. op={}", self.state.executer.inst);
         }
     }
 
     /// 命令実行のステップ処理
     pub fn exec_step(&mut self) {
-        let exec = &self.state.executer;
-        log::debug!("[Execute] op={}, counter={}",
-            exec.inst,
-            self.state.counter
-        );
-        (exec.inst.fn_exec)(self);
+        (self.state.executer.inst.fn_exec)(self);
     }
 
     /// 割り込みシーケンス(＝割り込みハンドラへジャンプする直前まで)のステップ処理。
     pub fn int_step(&mut self) {
-        log::debug!("[Interrupt] counter={}", self.state.counter);
         match self.state.counter {
             1 => {
                 // *** Brkの場合はすでに1クロック目を通過済みなので、ここには入らない ***
+                // 実行されなかった次の命令をダミーリードする。
+                self.mem.read(self.regs.pc);
             },
             2 => {
                 if self.state.int == IntType::Brk {
-                    // Brkの場合、ここに来た時点でPCはBrkの1バイト先を指しているので、更に+1する。
-                    self.regs.pc = self.regs.pc.wrapping_add(1);
+                    // Brkの場合、ここに来た時点でPCはBrkの1バイト先を指しているので、
+                    // その1バイト(パディング)を読み飛ばして更に+1する。
+                    self.fetch();
+                } else {
+                    self.mem.read(self.regs.pc);
                 }
             },
-            // Resetの場合はスタックを操作しない
-            3 => if self.state.int != IntType::Reset {
+            // Resetの場合は、スタックに書き込まずに読むだけ(スタックポインタは減算される)
+            3 => if self.state.int == IntType::Reset {
+                self.skip_stack();
+            } else {
                 self.push_stack((self.regs.pc >> 8 & 0x00FF) as u8);
             },
-            // Resetの場合はスタックを操作しない
-            4 => if self.state.int != IntType::Reset {
+            4 => if self.state.int == IntType::Reset {
+                self.skip_stack();
+            } else {
                 self.push_stack((self.regs.pc & 0x00FF) as u8);
             },
-            // Resetの場合はスタックを操作しない
-            5 => if self.state.int != IntType::Reset {
+            5 => if self.state.int == IntType::Reset {
+                self.skip_stack();
+            } else {
                 // ステータスレジスタをスタックに保存。
                 // その前にBrakeフラグを設定する。Brakeフラグはスタック上にのみ存在する。
                 let brk_flag = ((self.state.int == IntType::Brk) as u8) << 4;
@@ -116,27 +114,20 @@ impl Cpu {
             },
             6 => {
                 // ジャンプする先の割り込みハンドラのアドレス(下位8bit)を読み込む。
-                // が、エミュレーター実装としては何もしない(7クロック目でまとめて対応する)。
+                self.state.op_1 = self.mem.read(self.vector_addr());
 
                 // ここでIRQ/BRK無視フラグを立てる
                 self.regs.flags_on(Flags::INT_DISABLE);
             },
             7 => {
                 // ジャンプする先の割り込みハンドラのアドレス(上位8bit)を読み込む。
-                // クロック6で何もしていないので、ここで下位と上位アドレスをまとめて読み込む。
-                let vec_addr = match self.state.int {
-                    IntType::Reset => ADDR_INT_RESET,
-                    IntType::Nmi => ADDR_INT_NMI,
-                    IntType::Irq | IntType::Brk => ADDR_INT_IRQ,
-                    IntType::None => unreachable!(),
-                };
-                let low = self.mem.read(vec_addr);
-                let high = self.mem.read(vec_addr+1);
+                let low = self.state.op_1;
+                let high = self.mem.read(self.vector_addr() + 1);
                 self.regs.pc = make_addr(high, low);
+                self.flow_event = Some(FlowEvent::Interrupt(self.state.int));
                 if self.state.int == IntType::Reset {
                     // リセット時の初期化処理の開始
-                    // スタックポインタを3減算(ただしスタックの内容自体は操作しない)
-                    self.regs.s = self.regs.s.wrapping_sub(3);
+                    // (スタックポインタの3減算は、クロック3-5で済んでいる)
                     // IRQ/BRK無視フラグを立てる
                     self.regs.flags_on(Flags::INT_DISABLE);
                     // TODO: APUの状態リセットが必要
@@ -148,4 +139,20 @@ impl Cpu {
             _ => unreachable!(),
         };
     }
+
+    /// 処理中の割り込みのハンドラのアドレスが格納されている位置
+    fn vector_addr(&self) -> u16 {
+        match self.state.int {
+            IntType::Reset => ADDR_INT_RESET,
+            IntType::Nmi => ADDR_INT_NMI,
+            IntType::Irq | IntType::Brk => ADDR_INT_IRQ,
+            IntType::None => unreachable!(),
+        }
+    }
+
+    /// Resetのスタック操作。書き込みの代わりに読み込みが行われ、スタックポインタだけが減算される。
+    fn skip_stack(&mut self) {
+        self.peek_stack();
+        self.regs.s = self.regs.s.wrapping_sub(1);
+    }
 }
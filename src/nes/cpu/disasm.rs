@@ -0,0 +1,179 @@
+//! 逆アセンブラ。
+//! INSTRUCTION_SET の命令名とアドレッシングモードから、一般的なアセンブラの書式に変換する。
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5
+//! C003  B1 12     LDA ($12),Y
+//! ```
+//!
+//! Labels を渡すと、オペランドのアドレスをラベルに置き換える(例: "JMP main")。
+
+use std::fmt::{self, Display};
+
+use super::instruction::*;
+use crate::nes::util::make_addr;
+
+/// 逆アセンブルした1命令。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    /// オペコードとオペランド
+    pub bytes: Vec<u8>,
+    /// 非公式命令ならtrue
+    pub undocumented: bool,
+    /// 命令(例: "LDA ($12),Y")。命令として解釈できないバイトは ".byte $02" となる。
+    pub text: String,
+}
+
+impl Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.bytes.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mark = if self.undocumented { '*' } else { ' ' };
+        write!(f, "{:04X}  {:<8} {}{}", self.addr, bytes, mark, self.text)
+    }
+}
+
+/// アドレスに付けられたラベルを引く。逆アセンブル結果の注釈に使う。
+pub trait Labels {
+    fn label(&self, addr: u16) -> Option<&str>;
+}
+
+/// ラベルなし
+pub struct NoLabels;
+
+impl Labels for NoLabels {
+    fn label(&self, _addr: u16) -> Option<&str> {
+        None
+    }
+}
+
+/// オペコードの命令の長さ(オペコードを含むバイト数)。
+/// BRKと未定義のオペコードは1バイトとして扱う。
+pub fn instruction_len(opcode: u8) -> u16 {
+    match INSTRUCTION_SET[opcode as usize] {
+        Some(inst) => 1 + inst.addr_mode.operand_len(),
+        None => 1,
+    }
+}
+
+/// addr にある1命令を逆アセンブルする。メモリは peek で読む。
+pub fn disasm_at<F: FnMut(u16) -> u8>(peek: F, addr: u16) -> DisasmLine {
+    disasm_at_with(peek, addr, &NoLabels)
+}
+
+/// disasm_at と同じだが、オペランドのアドレスを labels のラベルに置き換える。
+pub fn disasm_at_with<F: FnMut(u16) -> u8>(mut peek: F, addr: u16, labels: &dyn Labels) -> DisasmLine {
+    let opcode = peek(addr);
+    let bytes: Vec<u8> = (0..instruction_len(opcode))
+        .map(|i| peek(addr.wrapping_add(i)))
+        .collect();
+    decode(addr, bytes, labels)
+}
+
+/// メモリの start から end (end を含む) までの範囲を逆アセンブルする。
+/// 最後の命令のオペランドは end を超えて読むことがある。
+pub fn disasm_range<F: FnMut(u16) -> u8>(peek: F, start: u16, end: u16) -> Vec<DisasmLine> {
+    disasm_range_with(peek, start, end, &NoLabels)
+}
+
+/// disasm_range と同じだが、オペランドのアドレスを labels のラベルに置き換える。
+pub fn disasm_range_with<F: FnMut(u16) -> u8>(mut peek: F, start: u16, end: u16, labels: &dyn Labels) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let line = disasm_at_with(&mut peek, addr as u16, labels);
+        addr += line.bytes.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/// バイト列を、先頭が origin に配置されているものとして逆アセンブルする。
+/// 末尾で命令が途切れた場合、残りのバイトはデータとして扱う。
+pub fn disasm_bytes(bin: &[u8], origin: u16) -> Vec<DisasmLine> {
+    disasm_bytes_with(bin, origin, &NoLabels)
+}
+
+/// disasm_bytes と同じだが、オペランドのアドレスを labels のラベルに置き換える。
+pub fn disasm_bytes_with(bin: &[u8], origin: u16, labels: &dyn Labels) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    while pos < bin.len() {
+        let addr = origin.wrapping_add(pos as u16);
+        let len = instruction_len(bin[pos]) as usize;
+        let line = match bin.get(pos..pos + len) {
+            Some(bytes) => decode(addr, bytes.to_vec(), labels),
+            None => data_byte(addr, bin[pos]),
+        };
+        pos += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// オペコードとオペランドのバイト列から1命令を組み立てる。
+fn decode(addr: u16, bytes: Vec<u8>, labels: &dyn Labels) -> DisasmLine {
+    let opcode = bytes[0];
+    let text = match INSTRUCTION_SET[opcode as usize] {
+        Some(inst) => {
+            let operand = format_operand(inst.addr_mode, addr, &bytes, labels);
+            format!("{} {}", inst.core_name, operand).trim_end().to_string()
+        },
+        // BRKは割り込みとして処理するのでテーブルに存在しない。
+        None if opcode == 0x00 => "BRK".to_string(),
+        None => return data_byte(addr, opcode),
+    };
+    DisasmLine { addr, bytes, undocumented: is_undocumented(opcode), text }
+}
+
+fn data_byte(addr: u16, data: u8) -> DisasmLine {
+    DisasmLine { addr, bytes: vec![data], undocumented: false, text: format!(".byte ${:02X}", data) }
+}
+
+/// オペランドを書式化する。addr は命令自身のアドレス(相対分岐の飛び先の計算に使う)。
+/// ラベルのあるアドレスはラベルに置き換える。
+pub(super) fn format_operand(addr_mode: AddrMode, addr: u16, bytes: &[u8], labels: &dyn Labels) -> String {
+    let op_1 = bytes.get(1).copied().unwrap_or(0);
+    let op_2 = bytes.get(2).copied().unwrap_or(0);
+    let zp = zero_page_operand(op_1, labels);
+    let abs = absolute_operand(make_addr(op_2, op_1), labels);
+    match addr_mode {
+        AddrMode::Implied => String::new(),
+        AddrMode::Accumulator => "A".to_string(),
+        AddrMode::Immediate => format!("#${:02X}", op_1),
+        AddrMode::ZeroPage => zp,
+        AddrMode::IndexedZeroPageX => format!("{},X", zp),
+        AddrMode::IndexedZeroPageY => format!("{},Y", zp),
+        AddrMode::Absolute => abs,
+        AddrMode::IndexedAbsoluteX => format!("{},X", abs),
+        AddrMode::IndexedAbsoluteY => format!("{},Y", abs),
+        AddrMode::IndexedIndirectX => format!("({},X)", zp),
+        AddrMode::IndirectIndexedY => format!("({}),Y", zp),
+        AddrMode::Indirect => format!("({})", abs),
+        AddrMode::Relative => absolute_operand(branch_target(addr, op_1), labels),
+    }
+}
+
+/// ゼロページのアドレスを、ラベルがあればラベル、なければ "$12" の形式で書式化する。
+pub(super) fn zero_page_operand(addr: u8, labels: &dyn Labels) -> String {
+    match labels.label(addr as u16) {
+        Some(label) => label.to_string(),
+        None => format!("${:02X}", addr),
+    }
+}
+
+/// アドレスを、ラベルがあればラベル、なければ "$1234" の形式で書式化する。
+pub(super) fn absolute_operand(addr: u16, labels: &dyn Labels) -> String {
+    match labels.label(addr) {
+        Some(label) => label.to_string(),
+        None => format!("${:04X}", addr),
+    }
+}
+
+/// 相対分岐の飛び先。オフセットは次の命令のアドレスからの符号付きの値。
+pub(super) fn branch_target(addr: u16, offset: u8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(((offset as i8) as i16) as u16)
+}
@@ -18,7 +18,6 @@ impl Cpu {
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn jsr_action(&mut self, _: u8) -> u8 {
-        log::debug!("[JSR]");
         // 何もしない
         0
     }
@@ -31,7 +30,6 @@ impl Cpu {
     //  (スタックの内容によって上書き)
     //////////////////////////////////////////////
     pub fn rti_action(&mut self, _: u8) -> u8 {
-        log::debug!("[RTI]");
         // 何もしない
         0
     }
@@ -45,7 +43,6 @@ impl Cpu {
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn rts_action(&mut self, _: u8) -> u8 {
-        log::debug!("[RTS]");
         // 何もしない
         0
     }
@@ -53,13 +50,13 @@ impl Cpu {
     //////////////////////////////////////////////
     /// PHP (Implied/Stack):
     /// ステータスレジスタの内容をスタックにPushし、スタックポインタを -1 する。
+    /// BRKと同様に、スタック上ではBreakフラグと予約ビットが立つ。
     //////////////////////////////////////////////
     //  N Z C I D V
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn php_action(&mut self, _: u8) -> u8 {
-        log::debug!("[PHP]");
-        self.push_stack(self.regs.p);
+        self.push_stack(self.regs.p | (Flags::BREAK | Flags::RESERVED).bits());
         0
     }
 
@@ -72,8 +69,8 @@ impl Cpu {
     //  (スタックの内容によって上書き)
     //////////////////////////////////////////////
     pub fn plp_action(&mut self, _: u8) -> u8 {
-        log::debug!("[PLP]");
-        self.regs.p = self.peek_stack();
+        let val = self.peek_stack();
+        self.regs.set_p_from_stack(val);
         0
     }
 
@@ -85,7 +82,6 @@ impl Cpu {
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn pha_action(&mut self, _: u8) -> u8 {
-        log::debug!("[PHA]");
         self.push_stack(self.regs.a);
         0
     }
@@ -99,7 +95,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn pla_action(&mut self, _: u8) -> u8 {
-        log::debug!("[PLA]");
         let val = self.peek_stack();
         self.regs.a_set(val);
         0
@@ -113,7 +108,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn dey_action(&mut self, _: u8) -> u8 {
-        log::debug!("[DEY]");
         self.regs.y_set(self.regs.y.wrapping_sub(1));
         0
     }
@@ -126,7 +120,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn tay_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TAY]");
         self.regs.y_set(self.regs.a);
         0
     }
@@ -139,7 +132,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn iny_action(&mut self, _: u8) -> u8 {
-        log::debug!("[INY]");
         self.regs.y_set(self.regs.y.wrapping_add(1));
         0
     }
@@ -152,7 +144,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn inx_action(&mut self, _: u8) -> u8 {
-        log::debug!("[INX]");
         self.regs.x_set(self.regs.x.wrapping_add(1));
         0
     }
@@ -165,7 +156,6 @@ impl Cpu {
     //  - - 0 - - -
     //////////////////////////////////////////////
     pub fn clc_action(&mut self, _: u8) -> u8 {
-        log::debug!("[CLC]");
         self.regs.flags_off(Flags::CARRY);
         0
     }
@@ -178,7 +168,6 @@ impl Cpu {
     //  - - 1 - - -
     //////////////////////////////////////////////
     pub fn sec_action(&mut self, _: u8) -> u8 {
-        log::debug!("[SEC]");
         self.regs.flags_on(Flags::CARRY);
         0
     }
@@ -191,7 +180,6 @@ impl Cpu {
     //  - - - 0 - -
     //////////////////////////////////////////////
     pub fn cli_action(&mut self, _: u8) -> u8 {
-        log::debug!("[CLI]");
         self.regs.flags_off(Flags::INT_DISABLE);
         0
     }
@@ -204,7 +192,6 @@ impl Cpu {
     //  - - - 1 - -
     //////////////////////////////////////////////
     pub fn sei_action(&mut self, _: u8) -> u8 {
-        log::debug!("[SEI]");
         self.regs.flags_on(Flags::INT_DISABLE);
         0
     }
@@ -217,7 +204,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn tya_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TYA]");
         self.regs.a_set(self.regs.y);
         0
     }
@@ -230,7 +216,6 @@ impl Cpu {
     //  - - - - - 0
     //////////////////////////////////////////////
     pub fn clv_action(&mut self, _: u8) -> u8 {
-        log::debug!("[CLV]");
         self.regs.flags_off(Flags::OVERFLOW);
         0
     }
@@ -243,7 +228,6 @@ impl Cpu {
     //  - - - - 0 -
     //////////////////////////////////////////////
     pub fn cld_action(&mut self, _: u8) -> u8 {
-        log::debug!("[CLD]");
         self.regs.flags_off(Flags::DECIMAL);
         0
     }
@@ -256,7 +240,6 @@ impl Cpu {
     //  - - - - 1 -
     //////////////////////////////////////////////
     pub fn sed_action(&mut self, _: u8) -> u8 {
-        log::debug!("[SED]");
         self.regs.flags_on(Flags::DECIMAL);
         0
     }
@@ -269,7 +252,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn txa_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TXA]");
         self.regs.a_set(self.regs.x);
         0
     }
@@ -282,7 +264,6 @@ impl Cpu {
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn txs_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TXS]");
         self.regs.s = self.regs.x;
         0
     }
@@ -295,7 +276,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn tax_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TAX]");
         self.regs.x_set(self.regs.a);
         0
     }
@@ -308,7 +288,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn tsx_action(&mut self, _: u8) -> u8 {
-        log::debug!("[TSX]");
         self.regs.x_set(self.regs.s);
         0
     }
@@ -321,7 +300,6 @@ impl Cpu {
     //  + + - - - -
     //////////////////////////////////////////////
     pub fn dex_action(&mut self, _: u8) -> u8 {
-        log::debug!("[DEX]");
         self.regs.x_set(self.regs.x.wrapping_sub(1));
         0
     }
@@ -334,7 +312,6 @@ impl Cpu {
     //  - - - - - -
     //////////////////////////////////////////////
     pub fn nop_action(&mut self, _: u8) -> u8 {
-        log::debug!("[NOP]");
         0
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 //! Instruction executer.
 
-use super::{Cpu, Flags, IntType};
+use super::{Cpu, FlowEvent, IntType};
 use crate::nes::util::make_addr;
 use super::instruction::*;
 
@@ -33,6 +33,18 @@ impl Cpu {
 
     pub fn fn_exec_dummy(&mut self) { unreachable!() }
     pub fn fn_core_dummy(&mut self, _val: u8) -> u8 { unreachable!() }
+
+    /// 命令の種類に応じて、実効アドレスの値を読んでコア処理に渡すか、
+    /// コア処理の結果を実効アドレスに書き込む。
+    fn access_operand(&mut self, addr: u16) {
+        if self.state.executer.inst.dst == Destination::Register {
+            let val = self.mem.read(addr);
+            (self.state.executer.inst.fn_core)(self, val);
+        } else {
+            let val = (self.state.executer.inst.fn_core)(self, 0);
+            self.mem.write(addr, val);
+        }
+    }
     
     pub fn exec_immediate(&mut self) {
         match self.state.counter {
@@ -69,7 +81,11 @@ impl Cpu {
     pub fn exec_indexed_zeropage_x(&mut self) {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
-            3 => self.state.op_1 = self.state.op_1.wrapping_add(self.regs.x),
+            3 => {
+                // インデックスを加算する間に、加算前のアドレスをダミーリードする。
+                self.mem.read(self.state.op_1 as u16);
+                self.state.op_1 = self.state.op_1.wrapping_add(self.regs.x);
+            },
             4 => {
                 let addr = self.state.op_1 as u16;
                 if self.state.executer.inst.dst == Destination::Register {
@@ -88,7 +104,11 @@ impl Cpu {
     pub fn exec_indexed_zeropage_y(&mut self) {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
-            3 => self.state.op_1 = self.state.op_1.wrapping_add(self.regs.y),
+            3 => {
+                // インデックスを加算する間に、加算前のアドレスをダミーリードする。
+                self.mem.read(self.state.op_1 as u16);
+                self.state.op_1 = self.state.op_1.wrapping_add(self.regs.y);
+            },
             4 => {
                 let addr = self.state.op_1 as u16;
                 if self.state.executer.inst.dst == Destination::Register {
@@ -126,6 +146,8 @@ impl Cpu {
     pub fn exec_implied(&mut self) {
         match self.state.counter {
             2 => {
+                // 次の命令をダミーリードする。(PCは進めない)
+                self.mem.read(self.regs.pc);
                 (self.state.executer.inst.fn_core)(self, 0);
                 self.exec_finished();
             },
@@ -134,53 +156,41 @@ impl Cpu {
     }
     
     pub fn exec_indexed_absolute_x(&mut self) {
-        match self.state.counter {
-            2 => self.state.op_1 = self.fetch(),
-            3 => self.state.op_2 = self.fetch(),
-            4 => {
-                let low = self.state.op_1;
-                let high = self.state.op_2;
-                let addr = make_addr(high, low).wrapping_add(self.regs.x as u16);
-                if self.state.executer.inst.dst == Destination::Register {
-                    let val = self.mem.read(addr);
-                    (self.state.executer.inst.fn_core)(self, val);
-                } else {
-                    let val = (self.state.executer.inst.fn_core)(self, 0);
-                    self.mem.write(addr, val);
-                }
-                if let Some(_) = low.checked_add(self.regs.x) {
-                    self.exec_finished();
-                } else {
-                    self.state.executer.last_cycle += 1;
-                }
-            },
-            5 => self.exec_finished(),
-            _ => unreachable!(),
-        }
+        let index = self.regs.x;
+        self.exec_indexed_absolute(index);
     }
 
     pub fn exec_indexed_absolute_y(&mut self) {
+        let index = self.regs.y;
+        self.exec_indexed_absolute(index);
+    }
+
+    /// IndexedAbsolute(X/Y)アドレッシングの共通部分
+    fn exec_indexed_absolute(&mut self, index: u8) {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
-            3 => self.state.op_2 = self.fetch(),
-            4 => {
+            3 => {
                 let low = self.state.op_1;
-                let high = self.state.op_2;
-                let addr = make_addr(high, low).wrapping_add(self.regs.y as u16);
-                if self.state.executer.inst.dst == Destination::Register {
-                    let val = self.mem.read(addr);
-                    (self.state.executer.inst.fn_core)(self, val);
-                } else {
-                    let val = (self.state.executer.inst.fn_core)(self, 0);
-                    self.mem.write(addr, val);
-                }
-                if let Some(_) = low.checked_add(self.regs.y) {
-                    self.exec_finished();
-                } else {
+                let high = self.fetch();
+                self.state.op_2 = high;
+                self.state.addr = make_addr(high, low).wrapping_add(index as u16);
+                // ページをまたぐ場合は、上位アドレスの補正に1クロック余分にかかる。
+                // 書き込み命令の場合は、ページをまたがなくても常に1クロック余分にかかる。
+                if low.checked_add(index).is_none() ||
+                    self.state.executer.inst.dst == Destination::Memory
+                {
                     self.state.executer.last_cycle += 1;
                 }
             },
-            5 => self.exec_finished(),
+            4 | 5 if self.state.counter == self.state.executer.last_cycle => {
+                self.access_operand(self.state.addr);
+                self.exec_finished();
+            },
+            // 上位アドレスの補正中。補正前のアドレスをダミーリードする。
+            4 => {
+                let addr = make_addr(self.state.op_2, self.state.op_1.wrapping_add(index));
+                self.mem.read(addr);
+            },
             _ => unreachable!(),
         }
     }
@@ -189,6 +199,8 @@ impl Cpu {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
             3 => {
+                // インデックスを加算する間に、加算前のアドレスをダミーリードする。
+                self.mem.read(self.state.op_1 as u16);
                 self.state.op_1 = self.state.op_1.wrapping_add(self.regs.x);
             }
             4 => {
@@ -226,35 +238,37 @@ impl Cpu {
             4 => {
                 let addr = self.state.op_1.wrapping_add(1);
                 let high = self.mem.read(addr as u16);
-                self.state.op_1 = high;
-            },
-            5 => {
-                let high = self.state.op_1;
                 let low = self.state.op_2;
-                let addr = make_addr(high, low);
-                let addr = addr.wrapping_add(self.regs.y as u16);
-                if self.state.executer.inst.dst == Destination::Register {
-                    let val = self.mem.read(addr);
-                    (self.state.executer.inst.fn_core)(self, val);
-                } else {
-                    let val = (self.state.executer.inst.fn_core)(self, 0);
-                    self.mem.write(addr, val);
-                }
-                if let Some(_) = low.checked_add(self.regs.y) {
-                    self.exec_finished();
-                } else {
+                // ポインタはもう使わないので、補正前のアドレスのために上位アドレスを覚えておく。
+                self.state.op_1 = high;
+                self.state.addr = make_addr(high, low).wrapping_add(self.regs.y as u16);
+                // ページをまたぐ場合と、書き込み命令の場合は1クロック余分にかかる。
+                if low.checked_add(self.regs.y).is_none() ||
+                    self.state.executer.inst.dst == Destination::Memory
+                {
                     self.state.executer.last_cycle += 1;
                 }
-            }
-            6 => self.exec_finished(),
+            },
+            5 | 6 if self.state.counter == self.state.executer.last_cycle => {
+                self.access_operand(self.state.addr);
+                self.exec_finished();
+            },
+            // 上位アドレスの補正中。補正前のアドレスをダミーリードする。
+            5 => {
+                let addr = make_addr(self.state.op_1, self.state.op_2.wrapping_add(self.regs.y));
+                self.mem.read(addr);
+            },
             _ => unreachable!(),
         }
     }
 
     pub fn exec_pull_stack(&mut self) {
         match self.state.counter {
-            2 => (),
+            // 次の命令をダミーリードする。
+            2 => { self.mem.read(self.regs.pc); },
             3 => {
+                // スタックポインタを加算する間に、加算前の位置をダミーリードする。
+                self.peek_stack();
                 self.inc_stack();
             }
             4 => { 
@@ -267,7 +281,8 @@ impl Cpu {
 
     pub fn exec_push_stack(&mut self) {
         match self.state.counter {
-            2 => (),
+            // 次の命令をダミーリードする。
+            2 => { self.mem.read(self.regs.pc); },
             3 => {
                 (self.state.executer.inst.fn_core)(self, 0);
                 self.exec_finished();
@@ -279,18 +294,21 @@ impl Cpu {
     /// 注：この関数内で処理が完結する。
     pub fn exec_rti(&mut self) {
         match self.state.counter {
-            2 => (),
-            3 => (),
+            // 次の命令をダミーリードする。
+            2 => { self.mem.read(self.regs.pc); },
+            // スタックポインタを加算する前の位置をダミーリードする。
+            3 => { self.peek_stack(); },
             4 => {
-                // スタックからステータスレジスタの内容を復元するが、
-                // Brkフラグは実在しないので 0 にしておく。
-                self.regs.p = self.pull_stack() & !Flags::BREAK.bits;
+                // スタックからステータスレジスタの内容を復元する。
+                let val = self.pull_stack();
+                self.regs.set_p_from_stack(val);
             },
             5 => self.state.op_1 = self.pull_stack(),
             6 => {
                 let low = self.state.op_1;
                 let high = self.pull_stack();
                 self.regs.pc = make_addr(high, low);
+                self.flow_event = Some(FlowEvent::ReturnFromInterrupt);
                 self.exec_finished();
             },
             _ => unreachable!(),
@@ -300,8 +318,13 @@ impl Cpu {
     /// 注：この関数内で処理が完結する。
     pub fn exec_rts(&mut self) {
         match self.state.counter {
-            2 => (),
-            3 => self.inc_stack(),
+            // 次の命令をダミーリードする。
+            2 => { self.mem.read(self.regs.pc); },
+            3 => {
+                // スタックポインタを加算する間に、加算前の位置をダミーリードする。
+                self.peek_stack();
+                self.inc_stack();
+            },
             4 => {
                 self.state.op_1 = self.peek_stack();
                 self.inc_stack();
@@ -310,7 +333,11 @@ impl Cpu {
             6 => {
                 let low = self.state.op_1;
                 let high = self.state.op_2;
-                self.regs.pc = make_addr(high, low).wrapping_add(1);
+                // スタックに積まれているのはJSRの最後のバイトのアドレス。
+                // そのアドレスをダミーリードしながら、PCを+1する。
+                self.regs.pc = make_addr(high, low);
+                self.fetch();
+                self.flow_event = Some(FlowEvent::Return);
                 self.exec_finished();
             },
             _ => unreachable!(),
@@ -321,7 +348,8 @@ impl Cpu {
     pub fn exec_jsr(&mut self) {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
-            3 => (),
+            // スタックの現在位置をダミーリードする。
+            3 => { self.peek_stack(); },
             4 => {
                 let high = ((self.regs.pc & 0xFF00) >> 8) as u8;
                 self.push_stack(high);
@@ -334,6 +362,7 @@ impl Cpu {
                 let low = self.state.op_1;
                 let high = self.fetch();
                 self.regs.pc = make_addr(high, low);
+                self.flow_event = Some(FlowEvent::Call);
                 self.exec_finished();
             },
             _ => unreachable!(),
@@ -343,6 +372,8 @@ impl Cpu {
     pub fn exec_accumulator(&mut self) {
         match self.state.counter {
             2 => {
+                // 次の命令をダミーリードする。
+                self.mem.read(self.regs.pc);
                 let result = (self.state.executer.inst.fn_core)(self, self.regs.a);
                 // フラグは変更済みなので、ここでは代入するだけ
                 self.regs.a = result;
@@ -360,6 +391,8 @@ impl Cpu {
                 self.state.op_2 = self.mem.read(self.state.op_1 as u16);
             },
             4 => {
+                // 結果を計算する間に、読んだ値をそのまま書き戻す。
+                self.mem.write(self.state.op_1 as u16, self.state.op_2);
                 self.state.op_2 = (self.state.executer.inst.fn_core)(self, self.state.op_2);
             },
             5 => {
@@ -376,12 +409,18 @@ impl Cpu {
     pub fn exec_indexed_zeropage_x_rmw(&mut self) {
         match self.state.counter {
             2 => self.state.op_1 = self.fetch(),
-            3 => self.state.op_1 = self.state.op_1.wrapping_add(self.regs.x),
+            3 => {
+                // インデックスを加算する間に、加算前のアドレスをダミーリードする。
+                self.mem.read(self.state.op_1 as u16);
+                self.state.op_1 = self.state.op_1.wrapping_add(self.regs.x);
+            },
             4 => {
                 self.state.addr = self.state.op_1 as u16;
                 self.state.op_2 = self.mem.read(self.state.addr);
             },
             5 => {
+                // 結果を計算する間に、読んだ値をそのまま書き戻す。
+                self.mem.write(self.state.addr, self.state.op_2);
                 self.state.op_2 = (self.state.executer.inst.fn_core)(self, self.state.op_2);
             },
             6 => {
@@ -405,6 +444,8 @@ impl Cpu {
                 self.state.op_2 = self.mem.read(addr);
             },
             5 => {
+                // 結果を計算する間に、読んだ値をそのまま書き戻す。
+                self.mem.write(self.state.addr, self.state.op_2);
                 self.state.op_2 = (self.state.executer.inst.fn_core)(self, self.state.op_2);
             },
             6 => {
@@ -423,11 +464,20 @@ impl Cpu {
             3 => {
                 let low = self.state.op_1;
                 let high = self.fetch();
+                self.state.op_2 = high;
                 self.state.addr = make_addr(high, low).wrapping_add(self.regs.x as u16);
             },
-            4 => (),
+            // 上位アドレスの補正中。ページをまたぐかどうかに関わらず、補正前のアドレスをダミーリードする。
+            4 => {
+                let addr = make_addr(self.state.op_2, self.state.op_1.wrapping_add(self.regs.x));
+                self.mem.read(addr);
+            },
             5 => self.state.op_1 = self.mem.read(self.state.addr),
-            6 => self.state.op_2 = (self.state.executer.inst.fn_core)(self, self.state.op_1),
+            6 => {
+                // 結果を計算する間に、読んだ値をそのまま書き戻す。
+                self.mem.write(self.state.addr, self.state.op_1);
+                self.state.op_2 = (self.state.executer.inst.fn_core)(self, self.state.op_1);
+            },
             7 => {
                 self.mem.write(self.state.addr, self.state.op_2);
                 self.exec_finished();
@@ -461,8 +511,12 @@ impl Cpu {
             },
             4 => self.state.op_1 = self.mem.read(self.state.addr),
             5 => {
+                // 6502のバグにより、ポインタが $xxFF の場合、上位アドレスは
+                // $xx00 から読まれる。(ページをまたがない)
+                let addr = self.state.addr;
+                let high_addr = (addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF);
                 let low = self.state.op_1;
-                let high = self.mem.read(self.state.addr.wrapping_add(1));
+                let high = self.mem.read(high_addr);
                 self.regs.pc = make_addr(high, low);
                 self.exec_finished();
             }
@@ -496,8 +550,10 @@ impl Cpu {
                 }
             },
             3 => {
+                // 分岐先を計算する間に、次の命令をダミーリードする。
+                self.mem.read(self.regs.pc);
                 self.state.op_1 -= 1;
-                if self.state.op_1 <= 0 {
+                if self.state.op_1 == 0 {
                     // 分岐が発生して、かつ同じページ内へジャンプする場合は、例外の発生が1命令遅れる。
                     if self.int_requested.kind != IntType::None {
                         self.int_requested.is_force_delayed = true;
@@ -509,6 +565,8 @@ impl Cpu {
                 }
             }
             4 => {
+                // 上位アドレスの補正中。補正前のアドレスをダミーリードする。
+                self.mem.read((self.regs.pc & 0xFF00) | (self.state.addr & 0x00FF));
                 self.regs.pc = self.state.addr;
                 self.exec_finished();
             }
@@ -28,6 +28,10 @@ pub enum AddrMode {
     /// そのアドレスが指す8bitの値に対して演算を行う。
     /// 算出したアドレスがゼロページ(0-255)を超過する、しないに関わらず、常に下位8bitの値しか見ない。
     IndexedZeroPageX,
+    /// オペランドで指定した8bitのアドレスに、レジスタYを加算して、
+    /// そのアドレスが指す8bitの値に対して演算を行う。(LDX, STXでのみ使用)
+    /// 算出したアドレスがゼロページ(0-255)を超過する、しないに関わらず、常に下位8bitの値しか見ない。
+    IndexedZeroPageY,
     /// オペランドで指定した8bitの値に、レジスタXの値を足して、ゼロページ内のアドレスを得る。
     /// 次に、このアドレスの指す8bitを下位アドレス、アドレス+1 の指す内容を上位8bitとして、
     /// 16bitの最終アドレスを得る。この最終アドレスの指す先の、8bitの値に対して操作を行う。
@@ -82,6 +86,33 @@ macro_rules! new_instruction{
     }
 }
 
+impl AddrMode {
+    /// オペコードに続くオペランドのバイト数。
+    pub fn operand_len(&self) -> u16 {
+        match self {
+            AddrMode::Accumulator |
+            AddrMode::Implied => 0,
+            AddrMode::Immediate |
+            AddrMode::ZeroPage |
+            AddrMode::IndexedZeroPageX |
+            AddrMode::IndexedZeroPageY |
+            AddrMode::IndexedIndirectX |
+            AddrMode::IndirectIndexedY |
+            AddrMode::Relative => 1,
+            AddrMode::Absolute |
+            AddrMode::IndexedAbsoluteX |
+            AddrMode::IndexedAbsoluteY |
+            AddrMode::Indirect => 2,
+        }
+    }
+}
+
+/// 非公式(undocumented)な命令であればtrue。
+/// INSTRUCTION_SET に "(undocumented)" と記載したオペコードと一致させること。
+pub fn is_undocumented(opcode: u8) -> bool {
+    matches!(opcode, 0x80 | 0x82 | 0xEB)
+}
+
 impl Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} ({})", self.core_name, self.template_name)
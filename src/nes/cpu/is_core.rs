@@ -128,7 +128,7 @@ pub const IS_JMP :IsCore = IsCore {
 pub const IS_STY :IsCore = IsCore {
     name: "STY",
     fn_core: Cpu::sty_action,
-    dst: Destination::Register,
+    dst: Destination::Memory,
 };
 
 pub const IS_LDY :IsCore = IsCore {
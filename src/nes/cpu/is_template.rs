@@ -57,7 +57,7 @@ pub const IS_TEMP_INDEXED_ZEROPAGE_Y :IsTemplate = IsTemplate {
     name: "exec_indexed_zeropage_y",
     min_clock: 4,
     fn_exec: Cpu::exec_indexed_zeropage_y,
-    addr_mode: AddrMode::IndexedZeroPageX,
+    addr_mode: AddrMode::IndexedZeroPageY,
 };
 
 pub const IS_TEMP_INDEXED_ZEROPAGE_X :IsTemplate = IsTemplate {
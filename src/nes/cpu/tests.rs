@@ -0,0 +1,246 @@
+//! CPUのテスト。
+//!
+//! 命令ごとのテストは SingleStepTests (https://github.com/SingleStepTests/65x02) の
+//! nes6502 のテストベクタを、フラットな64KBのRAMの上で実行する。
+//! 正とするのは上流のテストベクタで、tools/fetch_single_step_tests.sh で取得し、
+//! 環境変数 SINGLE_STEP_TESTS_DIR に nes6502/v1 ディレクトリを指定して実行する。
+//! 指定がなければ、オフラインでも動くよう同じ形式の tests/data/cpu
+//! (tools/gen_cpu_tests.py で生成した少数のベクタ) を使う。
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use super::*;
+use super::instruction::INSTRUCTION_SET;
+
+/// バス上で発生したアクセス (アドレス, 値, 書き込みならtrue)
+type BusCycle = (u16, u8, bool);
+
+/// 64KBのRAMだけが繋がったバス。バス上のアクセスを記録する。
+struct RecordingBus {
+    ram: Box<[u8]>,
+    cycles: Rc<RefCell<Vec<BusCycle>>>,
+}
+
+impl CpuBus for RecordingBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.ram[addr as usize];
+        self.cycles.borrow_mut().push((addr, data, false));
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+        self.cycles.borrow_mut().push((addr, data, true));
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+/// フラットなRAMに接続したCPUを作る。
+fn flat_cpu() -> (Cpu, Rc<RefCell<Vec<BusCycle>>>) {
+    let cycles = Rc::new(RefCell::new(Vec::new()));
+    let bus = RecordingBus {
+        ram: vec![0; 0x10000].into_boxed_slice(),
+        cycles: Rc::clone(&cycles),
+    };
+    (Cpu::new(Box::new(bus)), cycles)
+}
+
+//////////////////////////////////////////////
+// 演算器
+//////////////////////////////////////////////
+
+fn regs_with(a: u8, p: u8) -> Registers {
+    Registers { a, p, ..Default::default() }
+}
+
+#[test]
+fn a_add_sets_carry_and_overflow() {
+    // 符号付きで 0x50 + 0x50 = 0xA0 は桁溢れ
+    let mut regs = regs_with(0x50, 0);
+    regs.a_add(0x50);
+    assert_eq!(regs.a, 0xA0);
+    assert_eq!(regs.p, (Flags::OVERFLOW | Flags::NEGATIVE).bits());
+
+    // 0xFF + 0x01 = 0x00 (キャリーあり、オーバーフローなし)
+    let mut regs = regs_with(0xFF, 0);
+    regs.a_add(0x01);
+    assert_eq!(regs.a, 0x00);
+    assert_eq!(regs.p, (Flags::CARRY | Flags::ZERO).bits());
+
+    // キャリーも加算される
+    let mut regs = regs_with(0x10, Flags::CARRY.bits());
+    regs.a_add(0x20);
+    assert_eq!(regs.a, 0x31);
+    assert_eq!(regs.p, 0);
+}
+
+#[test]
+fn a_sub_borrows_when_carry_is_clear() {
+    let mut regs = regs_with(0x50, Flags::CARRY.bits());
+    regs.a_sub(0xB0);
+    assert_eq!(regs.a, 0xA0);
+    assert_eq!(regs.p, (Flags::OVERFLOW | Flags::NEGATIVE).bits());
+
+    let mut regs = regs_with(0x05, 0);
+    regs.a_sub(0x03);
+    assert_eq!(regs.a, 0x01);
+    assert_eq!(regs.p, Flags::CARRY.bits());
+}
+
+#[test]
+fn cmp_keeps_overflow_and_register() {
+    let mut regs = regs_with(0x40, Flags::OVERFLOW.bits());
+    regs.cmp(0x40, 0x41);
+    assert_eq!(regs.a, 0x40);
+    assert_eq!(regs.p, (Flags::OVERFLOW | Flags::NEGATIVE).bits());
+
+    regs.cmp(0x40, 0x40);
+    assert_eq!(regs.p, (Flags::OVERFLOW | Flags::ZERO | Flags::CARRY).bits());
+
+    regs.cmp(0x00, 0xFF);
+    assert_eq!(regs.p, Flags::OVERFLOW.bits());
+}
+
+//////////////////////////////////////////////
+// 命令ごとのテストベクタ
+//////////////////////////////////////////////
+
+/// 1命令が終わるまでに許容する最大クロック数
+const MAX_CYCLES_PER_INSTRUCTION: usize = 16;
+
+/// テスト対象のオペコード。BRKはテーブルに無いが割り込みとして実装されている。
+fn tested_opcodes() -> Vec<u8> {
+    (0..=0xFFu8)
+        .filter(|&op| op == 0x00 || INSTRUCTION_SET[op as usize].is_some())
+        .collect()
+}
+
+fn vector_dir() -> PathBuf {
+    match std::env::var_os("SINGLE_STEP_TESTS_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cpu"),
+    }
+}
+
+fn load_vectors(opcode: u8) -> Vec<Value> {
+    let path = vector_dir().join(format!("{:02x}.json", opcode));
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    match serde_json::from_str(&text) {
+        Ok(Value::Array(tests)) => tests,
+        _ => panic!("{}: not a test vector array", path.display()),
+    }
+}
+
+fn field(state: &Value, name: &str) -> u64 {
+    state[name].as_u64().unwrap_or_else(|| panic!("missing field: {}", name))
+}
+
+fn ram_of(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"].as_array().unwrap().iter()
+        .map(|pair| (pair[0].as_u64().unwrap() as u16, pair[1].as_u64().unwrap() as u8))
+        .collect()
+}
+
+fn cycles_of(test: &Value) -> Vec<BusCycle> {
+    test["cycles"].as_array().unwrap().iter()
+        .map(|c| (c[0].as_u64().unwrap() as u16, c[1].as_u64().unwrap() as u8, c[2] == "write"))
+        .collect()
+}
+
+/// テストベクタの初期状態から1命令を実行し、(CPU, 経過クロック数, バスのアクセス履歴) を返す。
+fn run_vector(test: &Value) -> (Cpu, usize, Vec<BusCycle>) {
+    let (mut cpu, cycles) = flat_cpu();
+    let initial = &test["initial"];
+    for (addr, data) in ram_of(initial) {
+        cpu.mem_mut().write(addr, data);
+    }
+    {
+        let regs = cpu.regs_mut();
+        regs.s = field(initial, "s") as u8;
+        regs.a = field(initial, "a") as u8;
+        regs.x = field(initial, "x") as u8;
+        regs.y = field(initial, "y") as u8;
+        regs.p = field(initial, "p") as u8;
+    }
+    cpu.jump_to(field(initial, "pc") as u16);
+    cycles.borrow_mut().clear();
+
+    let mut count = 0;
+    loop {
+        cpu.step();
+        count += 1;
+        if cpu.is_instruction_boundary() || count >= MAX_CYCLES_PER_INSTRUCTION {
+            break;
+        }
+    }
+    let cycles = cycles.borrow().clone();
+    (cpu, count, cycles)
+}
+
+/// 最終状態(レジスタ、メモリ)と所要クロック数を比較し、食い違いを返す。
+fn check_final_state(test: &Value, cpu: &Cpu, count: usize) -> Vec<String> {
+    let expected = &test["final"];
+    let regs = cpu.regs();
+    let mut errors = Vec::new();
+    let actual_regs = [
+        ("pc", regs.pc as u64), ("s", regs.s as u64), ("a", regs.a as u64),
+        ("x", regs.x as u64), ("y", regs.y as u64), ("p", regs.p as u64),
+    ];
+    for (name, actual) in actual_regs {
+        let expected = field(expected, name);
+        if expected != actual {
+            errors.push(format!("{}: expected {:#X}, actual {:#X}", name, expected, actual));
+        }
+    }
+    for (addr, data) in ram_of(expected) {
+        let actual = cpu.mem().peek(addr);
+        if actual != data {
+            errors.push(format!("[{:#06X}]: expected {:#04X}, actual {:#04X}", addr, data, actual));
+        }
+    }
+    let expected_cycles = test["cycles"].as_array().unwrap().len();
+    if expected_cycles != count {
+        errors.push(format!("cycles: expected {}, actual {}", expected_cycles, count));
+    }
+    errors
+}
+
+#[test]
+fn single_step_vectors() {
+    let mut failures = Vec::new();
+    for opcode in tested_opcodes() {
+        for test in load_vectors(opcode) {
+            let (cpu, count, _) = run_vector(&test);
+            let errors = check_final_state(&test, &cpu, count);
+            if !errors.is_empty() {
+                failures.push(format!("{:02X} [{}] {}", opcode, test["name"], errors.join(", ")));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
+
+/// クロックごとのバスへのアクセス(ダミーリードや、Read-Modify-Writeの2回の書き込みを含む)まで比較する。
+#[test]
+fn single_step_bus_activity() {
+    let mut failures = Vec::new();
+    for opcode in tested_opcodes() {
+        for test in load_vectors(opcode) {
+            let (_, _, cycles) = run_vector(&test);
+            let expected = cycles_of(&test);
+            if cycles != expected {
+                failures.push(format!("{:02X} [{}]\n  expected: {:?}\n  actual:   {:?}",
+                    opcode, test["name"], expected, cycles));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
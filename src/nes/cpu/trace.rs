@@ -0,0 +1,375 @@
+//! 命令単位のトレースログ。
+//! nestest.log (Nintendulator形式) と同じ書式で出力し、リファレンスのログとの比較も行う。
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//! ```
+
+use std::fmt::{self, Display};
+use std::io::BufRead;
+
+use bitflags::bitflags;
+
+use super::Cpu;
+use super::disasm::{self, Labels, NoLabels};
+use super::instruction::*;
+use crate::nes::util::make_addr;
+
+/// 逆アセンブル結果の表示幅(nestest.logの列位置に合わせる)
+const DISASM_WIDTH: usize = 31;
+
+/// 1命令分のトレース。命令を実行する直前の状態を表す。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub pc: u16,
+    /// オペコードとオペランド
+    pub bytes: Vec<u8>,
+    /// 非公式命令ならtrue。ログ上では命令の前に '*' が付く。
+    pub undocumented: bool,
+    /// 逆アセンブル結果。参照先のメモリの値などの注釈を含む。
+    pub disasm: String,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub s: u8,
+    pub scanline: u16,
+    pub dot: u16,
+    /// 電源投入後に経過したCPUのクロックサイクル数
+    pub cycle: u64,
+}
+
+bitflags! {
+    /// トレースに出力する項目。命令(アドレス、バイト列、逆アセンブル結果)は常に出力する。
+    pub struct TraceFields: u8 {
+        /// A, X, Y, P, SP
+        const REGS   = 0b0000_0001;
+        /// PPUの位置(スキャンライン, ドット)
+        const PPU    = 0b0000_0010;
+        /// CPUのクロックサイクル数
+        const CYCLES = 0b0000_0100;
+        /// PCにマップされているPRG-ROMのバンク番号(16KB単位)
+        const BANK   = 0b0000_1000;
+        /// nestest.log と同じ項目
+        const NESTEST = Self::REGS.bits | Self::PPU.bits | Self::CYCLES.bits;
+    }
+}
+
+impl TraceFields {
+    /// "regs,ppu,cycles,bank" のようなカンマ区切りの一覧から変換する。"none" なら命令だけ。
+    pub fn from_names(names: &str) -> Option<Self> {
+        let mut fields = TraceFields::empty();
+        for name in names.split(',').map(str::trim) {
+            fields |= match name.to_ascii_lowercase().as_str() {
+                "regs" | "registers" => TraceFields::REGS,
+                "ppu" | "scanline" | "dot" => TraceFields::PPU,
+                "cycles" | "cyc" => TraceFields::CYCLES,
+                "bank" | "banks" => TraceFields::BANK,
+                "none" => TraceFields::empty(),
+                _ => return None,
+            };
+        }
+        Some(fields)
+    }
+}
+
+impl Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format_with(TraceFields::NESTEST, None))
+    }
+}
+
+impl TraceLine {
+    /// 指定した項目だけを出力する。bank は PC にマップされているPRG-ROMのバンク番号。
+    /// 項目が TraceFields::NESTEST なら、nestest.log と同じ書式になる。
+    pub fn format_with(&self, fields: TraceFields, bank: Option<usize>) -> String {
+        let bytes = self.bytes.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mark = if self.undocumented { '*' } else { ' ' };
+        let mut line = format!("{:04X}  {:<8} {}{:<w$}", self.pc, bytes, mark, self.disasm, w = DISASM_WIDTH);
+        if fields.contains(TraceFields::REGS) {
+            line += &format!(" A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", self.a, self.x, self.y, self.p, self.s);
+        }
+        if fields.contains(TraceFields::PPU) {
+            line += &format!(" PPU:{:>3},{:>3}", self.scanline, self.dot);
+        }
+        if fields.contains(TraceFields::CYCLES) {
+            line += &format!(" CYC:{}", self.cycle);
+        }
+        if fields.contains(TraceFields::BANK) {
+            match bank {
+                Some(bank) => line += &format!(" BANK:{:02X}", bank),
+                None => line += " BANK:--",
+            }
+        }
+        line.truncate(line.trim_end().len());
+        line
+    }
+
+    /// nestest.log形式の1行を解析する。
+    pub fn parse(line: &str) -> Option<TraceLine> {
+        let line = line.trim_end();
+        let pc = u16::from_str_radix(line.get(0..4)?, 16).ok()?;
+        let bytes = line.get(6..14)?
+            .split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let undocumented = line.get(15..16)? == "*";
+        let regs_pos = line.find(" A:")?;
+        let disasm = line.get(16..regs_pos)?.trim_end().to_string();
+        let regs = &line[regs_pos..];
+
+        let hex_field = |name: &str| -> Option<u8> {
+            let start = regs.find(name)? + name.len();
+            u8::from_str_radix(regs.get(start..start+2)?, 16).ok()
+        };
+        let (scanline, dot) = {
+            let start = regs.find("PPU:")? + 4;
+            let end = regs.find(" CYC:")?;
+            let (scanline, dot) = regs.get(start..end)?.split_once(',')?;
+            (scanline.trim().parse().ok()?, dot.trim().parse().ok()?)
+        };
+        let cycle = {
+            let start = regs.find("CYC:")? + 4;
+            regs[start..].trim().parse().ok()?
+        };
+
+        Some(TraceLine {
+            pc,
+            bytes,
+            undocumented,
+            disasm,
+            a: hex_field(" A:")?,
+            x: hex_field(" X:")?,
+            y: hex_field(" Y:")?,
+            p: hex_field(" P:")?,
+            s: hex_field(" SP:")?,
+            scanline,
+            dot,
+            cycle,
+        })
+    }
+
+    /// 2つのトレースを比較し、最初に一致しなかった項目の名前を返す。
+    /// 逆アセンブル結果の書式の差異は無視する。
+    pub fn first_mismatch(&self, other: &TraceLine, check_ppu: bool) -> Option<&'static str> {
+        if self.pc != other.pc { return Some("PC") }
+        if self.bytes != other.bytes { return Some("bytes") }
+        if self.a != other.a { return Some("A") }
+        if self.x != other.x { return Some("X") }
+        if self.y != other.y { return Some("Y") }
+        if self.p != other.p { return Some("P") }
+        if self.s != other.s { return Some("SP") }
+        if check_ppu && (self.scanline, self.dot) != (other.scanline, other.dot) {
+            return Some("PPU")
+        }
+        if self.cycle != other.cycle { return Some("CYC") }
+        None
+    }
+}
+
+/// リファレンスのログと、実行中のトレースとの比較結果。
+#[derive(Debug)]
+pub enum Comparison {
+    /// ここまでは一致している。
+    Matched,
+    /// リファレンスのログを最後まで比較し終えた。
+    Finished,
+    /// 一致しなかった。
+    Diverged(Divergence),
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    /// リファレンスのログの行番号(1始まり)
+    pub line_no: usize,
+    /// 一致しなかった項目
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: TraceLine,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diverged at line {} ({}):", self.line_no, self.field)?;
+        writeln!(f, "expected: {}", self.expected)?;
+        write!(f, "actual:   {}", self.actual)
+    }
+}
+
+/// nestest.log等のリファレンスのログと、1行ずつ比較を行う。
+pub struct LogComparator<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line_no: usize,
+    /// PPUのドット/スキャンラインも比較するならtrue
+    check_ppu: bool,
+}
+
+impl<R: BufRead> LogComparator<R> {
+    pub fn new(reader: R, check_ppu: bool) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_no: 0,
+            check_ppu,
+        }
+    }
+
+    /// 次の命令のトレースを、リファレンスのログの次の行と比較する。
+    pub fn compare(&mut self, actual: &TraceLine) -> Comparison {
+        // 空行は読み飛ばす
+        let expected = loop {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line_no += 1;
+                    if !line.trim().is_empty() {
+                        break line
+                    }
+                },
+                _ => return Comparison::Finished,
+            }
+        };
+
+        let field = match TraceLine::parse(&expected) {
+            Some(expected) => actual.first_mismatch(&expected, self.check_ppu),
+            None => Some("format"),
+        };
+        match field {
+            None => Comparison::Matched,
+            Some(field) => Comparison::Diverged(Divergence {
+                line_no: self.line_no,
+                field,
+                expected,
+                actual: actual.clone(),
+            }),
+        }
+    }
+}
+
+impl Cpu {
+    /// 次に実行する命令のトレースを作成する。命令の境界で呼び出すこと。
+    /// PPUの位置はCPUからは分からないので、呼び出し側で指定する。
+    pub fn trace_line(&self, scanline: u16, dot: u16) -> TraceLine {
+        self.trace_line_with(scanline, dot, &NoLabels)
+    }
+
+    /// trace_line と同じだが、オペランドのアドレスを labels のラベルに置き換える。
+    /// 参照先の実効アドレスと値の注釈は16進数のまま。
+    pub fn trace_line_with(&self, scanline: u16, dot: u16, labels: &dyn Labels) -> TraceLine {
+        let pc = self.regs.pc;
+        let opcode = self.peek(pc);
+        // BRKはパディング用の1バイトがあるが、ログ上は1バイト命令として扱う。
+        let line = disasm::disasm_at(|addr| self.peek(addr), pc);
+        let disasm = match INSTRUCTION_SET[opcode as usize] {
+            Some(inst) => format!("{} {}", inst.core_name, self.annotated_operand(inst, &line.bytes, labels))
+                .trim_end().to_string(),
+            None if opcode == 0x00 => line.text,
+            None => "???".to_string(),
+        };
+
+        TraceLine {
+            pc,
+            undocumented: line.undocumented,
+            bytes: line.bytes,
+            disasm,
+            a: self.regs.a,
+            x: self.regs.x,
+            y: self.regs.y,
+            p: self.regs.p,
+            s: self.regs.s,
+            scanline,
+            dot,
+            cycle: self.clock_counter,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.mem.peek(addr)
+    }
+
+    /// 次に実行する命令が参照するメモリの実効アドレス。命令の境界で呼び出すこと。
+    /// JMP, JSR ではジャンプ先を返す。メモリを参照しない命令と未定義命令では None。
+    pub fn effective_addr(&self) -> Option<u16> {
+        let pc = self.regs.pc;
+        let inst = INSTRUCTION_SET[self.peek(pc) as usize]?;
+        let op_1 = self.peek(pc.wrapping_add(1));
+        let abs = make_addr(self.peek(pc.wrapping_add(2)), op_1);
+        let addr = match inst.addr_mode {
+            AddrMode::ZeroPage => op_1 as u16,
+            AddrMode::IndexedZeroPageX => op_1.wrapping_add(self.regs.x) as u16,
+            AddrMode::IndexedZeroPageY => op_1.wrapping_add(self.regs.y) as u16,
+            AddrMode::Absolute => abs,
+            AddrMode::IndexedAbsoluteX => abs.wrapping_add(self.regs.x as u16),
+            AddrMode::IndexedAbsoluteY => abs.wrapping_add(self.regs.y as u16),
+            AddrMode::IndexedIndirectX => {
+                let ptr = op_1.wrapping_add(self.regs.x);
+                make_addr(self.peek(ptr.wrapping_add(1) as u16), self.peek(ptr as u16))
+            },
+            AddrMode::IndirectIndexedY => {
+                let base = make_addr(self.peek(op_1.wrapping_add(1) as u16), self.peek(op_1 as u16));
+                base.wrapping_add(self.regs.y as u16)
+            },
+            AddrMode::Indirect => {
+                let high_addr = (abs & 0xFF00) | (abs.wrapping_add(1) & 0x00FF);
+                make_addr(self.peek(high_addr), self.peek(abs))
+            },
+            _ => return None,
+        };
+        Some(addr)
+    }
+
+    /// 実効アドレスと、そこに格納されている値の注釈付きでオペランドを書式化する。
+    fn annotated_operand(&self, inst: &Instruction, bytes: &[u8], labels: &dyn Labels) -> String {
+        let op_1 = bytes.get(1).copied().unwrap_or(0);
+        let op_2 = bytes.get(2).copied().unwrap_or(0);
+        let abs = make_addr(op_2, op_1);
+        let zp_text = disasm::zero_page_operand(op_1, labels);
+        let abs_text = disasm::absolute_operand(abs, labels);
+        // JMP, JSRはメモリの値を参照しないので注釈を付けない
+        let is_jump = inst.core_name == "JMP" || inst.core_name == "JSR";
+
+        match inst.addr_mode {
+            AddrMode::ZeroPage => format!("{} = {:02X}", zp_text, self.peek(op_1 as u16)),
+            AddrMode::IndexedZeroPageX => {
+                let addr = op_1.wrapping_add(self.regs.x);
+                format!("{},X @ {:02X} = {:02X}", zp_text, addr, self.peek(addr as u16))
+            },
+            AddrMode::IndexedZeroPageY => {
+                let addr = op_1.wrapping_add(self.regs.y);
+                format!("{},Y @ {:02X} = {:02X}", zp_text, addr, self.peek(addr as u16))
+            },
+            AddrMode::Absolute if !is_jump => format!("{} = {:02X}", abs_text, self.peek(abs)),
+            AddrMode::IndexedAbsoluteX => {
+                let addr = abs.wrapping_add(self.regs.x as u16);
+                format!("{},X @ {:04X} = {:02X}", abs_text, addr, self.peek(addr))
+            },
+            AddrMode::IndexedAbsoluteY => {
+                let addr = abs.wrapping_add(self.regs.y as u16);
+                format!("{},Y @ {:04X} = {:02X}", abs_text, addr, self.peek(addr))
+            },
+            AddrMode::IndexedIndirectX => {
+                let ptr = op_1.wrapping_add(self.regs.x);
+                let addr = make_addr(
+                    self.peek(ptr.wrapping_add(1) as u16),
+                    self.peek(ptr as u16));
+                format!("({},X) @ {:02X} = {:04X} = {:02X}", zp_text, ptr, addr, self.peek(addr))
+            },
+            AddrMode::IndirectIndexedY => {
+                let base = make_addr(
+                    self.peek(op_1.wrapping_add(1) as u16),
+                    self.peek(op_1 as u16));
+                let addr = base.wrapping_add(self.regs.y as u16);
+                format!("({}),Y = {:04X} @ {:04X} = {:02X}", zp_text, base, addr, self.peek(addr))
+            },
+            AddrMode::Indirect => {
+                // 6502のバグにより、上位アドレスはページをまたがずに読まれる。
+                let high_addr = (abs & 0xFF00) | (abs.wrapping_add(1) & 0x00FF);
+                let addr = make_addr(self.peek(high_addr), self.peek(abs));
+                format!("({}) = {:04X}", abs_text, addr)
+            },
+            // 注釈のないオペランドは、逆アセンブラと同じ書式。
+            addr_mode => disasm::format_operand(addr_mode, self.regs.pc, bytes, labels),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! CPUとメモリ空間を繋ぐバス。
+
+/// CPUからメモリ空間へアクセスする唯一の経路。
+/// NESでは mem::MemCon が実装するが、NSFの再生やテスト、NES以外の6502マシンなど、
+/// 用途に合わせて差し替えることができる。
+pub trait CpuBus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    /// 副作用なしでメモリを読む。デバッグ用途。
+    fn peek(&self, addr: u16) -> u8;
+    /// 副作用なしでメモリを書き換える。デバッガーでのメモリの編集用。
+    /// 既定では何もしない(書き換えられない)。書き込みに副作用の無いバスだけが実装すること。
+    fn poke(&mut self, _addr: u16, _data: u8) {}
+    /// addr に現在マップされているPRG-ROMの、先頭からのオフセット。
+    /// バンク切り替えを考慮したラベルの表示に使う。PRG-ROMがマップされていなければ None。
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /// CPUが1クロック進むたびに、そのクロックのメモリアクセスの後で呼ばれる。
+    /// バスに繋がったデバイスをCPUと同期させる場合に使う。
+    fn tick(&mut self) {}
+}
+
+/// ミラー領域もI/Oも無い、64KBのRAMだけが繋がったバス。
+pub struct FlatBus {
+    ram: Box<[u8]>,
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            ram: vec![0; 0x10000].into_boxed_slice(),
+        }
+    }
+
+    /// addr 以降にデータを書き込む。アドレス空間の末尾を超えた分は $0000 に折り返す。
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.ram[(addr as usize + i) & 0xFFFF] = byte;
+        }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpuBus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+    }
+}
@@ -0,0 +1,422 @@
+//! デバッガー。
+//!
+//! ブレークポイント(条件付きも可)、メモリのウォッチポイント、各種ステップ実行、
+//! レジスタとメモリの編集を提供する。シンボルファイル(symbols モジュール)のラベルも扱える。
+//! ブレークポイントやウォッチポイントで止まった場合は、トレースログ(tracer モジュール)の
+//! リングバッファを書き出す。ROMの解析用に、コード/データロガー(cdl モジュール)と
+//! サブルーチン単位のサイクルプロファイラー(profiler モジュール)も提供する。
+//! メモリの16進ダンプ(memory モジュール)は、実行を止めるたびに表示し直して変化を示せる。
+//! ゲームの変数を探すためのRAMサーチ(ram_search モジュール)も提供する。
+//! レジスタへの書き込みをPPUの位置と共に記録するイベントビューアー(events モジュール)もある。
+//! フロントエンドには依存せず、
+//! コマンド(command モジュール)を介して fami-headless の REPL から、
+//! または GDB のリモートプロトコル(gdb モジュール)を介して GDB 互換のフロントエンドから操作する。
+
+pub mod watch;
+pub mod command;
+pub mod gdb;
+pub mod symbols;
+pub mod tracer;
+pub mod cdl;
+pub mod profiler;
+pub mod memory;
+pub mod ram_search;
+pub mod events;
+
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::nes::console::Console;
+use crate::nes::cpu::Registers;
+use self::watch::{Access, MemWatch, WatchHit, Watchpoint};
+use self::memory::{HexView, MemorySpace};
+use self::ram_search::RamSearch;
+
+/// JSRのオペコード
+const OPCODE_JSR: u8 = 0x20;
+/// RTSのオペコード
+const OPCODE_RTS: u8 = 0x60;
+/// RTIのオペコード
+const OPCODE_RTI: u8 = 0x40;
+
+/// 条件付きブレークポイントで参照するレジスタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+}
+
+impl Register {
+    /// "a", "x", "y", "s"(または "sp"), "p", "pc" から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Register::A),
+            "x" => Some(Register::X),
+            "y" => Some(Register::Y),
+            "s" | "sp" => Some(Register::S),
+            "p" => Some(Register::P),
+            "pc" => Some(Register::Pc),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Register::A => "a",
+            Register::X => "x",
+            Register::Y => "y",
+            Register::S => "s",
+            Register::P => "p",
+            Register::Pc => "pc",
+        }
+    }
+
+    fn value(self, regs: &Registers) -> u16 {
+        match self {
+            Register::A => regs.a as u16,
+            Register::X => regs.x as u16,
+            Register::Y => regs.y as u16,
+            Register::S => regs.s as u16,
+            Register::P => regs.p as u16,
+            Register::Pc => regs.pc,
+        }
+    }
+}
+
+/// 比較演算子
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    /// 2文字の演算子を先に判定すること。
+    const SYMBOLS: [(&'static str, CompareOp); 6] = [
+        ("==", CompareOp::Eq), ("!=", CompareOp::Ne), ("<=", CompareOp::Le),
+        (">=", CompareOp::Ge), ("<", CompareOp::Lt), (">", CompareOp::Gt),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Self::SYMBOLS.iter().find(|(_, op)| *op == self).unwrap().0
+    }
+
+    fn eval<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            CompareOp::Eq => lhs == rhs,
+            CompareOp::Ne => lhs != rhs,
+            CompareOp::Lt => lhs < rhs,
+            CompareOp::Le => lhs <= rhs,
+            CompareOp::Gt => lhs > rhs,
+            CompareOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// ブレークポイントの条件。全ての項が成り立つ場合に停止する。
+/// 書式は "a==$10 && x!=0" のように、レジスタと値の比較を && で繋げたもの。
+/// 値は16進数($は省略可)。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    terms: Vec<(Register, CompareOp, u16)>,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Self> {
+        let terms = text.split("&&")
+            .map(|term| {
+                let term = term.trim();
+                let (pos, symbol, op) = CompareOp::SYMBOLS.iter()
+                    .find_map(|&(symbol, op)| term.find(symbol).map(|pos| (pos, symbol, op)))?;
+                let reg = Register::from_name(term[..pos].trim())?;
+                let value = parse_hex(term[pos + symbol.len()..].trim())?;
+                Some((reg, op, value))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Condition { terms })
+    }
+
+    pub fn eval(&self, regs: &Registers) -> bool {
+        self.terms.iter().all(|&(reg, op, value)| op.eval(reg.value(regs), value))
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self.terms.iter()
+            .map(|(reg, op, value)| format!("{}{}${:X}", reg.name(), op.symbol(), value))
+            .collect();
+        write!(f, "{}", terms.join(" && "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+/// 実行が止まった理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// ステップ実行が完了した。
+    Step,
+    /// ブレークポイントに到達した(次に実行する命令の直前で止まる)。
+    Breakpoint(usize),
+    /// ウォッチポイントに該当するアクセスがあった(アクセスした命令の直後で止まる)。
+    /// 実行のウォッチポイントの場合は、その命令の直前で止まる。
+    Watchpoint(WatchHit),
+    /// 指定したスキャンラインに到達した。
+    Scanline(u16),
+    /// 指定したフレーム数を実行しても止まらなかった。
+    FrameLimit,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step"),
+            StopReason::Breakpoint(id) => write!(f, "breakpoint #{}", id),
+            StopReason::Watchpoint(hit) => {
+                let kind = if hit.access == Access::READ { "read" }
+                    else if hit.access == Access::WRITE { "write" }
+                    else { "execute" };
+                write!(f, "watchpoint #{}: {} ${:04X} = ${:02X}", hit.id, kind, hit.addr, hit.data)
+            },
+            StopReason::Scanline(line) => write!(f, "scanline {}", line),
+            StopReason::FrameLimit => write!(f, "frame limit"),
+        }
+    }
+}
+
+/// 実行を止めるたびに表示するメモリの16進ダンプ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDisplay {
+    pub id: usize,
+    pub view: HexView,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    displays: Vec<MemoryDisplay>,
+    /// 実行中のRAMサーチ
+    ram_search: Option<RamSearch>,
+    /// Console と共有する、ウォッチポイントの一覧と該当したアクセスの記録
+    watch: Rc<RefCell<MemWatch>>,
+    /// ブレークポイントとウォッチポイントに共通の、次に割り当てるID
+    next_id: usize,
+}
+
+impl Debugger {
+    /// Console に接続したデバッガーを作る。
+    pub fn new(console: &Console) -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            displays: Vec::new(),
+            ram_search: None,
+            watch: Rc::clone(console.mem_watch()),
+            next_id: 1,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watch.borrow().watchpoints().to_vec()
+    }
+
+    /// PCのブレークポイントを追加し、IDを返す。
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Condition>) -> usize {
+        let id = self.new_id();
+        self.breakpoints.push(Breakpoint { id, addr, condition });
+        id
+    }
+
+    /// アドレス範囲へのアクセスのウォッチポイントを追加し、IDを返す。
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access) -> usize {
+        let id = self.new_id();
+        self.watch.borrow_mut().add(Watchpoint { id, range, access });
+        id
+    }
+
+    pub fn displays(&self) -> &[MemoryDisplay] {
+        &self.displays
+    }
+
+    /// 実行を止めるたびに表示するメモリの範囲を追加し、IDを返す。
+    pub fn add_display(&mut self, space: MemorySpace, start: u16, len: u16) -> usize {
+        let id = self.new_id();
+        self.displays.push(MemoryDisplay { id, view: HexView::new(space, start, len) });
+        id
+    }
+
+    /// 全ての表示を作り直す。前回の表示から変化したバイトには印が付く。
+    pub fn render_displays(&mut self, console: &Console) -> String {
+        let views: Vec<String> = self.displays.iter_mut()
+            .map(|display| {
+                let dump = display.view.render(console);
+                format!("#{} {}:{:04X}\n{}", display.id, display.view.space.name(), display.view.start, dump)
+            })
+            .collect();
+        views.join("\n")
+    }
+
+    /// ブレークポイント、ウォッチポイント、表示のいずれかを削除する。削除できたらtrue。
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len() + self.displays.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.displays.retain(|d| d.id != id);
+        self.breakpoints.len() + self.displays.len() != len || self.watch.borrow_mut().remove(id)
+    }
+
+    pub fn ram_search(&self) -> Option<&RamSearch> {
+        self.ram_search.as_ref()
+    }
+
+    pub fn ram_search_mut(&mut self) -> Option<&mut RamSearch> {
+        self.ram_search.as_mut()
+    }
+
+    /// RAMサーチを設定する。None なら終了する。
+    pub fn set_ram_search(&mut self, search: Option<RamSearch>) {
+        self.ram_search = search;
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// 1命令実行する。
+    pub fn step(&mut self, console: &mut Console) -> StopReason {
+        self.run_until(console, None, |_, _, _| false)
+            .unwrap_or(StopReason::Step)
+    }
+
+    /// 1命令実行する。JSRの場合は、サブルーチンから戻るまで実行する。
+    pub fn step_over(&mut self, console: &mut Console, max_frames: u64) -> StopReason {
+        let regs = console.cpu().regs();
+        if console.peek(regs.pc) != OPCODE_JSR {
+            return self.step(console);
+        }
+        let (ret, s) = (regs.pc.wrapping_add(3), regs.s);
+        // 再帰呼び出しの中で同じアドレスに戻った場合は止まらないよう、スタックの深さも見る。
+        self.run_until(console, Some(max_frames), |console, _, _| {
+            let regs = console.cpu().regs();
+            regs.pc == ret && regs.s >= s
+        }).unwrap_or(StopReason::Step)
+    }
+
+    /// 現在のサブルーチン(または割り込みハンドラ)から戻るまで実行する。
+    pub fn step_out(&mut self, console: &mut Console, max_frames: u64) -> StopReason {
+        let s = console.cpu().regs().s;
+        self.run_until(console, Some(max_frames), |console, opcode, _| {
+            (opcode == OPCODE_RTS || opcode == OPCODE_RTI) && console.cpu().regs().s > s
+        }).unwrap_or(StopReason::Step)
+    }
+
+    /// 指定したスキャンラインに入るまで実行する。
+    pub fn run_to_scanline(&mut self, console: &mut Console, line: u16, max_frames: u64) -> StopReason {
+        self.run_until(console, Some(max_frames), |console, _, prev_line| {
+            let (scanline, _) = console.ppu_position();
+            scanline == line && prev_line != line
+        }).unwrap_or(StopReason::Scanline(line))
+    }
+
+    /// ブレークポイントかウォッチポイントで止まるまで実行する。
+    pub fn run(&mut self, console: &mut Console, max_frames: u64) -> StopReason {
+        self.run_until(console, Some(max_frames), |_, _, _| false)
+            .unwrap_or(StopReason::FrameLimit)
+    }
+
+    /// 1命令ずつ実行し、done が true を返したら None を返す。
+    /// ブレークポイントやウォッチポイントで止まった場合や、
+    /// フレーム数の上限に達した場合はその理由を返す。
+    /// max_frames が None の場合は、1命令だけ実行する。
+    /// done には、実行後の Console、実行した命令のオペコード、実行前のスキャンラインが渡される。
+    fn run_until<F>(&mut self, console: &mut Console, max_frames: Option<u64>, mut done: F) -> Option<StopReason>
+        where F: FnMut(&Console, u8, u16) -> bool
+    {
+        let limit = max_frames.map(|frames| console.frame_count() + frames);
+        // デバッガーの外で実行していた間の記録は捨てる。
+        self.watch.borrow_mut().take_hit();
+        loop {
+            let opcode = console.peek(console.cpu().regs().pc);
+            let (prev_line, _) = console.ppu_position();
+            console.step_instruction();
+
+            // 実行のウォッチポイントは、次に実行する命令のアドレスで判定する。
+            let pc = console.cpu().regs().pc;
+            let hit = {
+                let mut watch = self.watch.borrow_mut();
+                watch.record(pc, Access::EXECUTE, console.peek(pc));
+                watch.take_hit()
+            };
+            if let Some(hit) = hit {
+                dump_trace(console);
+                return Some(StopReason::Watchpoint(hit));
+            }
+            if done(console, opcode, prev_line) {
+                return None;
+            }
+            let limit = limit?;
+            if let Some(id) = self.breakpoint_at(console) {
+                dump_trace(console);
+                return Some(StopReason::Breakpoint(id));
+            }
+            if console.frame_count() >= limit {
+                return Some(StopReason::FrameLimit);
+            }
+        }
+    }
+
+    /// 現在のPCで成立しているブレークポイントのID。
+    fn breakpoint_at(&self, console: &Console) -> Option<usize> {
+        let regs = console.cpu().regs();
+        self.breakpoints.iter()
+            .find(|b| b.addr == regs.pc && b.condition.as_ref().is_none_or(|c| c.eval(regs)))
+            .map(|b| b.id)
+    }
+
+    /// レジスタを書き換える。PCを書き換えた場合は、実行途中の命令は破棄される。
+    /// 8bitのレジスタでは value の下位8bitだけを使うので、範囲は呼び出し側で確認すること。
+    pub fn set_register(&mut self, console: &mut Console, reg: Register, value: u16) {
+        let cpu = console.cpu_mut();
+        match reg {
+            Register::A => cpu.regs_mut().a = value as u8,
+            Register::X => cpu.regs_mut().x = value as u8,
+            Register::Y => cpu.regs_mut().y = value as u8,
+            Register::S => cpu.regs_mut().s = value as u8,
+            Register::P => cpu.regs_mut().p = value as u8,
+            Register::Pc => cpu.jump_to(value),
+        }
+    }
+}
+
+/// トレースログがリングバッファのモードなら、直前の実行履歴を書き出す。
+fn dump_trace(console: &mut Console) {
+    if let Some(tracer) = console.tracer_mut() {
+        tracer.dump_ring();
+    }
+}
+
+/// 16進数("C000", "$C000", "0xC000")を解析する。
+pub fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
@@ -0,0 +1,371 @@
+//! コード/データロガー(CDL)。
+//!
+//! PRG-ROMとCHR-ROMの1バイトごとに、どのように使われたか(オペコードとして実行された、
+//! オペランドとして実行された、データとして読まれた、DMCで読まれた、
+//! BGやスプライトのタイルとして描画された)を記録し、FCEUX互換の .cdl 形式で書き出す。
+//! 逆アセンブル時のコードとデータの判別や、ROM内の未使用領域の調査に使う。
+//!
+//! .cdl の形式(PRG-ROMの各バイト、続いてCHR-ROMの各バイトに1バイトずつ):
+//! https://fceux.com/web/help/CodeDataLogger.html
+//! ```text
+//! PRG-ROM: xPdcAADC
+//!   C  = コードとして実行された
+//!   D  = データとして読まれた
+//!   AA = アクセス時に割り当てられていた8KBの窓 (0 = $8000, 1 = $A000, 2 = $C000, 3 = $E000)
+//!   c  = 間接ジャンプ(JMP (ind))の飛び先として実行された
+//!   d  = 間接アドレッシング((zp,X), (zp),Y)でデータとして読まれた
+//!   P  = DMCのサンプルとして読まれた
+//! CHR-ROM: xxxxxxRD
+//!   D  = 描画された
+//!   R  = PPUDATA($2007)から読まれた
+//! ```
+//! オペコードとオペランド、BGとスプライトの区別は .cdl には無いが、内部では区別して記録する。
+//!
+//! 現状の制限:
+//! * APUが未実装のため、DMCによる読み込みは記録されない(log_dmc_read は用意してある)。
+//! * PPUの描画はまだタイルを1つずつフェッチしないため、描画されたタイルは
+//!   フレームの先頭でのPPUCTRL、PPUMASK、スクロール位置、ネームテーブル、OAMから求める。
+//!   フレームの途中でのスクロールやパターンテーブルの切り替えは反映されない。
+
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use bitflags::bitflags;
+
+use crate::nes::console::Console;
+use crate::nes::cpu::disasm;
+use crate::nes::cpu::instruction::{AddrMode, INSTRUCTION_SET};
+use crate::nes::ppu::Ppu;
+use crate::nes::rom::NesRom;
+use crate::nes::util::make_addr;
+
+bitflags! {
+    /// PRG-ROMの1バイトの使われ方
+    #[derive(Default)]
+    pub struct PrgAccess: u8 {
+        /// 命令のオペコードとして実行された。
+        const OPCODE        = 0b0000_0001;
+        /// 命令のオペランドとして実行された。
+        const OPERAND       = 0b0000_0010;
+        /// データとして読まれた。
+        const DATA          = 0b0000_0100;
+        /// 間接ジャンプの飛び先として実行された。
+        const INDIRECT_CODE = 0b0000_1000;
+        /// 間接アドレッシングでデータとして読まれた。
+        const INDIRECT_DATA = 0b0001_0000;
+        /// DMCのサンプルとして読まれた。
+        const DMC           = 0b0010_0000;
+        const CODE = Self::OPCODE.bits | Self::OPERAND.bits;
+    }
+}
+
+bitflags! {
+    /// CHR-ROMの1バイトの使われ方
+    #[derive(Default)]
+    pub struct ChrAccess: u8 {
+        /// BGのタイルとして描画された。
+        const BACKGROUND = 0b0000_0001;
+        /// スプライトのタイルとして描画された。
+        const SPRITE     = 0b0000_0010;
+        /// PPUDATA($2007)から読まれた。
+        const READ       = 0b0000_0100;
+        const RENDERED = Self::BACKGROUND.bits | Self::SPRITE.bits;
+    }
+}
+
+// .cdl のビット
+const CDL_PRG_CODE: u8 = 0x01;
+const CDL_PRG_DATA: u8 = 0x02;
+const CDL_PRG_WINDOW_SHIFT: u8 = 2;
+const CDL_PRG_INDIRECT_CODE: u8 = 0x10;
+const CDL_PRG_INDIRECT_DATA: u8 = 0x20;
+const CDL_PRG_PCM: u8 = 0x40;
+const CDL_CHR_RENDERED: u8 = 0x01;
+const CDL_CHR_READ: u8 = 0x02;
+
+/// BRKのオペコード
+const OPCODE_BRK: u8 = 0x00;
+/// 割り込みベクタ (NMI, RESET, IRQ/BRK)
+const VECTORS: [u16; 3] = [0xFFFA, 0xFFFC, 0xFFFE];
+const VECTOR_IRQ: u16 = 0xFFFE;
+/// パターンテーブルの1タイルのバイト数
+const TILE_SIZE: usize = 16;
+/// PPUCTRL: スプライトのパターンテーブル($1000)
+const CTRL_SPRITE_TABLE: u8 = 0x08;
+/// PPUCTRL: BGのパターンテーブル($1000)
+const CTRL_BG_TABLE: u8 = 0x10;
+/// PPUCTRL: 8x16のスプライト
+const CTRL_SPRITE_8X16: u8 = 0x20;
+/// PPUMASK: BGを表示
+const MASK_SHOW_BG: u8 = 0x08;
+/// PPUMASK: スプライトを表示
+const MASK_SHOW_SPRITE: u8 = 0x10;
+/// OAMのY座標がこれ以上のスプライトは画面に出ない。
+const SPRITE_Y_HIDDEN: u8 = 0xEF;
+
+pub struct CodeDataLogger {
+    prg: Vec<PrgAccess>,
+    /// PRG-ROMの各バイトに最後にアクセスしたときの8KBの窓 (0-3)
+    prg_window: Vec<u8>,
+    chr: Vec<ChrAccess>,
+    /// JMP (ind) の飛び先。次の命令がここから始まれば間接ジャンプのコードとして記録する。
+    indirect_target: Option<u16>,
+}
+
+impl CodeDataLogger {
+    /// rom のPRG-ROMとCHR-ROMの大きさで、何も記録していないロガーを作る。
+    /// CHR-RAMのROMでは、CHRは記録しない。
+    pub fn new(rom: &NesRom) -> Self {
+        Self::with_sizes(rom.prg_rom().len(), rom.chr_rom().len())
+    }
+
+    pub fn with_sizes(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLogger {
+            prg: vec![PrgAccess::empty(); prg_len],
+            prg_window: vec![0; prg_len],
+            chr: vec![ChrAccess::empty(); chr_len],
+            indirect_target: None,
+        }
+    }
+
+    /// PRG-ROMの各バイトの使われ方
+    pub fn prg(&self) -> &[PrgAccess] {
+        &self.prg
+    }
+
+    /// CHR-ROMの各バイトの使われ方
+    pub fn chr(&self) -> &[ChrAccess] {
+        &self.chr
+    }
+
+    /// 記録を消去する。
+    pub fn clear(&mut self) {
+        self.prg.fill(PrgAccess::empty());
+        self.prg_window.fill(0);
+        self.chr.fill(ChrAccess::empty());
+        self.indirect_target = None;
+    }
+
+    /// 次に実行する命令を記録する。命令の境界で呼び出すこと。
+    /// 命令のバイト、命令が読むデータ、PPUDATAから読まれるCHR-ROMを記録する。
+    pub fn log_instruction(&mut self, console: &Console) {
+        let cpu = console.cpu();
+        let pc = cpu.regs().pc;
+        let opcode = console.peek(pc);
+
+        self.log_prg(console, pc, PrgAccess::OPCODE);
+        if self.indirect_target.take() == Some(pc) {
+            self.log_prg(console, pc, PrgAccess::INDIRECT_CODE);
+        }
+        let len = disasm::instruction_len(opcode);
+        for i in 1..len {
+            self.log_prg(console, pc.wrapping_add(i), PrgAccess::OPERAND);
+        }
+
+        // 割り込みの処理ルーチンの先頭に来たなら、そのベクタが読まれている。
+        for vector in VECTORS {
+            if make_addr(console.peek(vector + 1), console.peek(vector)) == pc {
+                self.log_vector(console, vector);
+            }
+        }
+        if opcode == OPCODE_BRK {
+            self.log_vector(console, VECTOR_IRQ);
+        }
+
+        let inst = match INSTRUCTION_SET[opcode as usize] {
+            Some(inst) => inst,
+            None => return,
+        };
+        let addr = match cpu.effective_addr() {
+            Some(addr) => addr,
+            None => return,
+        };
+        match inst.core_name {
+            "JSR" => (),
+            "JMP" if inst.addr_mode == AddrMode::Indirect => {
+                // ポインタはデータとして読まれる(上位バイトはページをまたがない)。
+                let ptr = make_addr(console.peek(pc.wrapping_add(2)), console.peek(pc.wrapping_add(1)));
+                self.log_prg(console, ptr, PrgAccess::DATA);
+                self.log_prg(console, (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF), PrgAccess::DATA);
+                self.indirect_target = Some(addr);
+            },
+            "JMP" => (),
+            // 書き込みだけの命令
+            "STA" | "STX" | "STY" => (),
+            _ => {
+                let indirect = matches!(inst.addr_mode, AddrMode::IndexedIndirectX | AddrMode::IndirectIndexedY);
+                let access = if indirect { PrgAccess::DATA | PrgAccess::INDIRECT_DATA } else { PrgAccess::DATA };
+                self.log_prg(console, addr, access);
+                if (0x2000..0x4000).contains(&addr) && addr & 0x0007 == 0x0007 {
+                    let vram_addr = console.ppu().borrow().vram_addr() & 0x3FFF;
+                    if vram_addr < 0x2000 {
+                        self.log_chr(vram_addr, ChrAccess::READ);
+                    }
+                }
+            },
+        }
+    }
+
+    /// DMCがサンプルを読んだアドレスを記録する。
+    pub fn log_dmc_read(&mut self, console: &Console, addr: u16) {
+        self.log_prg(console, addr, PrgAccess::DMC);
+    }
+
+    /// これから描画するフレームで表示されるタイルを記録する。フレームの先頭で呼び出すこと。
+    pub fn log_frame(&mut self, ppu: &Ppu) {
+        let ctrl = ppu.ctrl();
+        let mask = ppu.mask();
+        if mask & MASK_SHOW_BG != 0 {
+            let table = if ctrl & CTRL_BG_TABLE != 0 { 0x1000 } else { 0x0000 };
+            let (t, fine_x) = ppu.scroll_origin();
+            let fine_y = (t >> 12) & 0x07;
+            // 4画面分のネームテーブルを 64x60 タイルの平面とみなし、画面に入る範囲を求める。
+            let left = ((t >> 10) & 0x01) * 32 + (t & 0x1F);
+            let top = ((t >> 11) & 0x01) * 30 + ((t >> 5) & 0x1F).min(29);
+            let columns = if fine_x != 0 { 33 } else { 32 };
+            let rows = if fine_y != 0 { 31 } else { 30 };
+            for row in 0..rows {
+                let y = (top + row) % 60;
+                for column in 0..columns {
+                    let x = (left + column) % 64;
+                    let name_addr = 0x2000 + (y / 30) * 0x800 + (x / 32) * 0x400 + (y % 30) * 32 + x % 32;
+                    let tile = ppu.vram().peek(name_addr) as u16;
+                    self.log_tile(table + tile * TILE_SIZE as u16, ChrAccess::BACKGROUND);
+                }
+            }
+        }
+        if mask & MASK_SHOW_SPRITE != 0 {
+            for sprite in ppu.spr_ram().chunks(4) {
+                if sprite[0] >= SPRITE_Y_HIDDEN {
+                    continue;
+                }
+                let tile = sprite[1] as u16;
+                if ctrl & CTRL_SPRITE_8X16 != 0 {
+                    let table = (tile & 0x01) * 0x1000;
+                    let top = table + (tile & 0xFE) * TILE_SIZE as u16;
+                    self.log_tile(top, ChrAccess::SPRITE);
+                    self.log_tile(top + TILE_SIZE as u16, ChrAccess::SPRITE);
+                } else {
+                    let table = if ctrl & CTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0x0000 };
+                    self.log_tile(table + tile * TILE_SIZE as u16, ChrAccess::SPRITE);
+                }
+            }
+        }
+    }
+
+    /// FCEUX互換の .cdl 形式に変換する。
+    pub fn to_fceux(&self) -> Vec<u8> {
+        let prg = self.prg.iter().zip(&self.prg_window).map(|(&access, &window)| {
+            let mut bits = 0;
+            if access.intersects(PrgAccess::CODE) {
+                bits |= CDL_PRG_CODE;
+            }
+            if access.contains(PrgAccess::DATA) {
+                bits |= CDL_PRG_DATA;
+            }
+            if bits != 0 {
+                bits |= window << CDL_PRG_WINDOW_SHIFT;
+            }
+            if access.contains(PrgAccess::INDIRECT_CODE) {
+                bits |= CDL_PRG_INDIRECT_CODE;
+            }
+            if access.contains(PrgAccess::INDIRECT_DATA) {
+                bits |= CDL_PRG_INDIRECT_DATA;
+            }
+            if access.contains(PrgAccess::DMC) {
+                bits |= CDL_PRG_PCM;
+            }
+            bits
+        });
+        let chr = self.chr.iter().map(|&access| {
+            let mut bits = 0;
+            if access.intersects(ChrAccess::RENDERED) {
+                bits |= CDL_CHR_RENDERED;
+            }
+            if access.contains(ChrAccess::READ) {
+                bits |= CDL_CHR_READ;
+            }
+            bits
+        });
+        prg.chain(chr).collect()
+    }
+
+    /// FCEUX互換の .cdl 形式でファイルに書き出す。
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&self.to_fceux())?;
+        file.flush()
+    }
+
+    /// 記録した内容の集計。
+    pub fn summary(&self) -> Summary {
+        let count_prg = |flags: PrgAccess| self.prg.iter().filter(|access| access.intersects(flags)).count();
+        let count_chr = |flags: ChrAccess| self.chr.iter().filter(|access| access.intersects(flags)).count();
+        Summary {
+            prg_len: self.prg.len(),
+            code: count_prg(PrgAccess::CODE),
+            data: count_prg(PrgAccess::DATA | PrgAccess::DMC),
+            prg_unused: self.prg.iter().filter(|access| access.is_empty()).count(),
+            chr_len: self.chr.len(),
+            rendered: count_chr(ChrAccess::RENDERED),
+            chr_read: count_chr(ChrAccess::READ),
+            chr_unused: self.chr.iter().filter(|access| access.is_empty()).count(),
+        }
+    }
+
+    fn log_prg(&mut self, console: &Console, addr: u16, access: PrgAccess) {
+        if let Some(offset) = console.prg_rom_offset(addr) {
+            if let Some(flags) = self.prg.get_mut(offset) {
+                *flags |= access;
+                self.prg_window[offset] = ((addr >> 13) & 0x03) as u8;
+            }
+        }
+    }
+
+    fn log_vector(&mut self, console: &Console, vector: u16) {
+        self.log_prg(console, vector, PrgAccess::DATA);
+        self.log_prg(console, vector + 1, PrgAccess::DATA);
+    }
+
+    /// パターンテーブルのアドレスから始まる1タイルを記録する。
+    fn log_tile(&mut self, addr: u16, access: ChrAccess) {
+        for i in 0..TILE_SIZE as u16 {
+            self.log_chr(addr + i, access);
+        }
+    }
+
+    fn log_chr(&mut self, addr: u16, access: ChrAccess) {
+        // CHRのバンク切り替えは未実装のため、パターンテーブルのアドレスをそのまま使う。
+        if !self.chr.is_empty() {
+            let offset = addr as usize % self.chr.len();
+            self.chr[offset] |= access;
+        }
+    }
+}
+
+/// 記録した内容の集計(バイト数)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub prg_len: usize,
+    pub code: usize,
+    pub data: usize,
+    pub prg_unused: usize,
+    pub chr_len: usize,
+    pub rendered: usize,
+    pub chr_read: usize,
+    pub chr_unused: usize,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PRG-ROM: code {}, data {}, unused {} / {} bytes",
+            self.code, self.data, self.prg_unused, self.prg_len)?;
+        if self.chr_len > 0 {
+            write!(f, "\nCHR-ROM: rendered {}, read {}, unused {} / {} bytes",
+                self.rendered, self.chr_read, self.chr_unused, self.chr_len)?;
+        }
+        Ok(())
+    }
+}
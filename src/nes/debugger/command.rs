@@ -0,0 +1,399 @@
+//! デバッガーのコマンド。REPLの1行を解析して実行し、結果を文字列で返す。
+//!
+//! アドレスと値は16進数($は省略可)、回数・フレーム数・スキャンラインは10進数。
+//! アドレスには、シンボルファイルのラベル(`main`, `main+3` など)も使える。
+//! メモリのコマンドでは、`ppu:3F00` のようにメモリ空間を前に付けると、CPU以外の空間を指定できる。
+
+use std::fmt::Write;
+
+use super::{CompareOp, Condition, Debugger, Register, StopReason, parse_hex};
+use super::watch::Access;
+use super::memory::{HexView, MemorySpace};
+use super::ram_search::{RamSearch, SearchFilter, ValueType};
+use crate::nes::console::Console;
+
+/// 実行系のコマンドで、指定がない場合に実行する最大フレーム数(約60秒)
+pub const DEFAULT_MAX_FRAMES: u64 = 3600;
+/// メモリダンプの既定のバイト数
+const DEFAULT_DUMP_LEN: u16 = 64;
+/// 逆アセンブルの既定の命令数
+const DEFAULT_DISASM_COUNT: usize = 10;
+/// RAMサーチの候補を表示する既定の数
+const DEFAULT_SEARCH_LIST: usize = 20;
+
+pub const HELP: &str = "\
+s|step [n]               execute n instructions (default 1)
+n|next                   step over JSR
+finish|out               run until the current subroutine returns
+c|continue [frames]      run until a breakpoint or watchpoint
+sl|scanline <line> [frames]  run until the PPU enters the scanline
+b|break <addr> [if <cond>]   add a breakpoint (cond: a==$10 && x!=0)
+w|watch <addr>[-<end>] [rwx] add a watchpoint (default: rw)
+d|delete <id>            delete a breakpoint or watchpoint
+l|list                   list breakpoints and watchpoints
+r|regs                   show registers
+set <reg> <value>        set a register (a, x, y, s, p, pc)
+m|mem <mem> [len]        dump memory
+poke <mem> <byte>...     write memory without side effects
+disp|display <mem> [len] dump memory every time execution stops (changed bytes are marked with *)
+sr|search new [type]     start a RAM search (type: u8, s8, u16, s16; default u8)
+sr|search <op> [value]   keep candidates that compare to the value, or to the previous value
+                         (op: ==, !=, <, >, <=, >=, changed, unchanged)
+sr|search [list] [n]     list the first n candidates (default 20)
+u|disasm [addr] [count]  disassemble (default: from PC)
+h|help                   show this help
+q|quit                   quit
+<addr> may also be a label from a symbol file, optionally with a hex offset (main+3).
+<mem> is <addr>, or <space>:<addr> with space one of cpu, ppu, oam, sram (cartridge RAM at $6000).
+Search values are hex and may be negative (-1).";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Next,
+    Finish,
+    Continue(u64),
+    Scanline(u16, u64),
+    Break(u16, Option<Condition>),
+    Watch(u16, u16, Access),
+    Delete(usize),
+    List,
+    Regs,
+    Set(Register, u16),
+    Mem(MemorySpace, u16, u16),
+    Poke(MemorySpace, u16, Vec<u8>),
+    Display(MemorySpace, u16, u16),
+    Search(SearchCommand),
+    Disasm(Option<u16>, usize),
+    Help,
+    Quit,
+}
+
+/// RAMサーチのコマンド
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchCommand {
+    /// 作業用RAMの内容を記録して、サーチを始める。
+    New(ValueType),
+    Filter(SearchFilter),
+    /// 先頭から指定した数の候補を表示する。
+    List(usize),
+}
+
+impl Command {
+    /// 1行のコマンドを解析する。アドレスは16進数だけを受け付ける。
+    pub fn parse(line: &str) -> Result<Command, String> {
+        Self::parse_with(line, &parse_hex)
+    }
+
+    /// 1行のコマンドを解析する。アドレスは resolve_addr で解決する(Console::resolve_addr など)。
+    pub fn parse_with(line: &str, resolve_addr: &dyn Fn(&str) -> Option<u16>) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or_else(|| "empty command".to_string())?;
+        let args: Vec<&str> = words.collect();
+        let hex = |i: usize| -> Result<u16, String> {
+            let arg = args.get(i).ok_or_else(|| format!("{}: missing argument", name))?;
+            parse_hex(arg).ok_or_else(|| format!("{}: invalid hex number: {}", name, arg))
+        };
+        let byte = |i: usize| -> Result<u8, String> {
+            u8::try_from(hex(i)?).map_err(|_| format!("{}: value out of range", name))
+        };
+        let addr = |i: usize| -> Result<u16, String> {
+            let arg = args.get(i).ok_or_else(|| format!("{}: missing argument", name))?;
+            resolve_addr(arg).ok_or_else(|| format!("{}: invalid address or unknown label: {}", name, arg))
+        };
+        // "ppu:3F00" のようなメモリ空間付きのアドレス。空間がなければCPUのアドレス。
+        let mem = |i: usize| -> Result<(MemorySpace, u16), String> {
+            let arg = args.get(i).ok_or_else(|| format!("{}: missing argument", name))?;
+            match arg.split_once(':') {
+                Some((space, addr)) => {
+                    let space = MemorySpace::from_name(space)
+                        .ok_or_else(|| format!("{}: memory space must be one of cpu, ppu, oam, sram", name))?;
+                    let addr = if space == MemorySpace::Cpu { resolve_addr(addr) } else { parse_hex(addr) }
+                        .filter(|&addr| (addr as usize) < space.size())
+                        .ok_or_else(|| format!("{}: invalid {} address: {}", name, space.name(), addr))?;
+                    Ok((space, addr))
+                },
+                None => Ok((MemorySpace::Cpu, addr(i)?)),
+            }
+        };
+        let dec = |i: usize, default: u64| -> Result<u64, String> {
+            match args.get(i) {
+                Some(arg) => arg.parse().map_err(|_| format!("{}: invalid number: {}", name, arg)),
+                None => Ok(default),
+            }
+        };
+
+        let command = match name {
+            "s" | "step" => Command::Step(dec(0, 1)? as usize),
+            "n" | "next" => Command::Next,
+            "finish" | "out" => Command::Finish,
+            "c" | "continue" => Command::Continue(dec(0, DEFAULT_MAX_FRAMES)?),
+            "sl" | "scanline" => {
+                let line = args.first().and_then(|arg| arg.parse().ok())
+                    .ok_or_else(|| format!("{}: requires a scanline", name))?;
+                Command::Scanline(line, dec(1, DEFAULT_MAX_FRAMES)?)
+            },
+            "b" | "break" => {
+                let condition = match args.get(1) {
+                    Some(&"if") => {
+                        let text = args[2..].join(" ");
+                        Some(Condition::parse(&text)
+                            .ok_or_else(|| format!("{}: invalid condition: {}", name, text))?)
+                    },
+                    Some(arg) => return Err(format!("{}: unexpected argument: {}", name, arg)),
+                    None => None,
+                };
+                Command::Break(addr(0)?, condition)
+            },
+            "w" | "watch" => {
+                let range = args.first().ok_or_else(|| format!("{}: missing argument", name))?;
+                // "table-1" のようにラベルからのオフセットとも読めるので、
+                // 範囲として正しく読めなければ1つのアドレスとして扱う。
+                let as_range = range.split_once('-')
+                    .and_then(|(start, end)| Some((resolve_addr(start)?, resolve_addr(end)?)))
+                    .filter(|(start, end)| start <= end);
+                let (start, end) = as_range.or_else(|| resolve_addr(range).map(|addr| (addr, addr)))
+                    .ok_or_else(|| format!("{}: invalid range: {}", name, range))?;
+                let access = match args.get(1) {
+                    Some(arg) => Access::from_name(arg)
+                        .ok_or_else(|| format!("{}: access must be a combination of r, w, x", name))?,
+                    None => Access::READ | Access::WRITE,
+                };
+                Command::Watch(start, end, access)
+            },
+            "d" | "delete" => Command::Delete(dec(0, 0)? as usize),
+            "l" | "list" => Command::List,
+            "r" | "regs" => Command::Regs,
+            "set" => {
+                let reg = args.first().and_then(|arg| Register::from_name(arg))
+                    .ok_or_else(|| format!("{}: register must be one of a, x, y, s, p, pc", name))?;
+                let value = if reg == Register::Pc { hex(1)? } else { byte(1)? as u16 };
+                Command::Set(reg, value)
+            },
+            "m" | "mem" => {
+                let len = dec(1, DEFAULT_DUMP_LEN as u64)?.min(u16::MAX as u64) as u16;
+                let (space, addr) = mem(0)?;
+                Command::Mem(space, addr, len)
+            },
+            "poke" => {
+                let data = (1..args.len().max(2))
+                    .map(byte)
+                    .collect::<Result<Vec<u8>, String>>()?;
+                let (space, addr) = mem(0)?;
+                Command::Poke(space, addr, data)
+            },
+            "disp" | "display" => {
+                let len = dec(1, DEFAULT_DUMP_LEN as u64)?.min(u16::MAX as u64) as u16;
+                let (space, addr) = mem(0)?;
+                Command::Display(space, addr, len)
+            },
+            "sr" | "search" => {
+                let search = match args.first().copied() {
+                    None | Some("list") => SearchCommand::List(dec(1, DEFAULT_SEARCH_LIST as u64)? as usize),
+                    Some("new") => {
+                        let value_type = match args.get(1) {
+                            Some(arg) => ValueType::from_name(arg)
+                                .ok_or_else(|| format!("{}: type must be one of u8, s8, u16, s16", name))?,
+                            None => ValueType::default(),
+                        };
+                        SearchCommand::New(value_type)
+                    },
+                    Some("changed") => SearchCommand::Filter(SearchFilter::Previous(CompareOp::Ne)),
+                    Some("unchanged") => SearchCommand::Filter(SearchFilter::Previous(CompareOp::Eq)),
+                    Some(op) => {
+                        let op = CompareOp::SYMBOLS.iter().find(|(symbol, _)| *symbol == op)
+                            .map(|&(_, op)| op)
+                            .ok_or_else(|| format!("{}: unknown operator: {}", name, op))?;
+                        match args.get(1) {
+                            Some(arg) => {
+                                let value = parse_signed_hex(arg)
+                                    .ok_or_else(|| format!("{}: invalid hex number: {}", name, arg))?;
+                                SearchCommand::Filter(SearchFilter::Value(op, value))
+                            },
+                            None => SearchCommand::Filter(SearchFilter::Previous(op)),
+                        }
+                    },
+                };
+                Command::Search(search)
+            },
+            "u" | "disasm" => {
+                let addr = if args.is_empty() { None } else { Some(addr(0)?) };
+                Command::Disasm(addr, dec(1, DEFAULT_DISASM_COUNT as u64)? as usize)
+            },
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(format!("unknown command: {} (type 'help')", name)),
+        };
+        Ok(command)
+    }
+}
+
+impl Debugger {
+    /// コマンドを実行し、表示する結果を返す。Quit は呼び出し側で処理すること。
+    pub fn execute(&mut self, console: &mut Console, command: &Command) -> String {
+        let mut out = String::new();
+        match *command {
+            Command::Step(count) => {
+                let mut reason = StopReason::Step;
+                for _ in 0..count.max(1) {
+                    reason = self.step(console);
+                    if reason != StopReason::Step {
+                        break;
+                    }
+                }
+                self.write_stop(&mut out, console, &reason);
+            },
+            Command::Next => {
+                let reason = self.step_over(console, DEFAULT_MAX_FRAMES);
+                self.write_stop(&mut out, console, &reason);
+            },
+            Command::Finish => {
+                let reason = self.step_out(console, DEFAULT_MAX_FRAMES);
+                self.write_stop(&mut out, console, &reason);
+            },
+            Command::Continue(frames) => {
+                let reason = self.run(console, frames);
+                self.write_stop(&mut out, console, &reason);
+            },
+            Command::Scanline(line, frames) => {
+                let reason = self.run_to_scanline(console, line, frames);
+                self.write_stop(&mut out, console, &reason);
+            },
+            Command::Break(addr, ref condition) => {
+                let id = self.add_breakpoint(addr, condition.clone());
+                let _ = write!(out, "breakpoint #{} at ${:04X}{}", id, addr, label_suffix(console, addr));
+            },
+            Command::Watch(start, end, access) => {
+                let id = self.add_watchpoint(start..=end, access);
+                let _ = write!(out, "watchpoint #{} at ${:04X}-${:04X} ({})", id, start, end, access_name(access));
+            },
+            Command::Delete(id) => {
+                out = if self.remove(id) { format!("deleted #{}", id) } else { format!("no such id: {}", id) };
+            },
+            Command::Display(space, addr, len) => {
+                let id = self.add_display(space, addr, len);
+                let display = self.displays.last_mut().unwrap();
+                let _ = write!(out, "display #{}\n{}", id, display.view.render(console));
+            },
+            Command::List => {
+                for b in self.breakpoints() {
+                    let _ = write!(out, "#{} break ${:04X}{}", b.id, b.addr, label_suffix(console, b.addr));
+                    if let Some(condition) = &b.condition {
+                        let _ = write!(out, " if {}", condition);
+                    }
+                    out.push('\n');
+                }
+                for w in self.watchpoints() {
+                    let _ = writeln!(out, "#{} watch ${:04X}-${:04X} ({})",
+                        w.id, w.range.start(), w.range.end(), access_name(w.access));
+                }
+                for d in self.displays() {
+                    let _ = writeln!(out, "#{} display {}:{:04X} {}", d.id, d.view.space.name(), d.view.start, d.view.len);
+                }
+                out = out.trim_end().to_string();
+            },
+            Command::Regs => out = console.trace_line().to_string(),
+            Command::Set(reg, value) => {
+                self.set_register(console, reg, value);
+                out = console.trace_line().to_string();
+            },
+            Command::Mem(space, addr, len) => out = HexView::new(space, addr, len).render(console),
+            Command::Poke(space, addr, ref data) => {
+                for (i, &byte) in data.iter().enumerate() {
+                    space.poke(console, addr.wrapping_add(i as u16), byte);
+                }
+                let _ = write!(out, "wrote {} byte(s) at {}${:04X}", data.len(), space_prefix(space), addr);
+            },
+            Command::Search(ref search) => out = self.execute_search(console, search),
+            Command::Disasm(addr, count) => {
+                let mut addr = addr.unwrap_or(console.cpu().regs().pc);
+                for _ in 0..count {
+                    let line = console.disassemble(addr, addr).remove(0);
+                    if let Some(label) = console.label_at(addr) {
+                        let _ = writeln!(out, "{}:", label);
+                    }
+                    let _ = writeln!(out, "{}", line);
+                    addr = addr.wrapping_add(line.bytes.len() as u16);
+                }
+                out = out.trim_end().to_string();
+            },
+            Command::Help => out = HELP.to_string(),
+            Command::Quit => (),
+        }
+        out
+    }
+
+    /// 停止した理由と、次に実行する命令、メモリの表示を書き出す。
+    fn write_stop(&mut self, out: &mut String, console: &Console, reason: &StopReason) {
+        if *reason != StopReason::Step {
+            let _ = writeln!(out, "stopped: {}", reason);
+        }
+        let _ = write!(out, "{}", console.trace_line());
+        if !self.displays.is_empty() {
+            let _ = write!(out, "\n{}", self.render_displays(console));
+        }
+    }
+
+    fn execute_search(&mut self, console: &Console, command: &SearchCommand) -> String {
+        let count = match *command {
+            SearchCommand::New(value_type) => {
+                let search = RamSearch::new(console, value_type);
+                let count = search.candidates().len();
+                self.set_ram_search(Some(search));
+                return format!("{} candidate(s) ({})", count, value_type.name());
+            },
+            SearchCommand::Filter(filter) => match self.ram_search_mut() {
+                Some(search) => search.filter(console, filter),
+                None => return "no search in progress (use 'search new')".to_string(),
+            },
+            SearchCommand::List(count) => count,
+        };
+        let search = match self.ram_search() {
+            Some(search) => search,
+            None => return "no search in progress (use 'search new')".to_string(),
+        };
+        // 絞り込みの後は、候補が少なければそのまま一覧を表示する。
+        let candidates = search.candidates();
+        let list = match command {
+            SearchCommand::Filter(_) if count > DEFAULT_SEARCH_LIST => 0,
+            _ => count,
+        };
+        let mut out = format!("{} candidate(s) ({})", candidates.len(), search.value_type().name());
+        let hex_digits = search.value_type().size() as usize * 2;
+        for candidate in candidates.iter().take(list) {
+            let value = search.value_type().read(console, candidate.addr);
+            let raw = value as u32 & ((1 << (hex_digits * 4)) - 1);
+            let _ = write!(out, "\n${:04X} = {} (${:0width$X}){}",
+                candidate.addr, value, raw, label_suffix(console, candidate.addr), width = hex_digits);
+        }
+        out
+    }
+}
+
+/// CPU以外のメモリ空間なら "ppu:" のような接頭辞を返す。
+fn space_prefix(space: MemorySpace) -> String {
+    match space {
+        MemorySpace::Cpu => String::new(),
+        _ => format!("{}:", space.name()),
+    }
+}
+
+/// 符号付きの16進数("-10", "-$10")を解析する。
+fn parse_signed_hex(text: &str) -> Option<i32> {
+    match text.strip_prefix('-') {
+        Some(digits) => parse_hex(digits).map(|value| -(value as i32)),
+        None => parse_hex(text).map(|value| value as i32),
+    }
+}
+
+/// ラベルがあれば " <label>" を返す。
+fn label_suffix(console: &Console, addr: u16) -> String {
+    console.label_at(addr).map(|label| format!(" <{}>", label)).unwrap_or_default()
+}
+
+fn access_name(access: Access) -> String {
+    [(Access::READ, 'r'), (Access::WRITE, 'w'), (Access::EXECUTE, 'x')].iter()
+        .filter(|(flag, _)| access.contains(*flag))
+        .map(|(_, c)| *c)
+        .collect()
+}
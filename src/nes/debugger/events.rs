@@ -0,0 +1,242 @@
+//! イベントビューアー。
+//!
+//! CPUによるPPUのレジスタ($2000-$2007 とそのミラー)、OAM DMA($4014)、APUのレジスタ、
+//! マッパーのレジスタへの書き込みを、書き込んだ時点のPPUの位置(スキャンライン, ドット)と共に記録する。
+//! 1フレーム分の記録を、341ドット x 全スキャンラインの格子に重ねた画像と、一覧のテキストにできる。
+//! フレームの途中でのスクロールの変更など、ラスター効果のタイミングを確認するのに使う。
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::nes::cpu_bus::CpuBus;
+use crate::nes::ppu::{Ppu, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+/// 格子の幅(1スキャンラインのドット数)
+pub const GRID_WIDTH: usize = 341;
+/// 格子の、画面の外(HBlankとVBlank)の色
+const OFFSCREEN_COLOR: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+/// 格子に描く画面は、イベントが目立つよう暗くする。
+const PICTURE_DIM_SHIFT: u8 = 1;
+
+/// 書き込み先のレジスタの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    PpuCtrl,
+    PpuMask,
+    PpuStatus,
+    OamAddr,
+    OamData,
+    PpuScroll,
+    PpuAddr,
+    PpuData,
+    OamDma,
+    /// APUのレジスタ($4000-$4013, $4015, $4017)
+    Apu,
+    /// マッパーのレジスタ($4020-$5FFF, $8000-$FFFF)
+    Mapper,
+}
+
+impl EventKind {
+    /// 書き込み先のアドレスから判定する。記録の対象でなければ None。
+    pub fn from_addr(addr: u16) -> Option<Self> {
+        const PPU_REGISTERS: [EventKind; 8] = [
+            EventKind::PpuCtrl, EventKind::PpuMask, EventKind::PpuStatus, EventKind::OamAddr,
+            EventKind::OamData, EventKind::PpuScroll, EventKind::PpuAddr, EventKind::PpuData,
+        ];
+        match addr {
+            0x2000..=0x3FFF => Some(PPU_REGISTERS[(addr & 0x0007) as usize]),
+            0x4014 => Some(EventKind::OamDma),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => Some(EventKind::Apu),
+            0x4020..=0x5FFF | 0x8000..=0xFFFF => Some(EventKind::Mapper),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::PpuCtrl => "PPUCTRL",
+            EventKind::PpuMask => "PPUMASK",
+            EventKind::PpuStatus => "PPUSTATUS",
+            EventKind::OamAddr => "OAMADDR",
+            EventKind::OamData => "OAMDATA",
+            EventKind::PpuScroll => "PPUSCROLL",
+            EventKind::PpuAddr => "PPUADDR",
+            EventKind::PpuData => "PPUDATA",
+            EventKind::OamDma => "OAMDMA",
+            EventKind::Apu => "APU",
+            EventKind::Mapper => "MAPPER",
+        }
+    }
+
+    /// 格子に描く色(RGBA)
+    pub fn color(self) -> [u8; 4] {
+        match self {
+            EventKind::PpuCtrl => [0xFF, 0x40, 0x40, 0xFF],
+            EventKind::PpuMask => [0x40, 0xFF, 0x40, 0xFF],
+            EventKind::PpuStatus => [0xC0, 0xC0, 0xC0, 0xFF],
+            EventKind::OamAddr => [0xFF, 0xA0, 0x40, 0xFF],
+            EventKind::OamData => [0xA0, 0x60, 0x20, 0xFF],
+            EventKind::PpuScroll => [0x40, 0xFF, 0xFF, 0xFF],
+            EventKind::PpuAddr => [0x40, 0x80, 0xFF, 0xFF],
+            EventKind::PpuData => [0xA0, 0x60, 0xFF, 0xFF],
+            EventKind::OamDma => [0xFF, 0xFF, 0x40, 0xFF],
+            EventKind::Apu => [0xFF, 0x60, 0xC0, 0xFF],
+            EventKind::Mapper => [0xFF, 0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+/// レジスタへの書き込み
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub addr: u16,
+    pub data: u8,
+    /// 書き込んだ時点のPPUの位置。トレースログの PPU と同じく、次に処理するスキャンラインとドット。
+    pub scanline: u16,
+    pub dot: u16,
+    /// 書き込んだ命令のアドレス
+    pub pc: u16,
+}
+
+/// 書き込みの記録。Console と EventBus で共有する。
+#[derive(Default)]
+pub struct EventLog {
+    enabled: bool,
+    /// 実行中の命令のアドレス
+    pc: u16,
+    /// 描画中のフレームの記録
+    current: Vec<Event>,
+    /// 最後に描画を完了したフレームの記録
+    last_frame: Vec<Event>,
+}
+
+impl EventLog {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 記録するかを設定する。記録をやめると、それまでの記録は捨てる。
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.current.clear();
+            self.last_frame.clear();
+        }
+    }
+
+    /// 最後に描画を完了したフレームの記録
+    pub fn last_frame(&self) -> &[Event] {
+        &self.last_frame
+    }
+
+    /// 描画中のフレームの、これまでの記録
+    pub fn current(&self) -> &[Event] {
+        &self.current
+    }
+
+    /// 命令の実行を始める。命令をフェッチする直前に呼び出すこと。
+    pub fn begin_instruction(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// 書き込みが記録の対象なら記録する。
+    pub fn record(&mut self, addr: u16, data: u8, scanline: u16, dot: u16) {
+        if let Some(kind) = EventKind::from_addr(addr) {
+            self.current.push(Event { kind, addr, data, scanline, dot, pc: self.pc });
+        }
+    }
+
+    /// フレームの区切り。フレームの描画が完了した時点で呼び出すこと。
+    pub fn end_frame(&mut self) {
+        self.last_frame = std::mem::take(&mut self.current);
+    }
+}
+
+/// 記録を一覧にする。1行が1回の書き込み。
+pub fn format_list(events: &[Event]) -> String {
+    let mut out = String::new();
+    for event in events {
+        let _ = writeln!(out, "{:>3},{:>3}  ${:04X} = ${:02X}  {:<9}  PC:${:04X}",
+            event.scanline, event.dot, event.addr, event.data, event.kind.name(), event.pc);
+    }
+    out
+}
+
+/// 記録を、横 GRID_WIDTH ドット、縦 scanlines ラインの格子(RGBA)に描く。
+/// picture (256x240 の RGBA) を渡すと、画面が出力される位置に暗くして敷く。
+/// 書き込みは、その位置を中心とした3x3の点で描く。
+pub fn render_grid(events: &[Event], scanlines: usize, picture: Option<&[u8]>) -> Vec<u8> {
+    let mut rgba: Vec<u8> = OFFSCREEN_COLOR.iter().copied().cycle().take(GRID_WIDTH * scanlines * 4).collect();
+    if let Some(picture) = picture {
+        // ドット1-256で、画面の左端から右端までが出力される。
+        for y in 0..SCREEN_HEIGHT.min(scanlines) {
+            for x in 0..SCREEN_WIDTH {
+                let src = (y * SCREEN_WIDTH + x) * 4;
+                let dst = (y * GRID_WIDTH + x + 1) * 4;
+                for i in 0..3 {
+                    rgba[dst + i] = picture[src + i] >> PICTURE_DIM_SHIFT;
+                }
+            }
+        }
+    }
+    for event in events {
+        let (x, y) = (event.dot as isize, event.scanline as isize);
+        for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+            let (x, y) = (x + dx, y + dy);
+            if (0..GRID_WIDTH as isize).contains(&x) && (0..scanlines as isize).contains(&y) {
+                let dst = (y as usize * GRID_WIDTH + x as usize) * 4;
+                rgba[dst..dst + 4].copy_from_slice(&event.kind.color());
+            }
+        }
+    }
+    rgba
+}
+
+/// CPUとメモリの間に入り、書き込みを EventLog に記録するバス。
+pub struct EventBus {
+    inner: Box<dyn CpuBus>,
+    log: Rc<RefCell<EventLog>>,
+    /// 書き込んだ時点の位置を知るためのPPU
+    ppu: Rc<RefCell<Ppu>>,
+}
+
+impl EventBus {
+    pub fn new(inner: Box<dyn CpuBus>, log: Rc<RefCell<EventLog>>, ppu: Rc<RefCell<Ppu>>) -> Self {
+        EventBus { inner, log, ppu }
+    }
+}
+
+impl CpuBus for EventBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.inner.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        {
+            let mut log = self.log.borrow_mut();
+            if log.enabled {
+                let ppu = self.ppu.borrow();
+                log.record(addr, data, ppu.scanline(), ppu.dot());
+            }
+        }
+        self.inner.write(addr, data);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.inner.poke(addr, data);
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.inner.prg_rom_offset(addr)
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+    }
+}
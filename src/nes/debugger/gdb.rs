@@ -0,0 +1,428 @@
+//! GDBのリモートシリアルプロトコル(RSP)のスタブ。
+//! https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//!
+//! GDB互換のフロントエンドから、6502のレジスタとCPUのメモリ空間を操作できるようにする。
+//! レジスタは A, X, Y, S, P (各1バイト), PC (2バイト、リトルエンディアン) の順。
+//!
+//! 対応するパケット:
+//! `?`, `g`, `G`, `p`, `P`, `m`, `M`, `s`, `c`, `Z0`-`Z4`, `z0`-`z4`, `D`, `k`,
+//! および `qSupported`, `QStartNoAckMode`, `qXfer:features:read` 等の問い合わせ。
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+
+use super::{Debugger, Register, StopReason};
+use super::watch::Access;
+use crate::nes::console::Console;
+
+/// 実行の中断要求(Ctrl-C)
+const INTERRUPT: u8 = 0x03;
+/// SIGTRAP。ブレークポイントやステップ実行で停止した。
+const SIGTRAP: u8 = 5;
+/// SIGINT。中断要求で停止した。
+const SIGINT: u8 = 2;
+/// 中断要求を確認する間隔(フレーム数)
+const FRAMES_PER_POLL: u64 = 1;
+/// 受け付けるパケットの最大サイズ
+const PACKET_SIZE: usize = 0x1000;
+/// 1回のメモリの読み書き(m, M)で扱う最大のバイト数。16進数の文字列にすると PACKET_SIZE に収まる。
+const MAX_MEMORY_LEN: usize = PACKET_SIZE / 2;
+
+/// GDBのレジスタ番号順のレジスタ
+const REGISTERS: [Register; 6] = [Register::A, Register::X, Register::Y, Register::S, Register::P, Register::Pc];
+
+/// レジスタの説明。GDBはこれを見てレジスタの名前とサイズを知る。
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.mos6502.cpu">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8" regnum="1"/>
+    <reg name="y" bitsize="8" regnum="2"/>
+    <reg name="s" bitsize="8" regnum="3"/>
+    <reg name="p" bitsize="8" regnum="4"/>
+    <reg name="pc" bitsize="16" regnum="5" type="code_ptr"/>
+  </feature>
+</target>"#;
+
+/// GDBと通信する経路。
+pub trait Connection: Read + Write {
+    /// 実行中に、GDBから中断要求が届いていればtrue。
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Connection for TcpStream {
+    /// 中断要求(0x03)だけを読み捨てる。それ以外のバイトは、後に続くパケットとして読めるよう残しておく。
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut buf = [0; 1];
+        let result = match self.peek(&mut buf) {
+            Ok(1) if buf[0] == INTERRUPT => self.read(&mut buf).map(|_| true),
+            Ok(1) => Ok(false),
+            // 切断された場合も止めておく。続く読み込みで終了する。
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// 127.0.0.1 の port で待ち受け、GDBからの接続を1つ受け付ける。
+/// 外部から操作されないよう、ループバック以外では待ち受けない。
+pub fn accept(port: u16) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+pub struct GdbStub {
+    debugger: Debugger,
+    /// QStartNoAckMode 以降は、受信の確認応答('+')を送らない。
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new(console: &Console) -> Self {
+        GdbStub {
+            debugger: Debugger::new(console),
+            no_ack: false,
+        }
+    }
+
+    /// 接続が切れるか、GDBがデタッチするまでパケットを処理する。
+    pub fn serve<C: Connection>(&mut self, console: &mut Console, conn: &mut C) -> io::Result<()> {
+        loop {
+            let packet = match read_packet(conn)? {
+                Some(Incoming::Packet(packet)) => packet,
+                Some(Incoming::Interrupt) => {
+                    // 停止中の中断要求には、停止中であることを返す。
+                    write_packet(conn, &format!("S{:02x}", SIGINT))?;
+                    continue;
+                },
+                Some(Incoming::BadChecksum) => {
+                    conn.write_all(b"-")?;
+                    continue;
+                },
+                None => return Ok(()),
+            };
+            if !self.no_ack {
+                conn.write_all(b"+")?;
+            }
+            let mut interrupted = || conn.interrupt_requested().unwrap_or(true);
+            let reply = self.handle(console, &packet, &mut interrupted);
+            match reply {
+                Some(reply) => write_packet(conn, &reply)?,
+                None => return Ok(()),
+            }
+            // デタッチは返答してから切断する。
+            if packet == "D" {
+                return Ok(());
+            }
+        }
+    }
+
+    /// 1つのパケットを処理し、返答を返す。返答せずに接続を終了する場合(k)は None。
+    /// 実行中は interrupted を定期的に呼び、trueが返ったら停止する。
+    pub fn handle(&mut self, console: &mut Console, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self.read_registers(console),
+            "G" => self.write_registers(console, args),
+            "p" => match parse_hex(args) {
+                Some(n) => self.read_register(console, n as usize),
+                None => error(),
+            },
+            "P" => match args.split_once('=') {
+                Some((n, value)) => self.write_register(console, n, value),
+                None => error(),
+            },
+            "m" => self.read_memory(console, args),
+            "M" => self.write_memory(console, args),
+            "s" => {
+                let reason = self.debugger.step(console);
+                self.stop_reply(&reason)
+            },
+            "c" => self.resume(console, interrupted),
+            "Z" => self.insert_point(args),
+            "z" => self.remove_point(args),
+            "H" | "T" | "D" => "OK".to_string(),
+            "k" => return None,
+            "q" | "Q" => self.query(packet),
+            // 未対応のパケットには空の返答を返す。
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn read_registers(&self, console: &Console) -> String {
+        (0..REGISTERS.len()).map(|n| self.read_register(console, n)).collect()
+    }
+
+    fn write_registers(&mut self, console: &mut Console, args: &str) -> String {
+        let bytes = match decode_hex(args) {
+            Some(bytes) if bytes.len() >= 7 => bytes,
+            _ => return error(),
+        };
+        for (reg, &value) in REGISTERS.iter().zip(&bytes[..5]) {
+            self.debugger.set_register(console, *reg, value as u16);
+        }
+        // PCを書き換えると実行途中の命令が破棄されるため、変わった場合のみ書き換える。
+        let pc = u16::from_le_bytes([bytes[5], bytes[6]]);
+        if pc != console.cpu().regs().pc {
+            self.debugger.set_register(console, Register::Pc, pc);
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, console: &Console, n: usize) -> String {
+        let regs = console.cpu().regs();
+        match n {
+            0 => format!("{:02x}", regs.a),
+            1 => format!("{:02x}", regs.x),
+            2 => format!("{:02x}", regs.y),
+            3 => format!("{:02x}", regs.s),
+            4 => format!("{:02x}", regs.p),
+            5 => encode_hex(&regs.pc.to_le_bytes()),
+            _ => error(),
+        }
+    }
+
+    fn write_register(&mut self, console: &mut Console, n: &str, value: &str) -> String {
+        let (reg, bytes) = match (parse_hex(n).and_then(|n| REGISTERS.get(n as usize)), decode_hex(value)) {
+            (Some(&reg), Some(bytes)) if !bytes.is_empty() => (reg, bytes),
+            _ => return error(),
+        };
+        let value = match reg {
+            Register::Pc if bytes.len() >= 2 => u16::from_le_bytes([bytes[0], bytes[1]]),
+            Register::Pc => return error(),
+            _ => bytes[0] as u16,
+        };
+        self.debugger.set_register(console, reg, value);
+        "OK".to_string()
+    }
+
+    /// 長さが MAX_MEMORY_LEN を超える場合は、MAX_MEMORY_LEN バイトだけを返す。
+    fn read_memory(&self, console: &Console, args: &str) -> String {
+        match parse_addr_len(args) {
+            Some((addr, len)) => (0..len.min(MAX_MEMORY_LEN))
+                .map(|i| format!("{:02x}", console.peek(addr.wrapping_add(i as u16))))
+                .collect(),
+            None => error(),
+        }
+    }
+
+    /// 長さが MAX_MEMORY_LEN を超える場合はエラーを返す。
+    fn write_memory(&mut self, console: &mut Console, args: &str) -> String {
+        let parsed = args.split_once(':')
+            .and_then(|(range, data)| Some((parse_addr_len(range)?, decode_hex(data)?)));
+        match parsed {
+            Some(((addr, len), data)) if data.len() == len && len <= MAX_MEMORY_LEN => {
+                for (i, &byte) in data.iter().enumerate() {
+                    console.poke(addr.wrapping_add(i as u16), byte);
+                }
+                "OK".to_string()
+            },
+            _ => error(),
+        }
+    }
+
+    /// 中断要求かブレークポイント等で止まるまで実行する。
+    fn resume(&mut self, console: &mut Console, interrupted: &mut dyn FnMut() -> bool) -> String {
+        loop {
+            let reason = self.debugger.run(console, FRAMES_PER_POLL);
+            if reason != StopReason::FrameLimit {
+                return self.stop_reply(&reason);
+            }
+            if interrupted() {
+                return format!("S{:02x}", SIGINT);
+            }
+        }
+    }
+
+    /// Z0(ブレークポイント), Z2(書き込み), Z3(読み込み), Z4(読み書き)のウォッチポイントを設定する。
+    /// Z1(ハードウェアブレークポイント)はZ0と同じ扱い。
+    fn insert_point(&mut self, args: &str) -> String {
+        match parse_point(args) {
+            Some((None, addr, _)) => {
+                self.debugger.add_breakpoint(addr, None);
+            },
+            Some((Some(access), addr, len)) => {
+                self.debugger.add_watchpoint(watch_range(addr, len), access);
+            },
+            None => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    fn remove_point(&mut self, args: &str) -> String {
+        let (access, addr, len) = match parse_point(args) {
+            Some(point) => point,
+            None => return String::new(),
+        };
+        let id = match access {
+            None => self.debugger.breakpoints().iter()
+                .find(|b| b.addr == addr && b.condition.is_none())
+                .map(|b| b.id),
+            Some(access) => self.debugger.watchpoints().iter()
+                .find(|w| w.access == access && w.range == watch_range(addr, len))
+                .map(|w| w.id),
+        };
+        match id {
+            Some(id) => {
+                self.debugger.remove(id);
+                "OK".to_string()
+            },
+            None => error(),
+        }
+    }
+
+    /// 停止した理由を、GDBの停止応答に変換する。
+    fn stop_reply(&self, reason: &StopReason) -> String {
+        let hit = match reason {
+            StopReason::Watchpoint(hit) => hit,
+            _ => return format!("S{:02x}", SIGTRAP),
+        };
+        let access = self.debugger.watchpoints().iter()
+            .find(|w| w.id == hit.id)
+            .map(|w| w.access)
+            .unwrap_or(hit.access);
+        if access.contains(Access::EXECUTE) {
+            return format!("S{:02x}", SIGTRAP);
+        }
+        let kind = if access == Access::WRITE { "watch" }
+            else if access == Access::READ { "rwatch" }
+            else { "awatch" };
+        format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.addr)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE)
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            // 指定された範囲を切り出して返す。'l' は最後、'm' は続きがあることを表す。
+            let (offset, len) = match parse_addr_len(args) {
+                Some((offset, len)) => (offset as usize, len),
+                None => return error(),
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = (start + len).min(TARGET_XML.len());
+            let mark = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            format!("{}{}", mark, &TARGET_XML[start..end])
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// "type,addr,kind" を解析する。ブレークポイントなら種類は None。
+fn parse_point(args: &str) -> Option<(Option<Access>, u16, usize)> {
+    let (kind, rest) = args.split_once(',')?;
+    let access = match kind {
+        "0" | "1" => None,
+        "2" => Some(Access::WRITE),
+        "3" => Some(Access::READ),
+        "4" => Some(Access::READ | Access::WRITE),
+        _ => return None,
+    };
+    let (addr, len) = parse_addr_len(rest)?;
+    Some((access, addr, len))
+}
+
+/// addr から len バイトの監視範囲。アドレス空間の末尾で打ち切る。
+fn watch_range(addr: u16, len: usize) -> RangeInclusive<u16> {
+    let last = len.max(1) - 1;
+    addr..=addr.saturating_add(last.min(u16::MAX as usize) as u16)
+}
+
+/// "addr,len" を解析する。
+fn parse_addr_len(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)? as u16, parse_hex(len)? as usize))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    BadChecksum,
+}
+
+/// "$<data>#<checksum>" 形式のパケットを1つ読む。接続が切れたら None。
+fn read_packet<R: Read>(conn: &mut R) -> io::Result<Option<Incoming>> {
+    let mut byte = [0; 1];
+    // パケットの開始まで読み飛ばす(確認応答の '+', '-' など)。
+    loop {
+        if conn.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'$' => break,
+            INTERRUPT => return Ok(Some(Incoming::Interrupt)),
+            _ => (),
+        }
+    }
+    let mut data = Vec::new();
+    loop {
+        if conn.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+    let mut checksum = [0; 2];
+    conn.read_exact(&mut checksum)?;
+    let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+    if expected != Some(checksum_of(&data)) {
+        return Ok(Some(Incoming::BadChecksum));
+    }
+    Ok(Some(Incoming::Packet(String::from_utf8_lossy(&data).into_owned())))
+}
+
+fn write_packet<W: Write>(conn: &mut W, data: &str) -> io::Result<()> {
+    write!(conn, "${}#{:02x}", data, checksum_of(data.as_bytes()))?;
+    conn.flush()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
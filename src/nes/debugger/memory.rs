@@ -0,0 +1,131 @@
+//! メモリビューアー。
+//!
+//! CPUのメモリ空間、PPUのメモリ空間、OAM、カートリッジのRAMを、副作用なしで読み書きする。
+//! 16進ダンプ(HexView)は前回表示した内容を覚えておき、変化したバイトに印を付ける。
+
+use std::fmt::Write;
+
+use crate::nes::console::Console;
+use crate::nes::ppu::SPR_RAM_SIZE;
+
+/// カートリッジのRAM(PRG-RAM)がマップされるCPUのアドレス
+const CART_RAM_BASE: u16 = 0x6000;
+/// カートリッジのRAMの容量(bytes)
+const CART_RAM_SIZE: usize = 0x2000;
+/// 16進ダンプの1行のバイト数
+const BYTES_PER_LINE: usize = 16;
+
+/// メモリ空間
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemorySpace {
+    /// CPUのメモリ空間($0000-$FFFF)
+    Cpu,
+    /// PPUのメモリ空間($0000-$3FFF)
+    Ppu,
+    /// スプライト用のメモリ(256バイト)
+    Oam,
+    /// カートリッジのRAM。CPUの $6000-$7FFF を $0000-$1FFF として扱う。
+    CartRam,
+}
+
+impl MemorySpace {
+    /// "cpu", "ppu"(または "vram"), "oam", "sram"(または "prg-ram") から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cpu" => Some(MemorySpace::Cpu),
+            "ppu" | "vram" => Some(MemorySpace::Ppu),
+            "oam" => Some(MemorySpace::Oam),
+            "sram" | "prg-ram" => Some(MemorySpace::CartRam),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MemorySpace::Cpu => "cpu",
+            MemorySpace::Ppu => "ppu",
+            MemorySpace::Oam => "oam",
+            MemorySpace::CartRam => "sram",
+        }
+    }
+
+    /// 空間の広さ(bytes)。どれも2のべき乗。
+    pub fn size(self) -> usize {
+        match self {
+            MemorySpace::Cpu => 0x10000,
+            MemorySpace::Ppu => 0x4000,
+            MemorySpace::Oam => SPR_RAM_SIZE,
+            MemorySpace::CartRam => CART_RAM_SIZE,
+        }
+    }
+
+    /// 副作用なしで読む。addr は空間の広さで折り返す。
+    pub fn peek(self, console: &Console, addr: u16) -> u8 {
+        let addr = self.wrap(addr as usize);
+        match self {
+            MemorySpace::Cpu => console.peek(addr),
+            MemorySpace::Ppu => console.ppu().borrow().vram().peek(addr),
+            MemorySpace::Oam => console.ppu().borrow().spr_ram()[addr as usize],
+            MemorySpace::CartRam => console.peek(CART_RAM_BASE + addr),
+        }
+    }
+
+    /// 副作用なしで書き換える。ミラー領域にも反映し、ROMも書き換えられる。
+    pub fn poke(self, console: &mut Console, addr: u16, data: u8) {
+        let addr = self.wrap(addr as usize);
+        match self {
+            MemorySpace::Cpu => console.poke(addr, data),
+            MemorySpace::Ppu => console.ppu().borrow_mut().poke_vram(addr, data),
+            MemorySpace::Oam => console.ppu().borrow_mut().poke_oam(addr as u8, data),
+            MemorySpace::CartRam => console.poke(CART_RAM_BASE + addr, data),
+        }
+    }
+
+    fn wrap(self, addr: usize) -> u16 {
+        (addr & (self.size() - 1)) as u16
+    }
+}
+
+/// メモリの16進ダンプ。表示するたびに内容を覚えておき、次の表示で変化したバイトの後ろに `*` を付ける。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexView {
+    pub space: MemorySpace,
+    pub start: u16,
+    pub len: u16,
+    /// 前回表示した内容
+    previous: Option<Vec<u8>>,
+}
+
+impl HexView {
+    pub fn new(space: MemorySpace, start: u16, len: u16) -> Self {
+        HexView { space, start, len: len.max(1), previous: None }
+    }
+
+    /// 現在の内容を読む。
+    pub fn bytes(&self, console: &Console) -> Vec<u8> {
+        (0..self.len as usize)
+            .map(|i| self.space.peek(console, self.addr(i)))
+            .collect()
+    }
+
+    /// 16進ダンプを作る。`0800: 12 34* 56` のように、前回から変化したバイトに印を付ける。
+    pub fn render(&mut self, console: &Console) -> String {
+        let bytes = self.bytes(console);
+        let mut out = String::new();
+        for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let _ = write!(out, "{:04X}:", self.addr(line * BYTES_PER_LINE));
+            for (i, byte) in chunk.iter().enumerate() {
+                let changed = self.previous.as_ref()
+                    .is_some_and(|previous| previous[line * BYTES_PER_LINE + i] != *byte);
+                let _ = write!(out, " {:02X}{}", byte, if changed { "*" } else { "" });
+            }
+            out.push('\n');
+        }
+        self.previous = Some(bytes);
+        out.trim_end().to_string()
+    }
+
+    fn addr(&self, offset: usize) -> u16 {
+        self.space.wrap(self.start as usize + offset)
+    }
+}
@@ -0,0 +1,264 @@
+//! サブルーチン単位のサイクルプロファイラー。
+//!
+//! JSR/RTS と割り込みの出入り(割り込みシーケンスと RTI)を追いかけてコールスタックを再現し、
+//! CPUのサイクルを実行中のサブルーチンに割り当てる。サブルーチンごとに、
+//! 呼び出し回数、自身と呼び出し先を含むサイクル数(inclusive)、自身だけのサイクル数(exclusive)、
+//! 1フレームあたりの平均と最大を集計する。
+//! 結果は表形式のテキストと、flamegraph.pl などが読める collapsed stack 形式で出力できる。
+//!
+//! RTS や RTI で戻った先は、呼び出し時のスタックポインタと比べて判断する。
+//! スタックに積んだアドレスへ RTS で飛ぶジャンプテーブルや、TXS でスタックを捨てる処理があっても、
+//! コールスタックが崩れないようにするため。
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::nes::console::Console;
+use crate::nes::cpu::{FlowEvent, IntType};
+
+/// PPUの1スキャンラインのドット数
+const DOTS_PER_SCANLINE: f64 = 341.0;
+/// リセット後のコールスタックの底。RTS や RTI で取り除かれることはない。
+const STACK_BOTTOM: u16 = 0x100;
+
+/// サブルーチンへの入り方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RoutineKind {
+    /// JSRで呼ばれた。
+    Subroutine,
+    Reset,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+/// サブルーチン。バンク切り替えで同じアドレスに別のコードが見える場合は別のものとして扱う。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Routine {
+    /// 先頭アドレス
+    pub entry: u16,
+    /// 先頭アドレスに割り当てられていたPRG-ROMのオフセット
+    pub prg_offset: Option<usize>,
+    pub kind: RoutineKind,
+}
+
+/// サブルーチンごとの集計(サイクル数)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    /// 呼び出し先を含むサイクル数
+    pub inclusive: u64,
+    /// 自身だけのサイクル数
+    pub exclusive: u64,
+    /// 1フレームでの inclusive の最大
+    pub max_frame_inclusive: u64,
+    /// 1フレームでの exclusive の最大
+    pub max_frame_exclusive: u64,
+    frame_inclusive: u64,
+    frame_exclusive: u64,
+}
+
+struct StackEntry {
+    /// routines のインデックス
+    routine: usize,
+    /// 呼び出し前のスタックポインタ。戻った後のSがこれ以上なら、呼び出し元に戻っている。
+    s_at_entry: u16,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    routines: Vec<Routine>,
+    stats: Vec<RoutineStats>,
+    index: HashMap<Routine, usize>,
+    stack: Vec<StackEntry>,
+    /// コールスタック(routines のインデックスの列)ごとの exclusive のサイクル数
+    collapsed: HashMap<Vec<usize>, u64>,
+    /// どのサブルーチンにも入っていない間のサイクル数
+    top_level: u64,
+    /// 最後にサイクルを割り当てた時点の Cpu::clock_counter
+    last_cycle: u64,
+    frames: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// 集計したサブルーチンと、その集計結果。
+    pub fn routines(&self) -> impl Iterator<Item = (&Routine, &RoutineStats)> {
+        self.routines.iter().zip(&self.stats)
+    }
+
+    /// entry から始まるサブルーチンの集計結果。
+    pub fn stats_at(&self, entry: u16) -> Option<&RoutineStats> {
+        self.routines().find(|(routine, _)| routine.entry == entry).map(|(_, stats)| stats)
+    }
+
+    /// 集計したフレーム数
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// サブルーチンや割り込みの出入りを記録する。event の発生直後に呼び出すこと。
+    pub fn record(&mut self, event: FlowEvent, console: &Console) {
+        let cpu = console.cpu();
+        self.attribute(cpu.clock_counter());
+        let pc = cpu.regs().pc;
+        let s = cpu.regs().s as u16;
+        let (kind, s_at_entry) = match event {
+            FlowEvent::Call => (RoutineKind::Subroutine, s + 2),
+            FlowEvent::Interrupt(IntType::Reset) => {
+                self.stack.clear();
+                (RoutineKind::Reset, STACK_BOTTOM)
+            },
+            FlowEvent::Interrupt(IntType::Nmi) => (RoutineKind::Nmi, s + 3),
+            FlowEvent::Interrupt(IntType::Irq) => (RoutineKind::Irq, s + 3),
+            FlowEvent::Interrupt(IntType::Brk) => (RoutineKind::Brk, s + 3),
+            FlowEvent::Interrupt(IntType::None) => return,
+            FlowEvent::Return | FlowEvent::ReturnFromInterrupt => {
+                self.unwind(s);
+                return;
+            },
+        };
+        // 戻りアドレスを捨てたサブルーチン(PLA PLA で戻らずに抜けるなど)は、
+        // 新しい呼び出しがその上に積まれた時点で終わっている。
+        self.unwind(s_at_entry);
+        let routine = Routine { entry: pc, prg_offset: console.prg_rom_offset(pc), kind };
+        let index = self.routine_index(routine);
+        self.stats[index].calls += 1;
+        self.stack.push(StackEntry { routine: index, s_at_entry });
+    }
+
+    /// フレームの区切り。フレームの描画が完了した時点で呼び出すこと。
+    pub fn end_frame(&mut self, cycle: u64) {
+        self.attribute(cycle);
+        self.frames += 1;
+        for stats in &mut self.stats {
+            stats.max_frame_inclusive = stats.max_frame_inclusive.max(stats.frame_inclusive);
+            stats.max_frame_exclusive = stats.max_frame_exclusive.max(stats.frame_exclusive);
+            stats.frame_inclusive = 0;
+            stats.frame_exclusive = 0;
+        }
+    }
+
+    /// 集計結果を inclusive の多い順に表にする。ラベルがあればサブルーチン名に使う。
+    pub fn report(&self, console: &Console) -> String {
+        let region = console.region();
+        let (num, den) = region.ppu_clocks_per_cpu_clock();
+        let cycles_per_dot = den as f64 / num as f64;
+        let frame_cycles = region.scanlines_per_frame() as f64 * DOTS_PER_SCANLINE * cycles_per_dot;
+        let vblank_lines = region.pre_render_scanline() - region.vblank_scanline();
+        let vblank_cycles = vblank_lines as f64 * DOTS_PER_SCANLINE * cycles_per_dot;
+        let frames = self.frames.max(1) as f64;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "frames: {}, cycles/frame: {:.1}, vblank: {:.1} cycles",
+            self.frames, frame_cycles, vblank_cycles);
+        let _ = writeln!(out, "{:<24} {:>8} {:>12} {:>12} {:>10} {:>10} {:>7}",
+            "routine", "calls", "inclusive", "exclusive", "incl/frame", "max/frame", "%frame");
+        let mut order: Vec<usize> = (0..self.routines.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.stats[i].inclusive));
+        for i in order {
+            let stats = &self.stats[i];
+            let per_frame = stats.inclusive as f64 / frames;
+            let _ = writeln!(out, "{:<24} {:>8} {:>12} {:>12} {:>10.1} {:>10} {:>6.1}%",
+                self.name(i, console), stats.calls, stats.inclusive, stats.exclusive,
+                per_frame, stats.max_frame_inclusive, per_frame * 100.0 / frame_cycles);
+        }
+        if self.top_level > 0 {
+            let _ = writeln!(out, "{:<24} {:>8} {:>12} {:>12}", "(top level)", "", self.top_level, self.top_level);
+        }
+        // NMIの処理がVBlankに収まっているか。
+        for (i, routine) in self.routines.iter().enumerate() {
+            if routine.kind == RoutineKind::Nmi {
+                let max = self.stats[i].max_frame_inclusive;
+                let _ = writeln!(out, "{}: max {} cycles/frame = {:.1}% of vblank",
+                    self.name(i, console), max, max as f64 * 100.0 / vblank_cycles);
+            }
+        }
+        out
+    }
+
+    /// 集計結果を collapsed stack 形式(`呼び出し元;呼び出し先 サイクル数` の行)にする。
+    /// flamegraph.pl や inferno でフレームグラフにできる。
+    pub fn collapsed(&self, console: &Console) -> String {
+        let mut lines: Vec<String> = self.collapsed.iter()
+            .filter(|(_, &cycles)| cycles > 0)
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack.iter().map(|&i| self.name(i, console)).collect();
+                let path = if names.is_empty() { "(top level)".to_string() } else { names.join(";") };
+                format!("{} {}", path, cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// 呼び出し前のスタックポインタが s 以下のサブルーチンを、コールスタックから取り除く。
+    fn unwind(&mut self, s: u16) {
+        while self.stack.last().is_some_and(|entry| entry.s_at_entry <= s) {
+            self.stack.pop();
+        }
+    }
+
+    /// 前回から cycle までのサイクルを、現在のコールスタックに割り当てる。
+    fn attribute(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.last_cycle);
+        self.last_cycle = cycle;
+        if elapsed == 0 {
+            return;
+        }
+        let path: Vec<usize> = self.stack.iter().map(|entry| entry.routine).collect();
+        match self.collapsed.get_mut(path.as_slice()) {
+            Some(cycles) => *cycles += elapsed,
+            None => {
+                self.collapsed.insert(path.clone(), elapsed);
+            },
+        }
+        let current = match path.last() {
+            Some(&current) => current,
+            None => {
+                self.top_level += elapsed;
+                return;
+            },
+        };
+        let stats = &mut self.stats[current];
+        stats.exclusive += elapsed;
+        stats.frame_exclusive += elapsed;
+        // 再帰呼び出しで同じサブルーチンが複数回積まれていても、inclusive は1回だけ数える。
+        for (depth, &routine) in path.iter().enumerate() {
+            if !path[..depth].contains(&routine) {
+                let stats = &mut self.stats[routine];
+                stats.inclusive += elapsed;
+                stats.frame_inclusive += elapsed;
+            }
+        }
+    }
+
+    fn routine_index(&mut self, routine: Routine) -> usize {
+        if let Some(&index) = self.index.get(&routine) {
+            return index;
+        }
+        self.routines.push(routine);
+        self.stats.push(RoutineStats::default());
+        self.index.insert(routine, self.routines.len() - 1);
+        self.routines.len() - 1
+    }
+
+    /// サブルーチンの表示名。ラベルが無ければアドレス。割り込みハンドラには種類を付ける。
+    fn name(&self, index: usize, console: &Console) -> String {
+        let routine = &self.routines[index];
+        let base = match console.symbols().label_at(routine.entry, routine.prg_offset) {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", routine.entry),
+        };
+        match routine.kind {
+            RoutineKind::Subroutine => base,
+            RoutineKind::Reset => format!("RESET:{}", base),
+            RoutineKind::Nmi => format!("NMI:{}", base),
+            RoutineKind::Irq => format!("IRQ:{}", base),
+            RoutineKind::Brk => format!("BRK:{}", base),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! RAMサーチ(チートサーチ)。
+//!
+//! 作業用RAM(内蔵RAMとカートリッジのRAM)の内容を記録し、前回の値や指定した値との比較で
+//! 候補のアドレスを絞り込んでいく。ゲームの変数(残機数、座標など)のアドレスを探すのに使う。
+//! 値は8bitか16bit(リトルエンディアン)で、符号付きか符号なしとして比較する。
+
+use std::ops::RangeInclusive;
+
+use super::CompareOp;
+use crate::nes::console::Console;
+
+/// 検索するCPUのアドレス範囲(内蔵RAMとカートリッジのRAM)。内蔵RAMのミラー領域は含まない。
+pub const SEARCH_RANGES: [RangeInclusive<u16>; 2] = [0x0000..=0x07FF, 0x6000..=0x7FFF];
+
+/// 値の大きさと解釈
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueType {
+    #[default]
+    U8,
+    S8,
+    U16,
+    S16,
+}
+
+impl ValueType {
+    /// "u8", "s8", "u16", "s16" から変換する。"8", "16" は符号なしとして扱う。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "u8" | "8" => Some(ValueType::U8),
+            "s8" => Some(ValueType::S8),
+            "u16" | "16" => Some(ValueType::U16),
+            "s16" => Some(ValueType::S16),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::U8 => "u8",
+            ValueType::S8 => "s8",
+            ValueType::U16 => "u16",
+            ValueType::S16 => "s16",
+        }
+    }
+
+    /// 値のバイト数
+    pub fn size(self) -> u16 {
+        match self {
+            ValueType::U8 | ValueType::S8 => 1,
+            ValueType::U16 | ValueType::S16 => 2,
+        }
+    }
+
+    /// addr から値を読む。16bitの場合は addr が下位バイト。
+    pub fn read(self, console: &Console, addr: u16) -> i32 {
+        let lo = console.peek(addr);
+        match self {
+            ValueType::U8 => lo as i32,
+            ValueType::S8 => lo as i8 as i32,
+            ValueType::U16 | ValueType::S16 => {
+                let value = u16::from_le_bytes([lo, console.peek(addr.wrapping_add(1))]);
+                if self == ValueType::S16 { value as i16 as i32 } else { value as i32 }
+            },
+        }
+    }
+}
+
+/// 候補の絞り込み方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchFilter {
+    /// 前回の値と比べる。値が変化した = Previous(Ne)、変化していない = Previous(Eq)。
+    Previous(CompareOp),
+    /// 指定した値と比べる。
+    Value(CompareOp, i32),
+}
+
+/// 候補のアドレス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u16,
+    /// 前回記録した値
+    pub previous: i32,
+}
+
+pub struct RamSearch {
+    value_type: ValueType,
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    /// 作業用RAMの全アドレスを候補として、現在の値を記録する。
+    /// 16bitの場合、上位バイトが範囲の外にはみ出すアドレスは含めない。
+    pub fn new(console: &Console, value_type: ValueType) -> Self {
+        let candidates = SEARCH_RANGES.iter()
+            .flat_map(|range| *range.start()..=range.end() - (value_type.size() - 1))
+            .map(|addr| Candidate { addr, previous: value_type.read(console, addr) })
+            .collect();
+        RamSearch { value_type, candidates }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// 条件に合う候補だけを残し、残った候補の値を記録し直す。残った候補の数を返す。
+    pub fn filter(&mut self, console: &Console, filter: SearchFilter) -> usize {
+        let value_type = self.value_type;
+        self.candidates.retain_mut(|candidate| {
+            let value = value_type.read(console, candidate.addr);
+            let matched = match filter {
+                SearchFilter::Previous(op) => op.eval(value, candidate.previous),
+                SearchFilter::Value(op, rhs) => op.eval(value, rhs),
+            };
+            candidate.previous = value;
+            matched
+        });
+        self.candidates.len()
+    }
+
+    /// 候補を減らさずに、現在の値を記録し直す。
+    pub fn snapshot(&mut self, console: &Console) {
+        for candidate in &mut self.candidates {
+            candidate.previous = self.value_type.read(console, candidate.addr);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! デバッグシンボル(ラベル)の読み込み。
+//!
+//! 以下の形式に対応する。
+//! * ca65 (ld65 の `--dbgfile`) の .dbg ファイル
+//! * FCEUX の .nl ファイル(`game.nes.ram.nl`, 16KBのバンクごとの `game.nes.0.nl` など)
+//! * Mesen の .mlb ファイル
+//!
+//! PRG-ROM内のラベルはPRG-ROM上のオフセットも保持し、バンク切り替えで
+//! 同じアドレスに別のバンクが見えている場合は、そのバンクのラベルを使う。
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::nes::cpu::disasm::Labels;
+use crate::nes::rom;
+use crate::nes::util;
+
+/// iNESヘッダのサイズ
+const INES_HEADER_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// CPUのアドレス。PRG-ROM上のオフセットしか分からない場合(Mesenの P: など)は None。
+    pub addr: Option<u16>,
+    /// PRG-ROM内のラベルなら、PRG-ROMの先頭からのオフセット
+    pub prg_offset: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    /// 名前から symbols の添字。同じ名前が複数ある場合は最初のもの。
+    by_name: HashMap<String, usize>,
+    /// CPUのアドレスから symbols の添字
+    by_addr: HashMap<u16, usize>,
+    /// PRG-ROM上のオフセットから symbols の添字
+    by_prg_offset: HashMap<usize, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn add(&mut self, symbol: Symbol) {
+        let index = self.symbols.len();
+        self.by_name.entry(symbol.name.clone()).or_insert(index);
+        if let Some(addr) = symbol.addr {
+            self.by_addr.entry(addr).or_insert(index);
+        }
+        if let Some(offset) = symbol.prg_offset {
+            self.by_prg_offset.entry(offset).or_insert(index);
+        }
+        self.symbols.push(symbol);
+    }
+
+    /// other のシンボルを追加する。
+    pub fn extend(&mut self, other: SymbolTable) {
+        for symbol in other.symbols {
+            self.add(symbol);
+        }
+    }
+
+    /// 拡張子から形式を判断して読み込む。
+    pub fn load_from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| util::Error::new(format!("[{}] {}", path.display(), err)))?;
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let result = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => Self::parse_ca65_dbg(&text),
+            Some("nl") => Self::parse_fceux_nl(&text, fceux_nl_bank(file_name)),
+            Some("mlb") => Self::parse_mesen_mlb(&text),
+            _ => Err(util::Error::new("unknown symbol file type (expected .dbg, .nl or .mlb)".to_string())),
+        };
+        result.map_err(|err| util::Error::new(format!("[{}] {}", path.display(), err)))
+    }
+
+    /// ROMと同じ場所にあるシンボルファイルをすべて読み込む。
+    /// `game.nes` なら `game.dbg`, `game.mlb`, `game.nes.ram.nl`, `game.nes.0.nl`, ...
+    pub fn load_beside_rom(rom_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut table = SymbolTable::new();
+        for path in companion_files(rom_path) {
+            table.extend(Self::load_from_file(&path)?);
+        }
+        Ok(table)
+    }
+
+    /// ca65 の .dbg ファイルを読む。ラベル(type=lab)だけを取り込み、定数は無視する。
+    pub fn parse_ca65_dbg(text: &str) -> Result<Self, Box<dyn Error>> {
+        // セグメントID -> (開始アドレス, 出力ファイル上のオフセット, 出力ファイル名)
+        let mut segments: HashMap<u32, (u32, Option<usize>, String)> = HashMap::new();
+        let mut labels: Vec<(String, u32, Option<u32>)> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let (keyword, fields) = match line.split_once(char::is_whitespace) {
+                Some((keyword, fields)) => (keyword, dbg_fields(fields.trim())),
+                None => continue,
+            };
+            let error = || util::Error::new(format!("line {}: invalid {} record", number + 1, keyword));
+            match keyword {
+                "seg" => {
+                    let id = fields.get("id").and_then(|v| parse_number(v)).ok_or_else(error)?;
+                    let start = fields.get("start").and_then(|v| parse_number(v)).ok_or_else(error)?;
+                    let ooffs = fields.get("ooffs").and_then(|v| parse_number(v)).map(|v| v as usize);
+                    let oname = fields.get("oname").cloned().unwrap_or_default();
+                    segments.insert(id, (start, ooffs, oname));
+                },
+                "sym" if fields.get("type").map(String::as_str) == Some("lab") => {
+                    let name = fields.get("name").cloned().ok_or_else(error)?;
+                    let val = fields.get("val").and_then(|v| parse_number(v)).ok_or_else(error)?;
+                    let seg = fields.get("seg").and_then(|v| parse_number(v));
+                    labels.push((name, val, seg));
+                },
+                _ => (),
+            }
+        }
+
+        let mut table = SymbolTable::new();
+        for (name, val, seg) in labels {
+            // 出力ファイル上の位置から、PRG-ROM上のオフセットを求める。
+            let prg_offset = seg.and_then(|seg| segments.get(&seg))
+                .and_then(|(start, ooffs, oname)| {
+                    let file_offset = (*ooffs)? + val.checked_sub(*start)? as usize;
+                    let header_len = if oname.to_ascii_lowercase().ends_with(".nes") { INES_HEADER_LEN } else { 0 };
+                    file_offset.checked_sub(header_len)
+                });
+            table.add(Symbol { name, addr: Some(val as u16), prg_offset });
+        }
+        Ok(table)
+    }
+
+    /// FCEUX の .nl ファイルを読む。bank は16KB単位のPRG-ROMのバンク番号で、
+    /// RAMのラベル(`.ram.nl`)なら None。
+    /// 各行は `$C000#Reset#コメント` の形式(配列なら `$0300/10#buffer#`)。
+    pub fn parse_fceux_nl(text: &str, bank: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let mut table = SymbolTable::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || util::Error::new(format!("line {}: invalid label: {}", number + 1, line));
+            let mut fields = line.splitn(3, '#');
+            let addr = fields.next().and_then(|addr| addr.strip_prefix('$')).ok_or_else(error)?;
+            let addr = addr.split('/').next().unwrap_or(addr);
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error())?;
+            let name = fields.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            let prg_offset = bank.filter(|_| addr >= 0x8000)
+                .map(|bank| bank * rom::PRG_ROM_UNIT_SIZE + (addr as usize & (rom::PRG_ROM_UNIT_SIZE - 1)));
+            table.add(Symbol { name: name.to_string(), addr: Some(addr), prg_offset });
+        }
+        Ok(table)
+    }
+
+    /// Mesen の .mlb ファイルを読む。各行は `種類:アドレス[-終了アドレス]:ラベル[:コメント]` の形式。
+    /// PRG-ROM(P), 内蔵RAM(R), セーブRAM(S), ワークRAM(W), レジスタ(G)のラベルを取り込む。
+    pub fn parse_mesen_mlb(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut table = SymbolTable::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || util::Error::new(format!("line {}: invalid label: {}", number + 1, line));
+            let mut fields = line.splitn(4, ':');
+            let kind = fields.next().ok_or_else(error)?;
+            let addr = fields.next().ok_or_else(error)?;
+            let addr = addr.split('-').next().unwrap_or(addr);
+            let addr = usize::from_str_radix(addr, 16).map_err(|_| error())?;
+            let name = fields.next().unwrap_or("").trim();
+            // コメントだけの行もある。
+            if name.is_empty() {
+                continue;
+            }
+            let (addr, prg_offset) = match kind {
+                "P" | "NesPrgRom" => (None, Some(addr)),
+                "R" | "NesInternalRam" | "G" | "NesMemory" => (Some(addr as u16), None),
+                "S" | "NesSaveRam" | "W" | "NesWorkRam" => (Some(0x6000u16.wrapping_add(addr as u16)), None),
+                // CHR-ROMなど、CPUから見えないメモリのラベルは使わない。
+                _ => continue,
+            };
+            table.add(Symbol { name: name.to_string(), addr, prg_offset });
+        }
+        Ok(table)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&i| &self.symbols[i])
+    }
+
+    /// addr のラベル。prg_offset は addr に現在マップされているPRG-ROM上のオフセット。
+    /// 他のバンクのラベルは返さない。
+    pub fn label_at(&self, addr: u16, prg_offset: Option<usize>) -> Option<&str> {
+        if let Some(&i) = prg_offset.and_then(|offset| self.by_prg_offset.get(&offset)) {
+            return Some(&self.symbols[i].name);
+        }
+        self.by_addr.get(&addr)
+            .map(|&i| &self.symbols[i])
+            .filter(|symbol| symbol.prg_offset.is_none() || prg_offset.is_none())
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /// ラベルのCPUのアドレス。prg_offset_of はアドレスに現在マップされているPRG-ROM上のオフセット。
+    /// PRG-ROM内のラベルで、そのバンクが現在マップされていなければ、シンボルファイル上のアドレスを返す。
+    pub fn resolve<F: Fn(u16) -> Option<usize>>(&self, name: &str, prg_offset_of: F) -> Option<u16> {
+        let symbol = self.find(name)?;
+        let offset = match symbol.prg_offset {
+            Some(offset) => offset,
+            None => return symbol.addr,
+        };
+        symbol.addr
+            .filter(|&addr| prg_offset_of(addr) == Some(offset))
+            .or_else(|| (0x8000..=0xFFFF).find(|&addr| prg_offset_of(addr) == Some(offset)))
+            .or(symbol.addr)
+    }
+
+    /// アドレスの式を解析する。`$C000`, `0xC000`, `C000`(16進数)、ラベル名、
+    /// ラベル名に16進数のオフセットを付けた `main+3`, `table-1` を受け付ける。
+    /// 接頭辞のない16進数として読める名前のラベルがあれば、ラベルを優先する。
+    pub fn resolve_expr<F: Fn(u16) -> Option<usize>>(&self, text: &str, prg_offset_of: F) -> Option<u16> {
+        let (base, offset) = match text.find(['+', '-']) {
+            Some(pos) if pos > 0 => {
+                let offset = super::parse_hex(&text[pos + 1..])?;
+                let offset = if text.as_bytes()[pos] == b'-' { offset.wrapping_neg() } else { offset };
+                (&text[..pos], offset)
+            },
+            _ => (text, 0),
+        };
+        let addr = self.resolve(base, &prg_offset_of)
+            .or_else(|| super::parse_hex(base))?;
+        Some(addr.wrapping_add(offset))
+    }
+}
+
+/// アドレスに現在マップされているPRG-ROMのバンクに合わせてラベルを引く。
+pub struct BankedLabels<'a, F: Fn(u16) -> Option<usize>> {
+    symbols: &'a SymbolTable,
+    prg_offset_of: F,
+}
+
+impl<'a, F: Fn(u16) -> Option<usize>> BankedLabels<'a, F> {
+    pub fn new(symbols: &'a SymbolTable, prg_offset_of: F) -> Self {
+        BankedLabels { symbols, prg_offset_of }
+    }
+}
+
+impl<F: Fn(u16) -> Option<usize>> Labels for BankedLabels<'_, F> {
+    fn label(&self, addr: u16) -> Option<&str> {
+        self.symbols.label_at(addr, (self.prg_offset_of)(addr))
+    }
+}
+
+/// .dbg の `key=value,key="value"` を分解する。
+fn dbg_fields(text: &str) -> HashMap<&str, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (quoted[..end].to_string(), next.strip_prefix(',').unwrap_or(next))
+            },
+            None => match value.split_once(',') {
+                Some((value, next)) => (value.to_string(), next),
+                None => (value.to_string(), ""),
+            },
+        };
+        fields.insert(key.trim(), value);
+        rest = next;
+    }
+    fields
+}
+
+/// .dbg の数値("0xC000" または10進数)を解析する。
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// `game.nes.3.nl` のバンク番号(16進数)。`game.nes.ram.nl` なら None。
+fn fceux_nl_bank(file_name: &str) -> Option<usize> {
+    let stem = file_name.strip_suffix(".nl")?;
+    let (_, bank) = stem.rsplit_once('.')?;
+    usize::from_str_radix(bank, 16).ok()
+}
+
+/// ROMと同じ場所にある、シンボルファイルの候補のうち存在するもの。
+fn companion_files(rom_path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![rom_path.with_extension("dbg"), rom_path.with_extension("mlb")];
+    // FCEUX の .nl は、ROMのファイル名の後ろに付く。
+    if let (Some(dir), Some(rom_name)) = (rom_path.parent(), rom_path.file_name().and_then(|n| n.to_str())) {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let mut nl_files: Vec<PathBuf> = fs::read_dir(dir).into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().and_then(|n| n.to_str())
+                .is_some_and(|name| name.starts_with(&format!("{}.", rom_name)) && name.ends_with(".nl")))
+            .collect();
+        nl_files.sort();
+        paths.extend(nl_files);
+    }
+    paths.into_iter().filter(|path| path.is_file()).collect()
+}
@@ -0,0 +1,186 @@
+//! 命令単位のトレースログ。
+//!
+//! Console に設定すると、CPUが命令をフェッチする直前に1行ずつ記録する。
+//! 出力する項目、PCの範囲とバンクによる絞り込み、記録の開始・終了の条件を指定できる。
+//! リングバッファのモードでは直近の N 行だけをメモリ上に保持し、
+//! ブレークポイントで止まった場合やエミュレーターが異常終了した場合に書き出す。
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::nes::console::Console;
+use crate::nes::cpu::trace::TraceFields;
+use crate::nes::rom;
+
+/// 記録の開始・終了の条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// PCが指定したアドレスに来た。
+    Pc(u16),
+    /// 指定したフレーム(電源投入後に描画を完了したフレーム数)に達した。
+    Frame(u64),
+}
+
+impl Trigger {
+    /// "frame:<N>"(10進数)か、アドレスを解析する。アドレスは resolve_addr で解決する。
+    pub fn parse(text: &str, resolve_addr: &dyn Fn(&str) -> Option<u16>) -> Option<Self> {
+        match text.strip_prefix("frame:") {
+            Some(frame) => frame.parse().ok().map(Trigger::Frame),
+            None => resolve_addr(text).map(Trigger::Pc),
+        }
+    }
+
+    fn hit(self, pc: u16, frame: u64) -> bool {
+        match self {
+            Trigger::Pc(addr) => pc == addr,
+            Trigger::Frame(n) => frame >= n,
+        }
+    }
+}
+
+pub struct Tracer {
+    fields: TraceFields,
+    /// 記録するPCの範囲
+    pc_range: Option<RangeInclusive<u16>>,
+    /// 記録するPRG-ROMのバンク番号(16KB単位)。PRG-ROM以外を実行中は記録しない。
+    banks: Option<Vec<usize>>,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    /// 開始の条件を満たした。
+    started: bool,
+    /// 終了の条件を満たした。以降は記録しない。
+    stopped: bool,
+    /// リングバッファのモードなら、直近の行と保持する最大行数
+    ring: Option<(VecDeque<String>, usize)>,
+    output: Box<dyn Write>,
+    /// 書き込みで最初に発生したエラー
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// すべての命令を、nestest.log と同じ項目で output に書き出すトレーサーを作る。
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Tracer {
+            fields: TraceFields::NESTEST,
+            pc_range: None,
+            banks: None,
+            start: None,
+            stop: None,
+            started: false,
+            stopped: false,
+            ring: None,
+            output,
+            error: None,
+        }
+    }
+
+    pub fn set_fields(&mut self, fields: TraceFields) {
+        self.fields = fields;
+    }
+
+    pub fn set_pc_range(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.pc_range = range;
+    }
+
+    pub fn set_banks(&mut self, banks: Option<Vec<usize>>) {
+        self.banks = banks;
+    }
+
+    /// 記録を開始する条件。None なら最初から記録する。
+    pub fn set_start(&mut self, trigger: Option<Trigger>) {
+        self.start = trigger;
+    }
+
+    /// 記録を終了する条件。条件を満たした命令は記録しない。
+    pub fn set_stop(&mut self, trigger: Option<Trigger>) {
+        self.stop = trigger;
+    }
+
+    /// リングバッファのモードにする。直近の lines 行だけを保持し、dump_ring で書き出す。
+    /// None なら、記録した行をすぐに書き出す。
+    pub fn set_ring(&mut self, lines: Option<usize>) {
+        self.ring = lines.map(|lines| (VecDeque::with_capacity(lines), lines.max(1)));
+    }
+
+    /// リングバッファに保持している行。
+    pub fn ring_lines(&self) -> impl Iterator<Item = &str> {
+        self.ring.iter().flat_map(|(ring, _)| ring.iter().map(String::as_str))
+    }
+
+    /// 次に実行する命令を記録する。命令の境界で呼び出すこと。
+    pub fn record(&mut self, console: &Console) {
+        let pc = console.cpu().regs().pc;
+        let frame = console.frame_count();
+        if self.stopped {
+            return;
+        }
+        if !self.started {
+            if !self.start.is_none_or(|trigger| trigger.hit(pc, frame)) {
+                return;
+            }
+            self.started = true;
+        }
+        if self.stop.is_some_and(|trigger| trigger.hit(pc, frame)) {
+            self.stopped = true;
+            return;
+        }
+        if self.pc_range.as_ref().is_some_and(|range| !range.contains(&pc)) {
+            return;
+        }
+        let bank = console.prg_rom_offset(pc).map(|offset| offset / rom::PRG_ROM_UNIT_SIZE);
+        if let Some(banks) = &self.banks {
+            if !bank.is_some_and(|bank| banks.contains(&bank)) {
+                return;
+            }
+        }
+
+        let line = console.trace_line().format_with(self.fields, bank);
+        match &mut self.ring {
+            Some((ring, capacity)) => {
+                if ring.len() == *capacity {
+                    ring.pop_front();
+                }
+                ring.push_back(line);
+            },
+            None => {
+                let result = writeln!(self.output, "{}", line);
+                self.keep_error(result);
+            },
+        }
+    }
+
+    /// リングバッファに保持している行を書き出し、バッファを空にする。
+    pub fn dump_ring(&mut self) {
+        let lines: Vec<String> = match &mut self.ring {
+            Some((ring, _)) => ring.drain(..).collect(),
+            None => return,
+        };
+        for line in lines {
+            let result = writeln!(self.output, "{}", line);
+            self.keep_error(result);
+        }
+        self.flush();
+    }
+
+    /// 出力をフラッシュする。エラーは finish で返す。
+    pub fn flush(&mut self) {
+        let result = self.output.flush();
+        self.keep_error(result);
+    }
+
+    /// 出力をフラッシュし、書き込みでエラーが発生していればそれを返す。
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush();
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn keep_error(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+    }
+}
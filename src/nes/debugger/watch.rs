@@ -0,0 +1,143 @@
+//! メモリアクセスの監視(ウォッチポイント)。
+//! CPUのバスに割り込ませ、監視範囲への読み書きを記録する。
+
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use bitflags::bitflags;
+
+use crate::nes::cpu_bus::CpuBus;
+
+bitflags! {
+    /// 監視するアクセスの種類
+    pub struct Access: u8 {
+        const READ    = 0b0000_0001;
+        const WRITE   = 0b0000_0010;
+        /// 命令の実行(その範囲に PC が来た)
+        const EXECUTE = 0b0000_0100;
+    }
+}
+
+impl Access {
+    /// "r", "w", "x" の組み合わせ(例: "rw")から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut access = Access::empty();
+        for c in name.chars() {
+            access |= match c {
+                'r' => Access::READ,
+                'w' => Access::WRITE,
+                'x' => Access::EXECUTE,
+                _ => return None,
+            };
+        }
+        (!access.is_empty()).then_some(access)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+}
+
+/// ウォッチポイントに該当したアクセス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub addr: u16,
+    pub access: Access,
+    pub data: u8,
+}
+
+/// ウォッチポイントの一覧と、該当したアクセスの記録。
+/// Console と WatchBus で共有する。
+#[derive(Default)]
+pub struct MemWatch {
+    watchpoints: Vec<Watchpoint>,
+    /// 最後に取り出してから、最初に該当したアクセス
+    hit: Option<WatchHit>,
+}
+
+impl MemWatch {
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// 指定したIDのウォッチポイントを削除する。削除できたらtrue。
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.id != id);
+        self.watchpoints.len() != len
+    }
+
+    /// 記録したアクセスを取り出す。
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    /// アクセスがウォッチポイントに該当すれば記録する。
+    /// 読み込みには命令のフェッチも含まれる。
+    pub fn record(&mut self, addr: u16, access: Access, data: u8) {
+        if self.hit.is_some() {
+            return;
+        }
+        self.hit = self.watchpoints.iter()
+            .find(|w| w.access.contains(access) && w.range.contains(&addr))
+            .map(|w| WatchHit { id: w.id, addr, access, data });
+    }
+}
+
+/// CPUとメモリの間に入り、読み書きを MemWatch に通知するバス。
+pub struct WatchBus {
+    inner: Box<dyn CpuBus>,
+    watch: Rc<RefCell<MemWatch>>,
+}
+
+impl WatchBus {
+    pub fn new(inner: Box<dyn CpuBus>, watch: Rc<RefCell<MemWatch>>) -> Self {
+        WatchBus { inner, watch }
+    }
+}
+
+impl CpuBus for WatchBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.inner.read(addr);
+        let mut watch = self.watch.borrow_mut();
+        if !watch.watchpoints.is_empty() {
+            watch.record(addr, Access::READ, data);
+        }
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        {
+            let mut watch = self.watch.borrow_mut();
+            if !watch.watchpoints.is_empty() {
+                watch.record(addr, Access::WRITE, data);
+            }
+        }
+        self.inner.write(addr, data);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) {
+        self.inner.poke(addr, data);
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.inner.prg_rom_offset(addr)
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+    }
+}
@@ -0,0 +1,65 @@
+//! 標準コントローラー。
+//! CPU側の $4016(書き込み:ストローブ / 読み込み:1P) と $4017(読み込み:2P) に接続される。
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// コントローラーのボタン。ビットの並びはシフトレジスタから読み出される順番と同じ。
+    #[derive(Default)]
+    pub struct Buttons: u8 {
+        const A         = 0b0000_0001;
+        const B         = 0b0000_0010;
+        const SELECT    = 0b0000_0100;
+        const START     = 0b0000_1000;
+        const UP        = 0b0001_0000;
+        const DOWN      = 0b0010_0000;
+        const LEFT      = 0b0100_0000;
+        const RIGHT     = 0b1000_0000;
+    }
+}
+
+/// 4021(8bitパラレル入力シフトレジスタ)を内蔵した標準コントローラー。
+#[derive(Default)]
+pub struct Joypad {
+    /// フロントエンドから通知された、現在押されているボタン。
+    buttons: Buttons,
+    /// 読み出し待ちのシフトレジスタ。
+    shift: u8,
+    /// ストローブ中(=$4016のbit0が1)ならtrue。
+    /// ストローブ中はボタンの状態がシフトレジスタへ常時ロードされ続ける。
+    strobe: bool,
+}
+
+impl Joypad {
+    /// ボタンの押下状態を更新する。
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// $4016への書き込み。bit0のみ意味を持つ。
+    pub fn write_strobe(&mut self, data: u8) {
+        self.strobe = (data & 0b0000_0001) != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    /// $4016/$4017からの読み込み。
+    /// A, B, Select, Start, Up, Down, Left, Right の順に1bitずつ返す。
+    /// 8回読み終えた後は、純正コントローラーと同様に1を返し続ける。
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons.bits() & 0b0000_0001
+        }
+        let bit = self.shift & 0b0000_0001;
+        self.shift = (self.shift >> 1) | 0b1000_0000;
+        bit
+    }
+}
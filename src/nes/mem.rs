@@ -7,8 +7,10 @@ use std::rc::Rc;
 use std::ops::RangeInclusive;
 use num_traits::FromPrimitive;
 
-use crate::util;
+use crate::nes::rom;
+use crate::nes::cpu_bus::CpuBus;
 use crate::nes::ppu_databus::PpuDataBus;
+use crate::nes::joypad::Joypad;
 
 /// NESに搭載されている物理RAM容量(bytes)
 pub const PHYSICAL_RAM_SIZE: usize = 0x0800;
@@ -18,46 +20,98 @@ pub const LOGICAL_RAM_SPACE: usize = 0x10000;
 pub struct MemCon {
     pub ram: Box<[u8]>,
     pub ppu: Rc<RefCell<dyn PpuDataBus>>,
+    /// $4016(1P)と$4017(2P)に接続されたコントローラー
+    pub joypads: Rc<RefCell<[Joypad; 2]>>,
+    /// $8000 以降に展開したPRG-ROMの容量(bytes)
+    prg_rom_len: usize,
 }
 
 impl MemCon {
     
-    pub fn new(ppu_databus: Rc<RefCell<dyn PpuDataBus>>) -> Self {
+    pub fn new(ppu_databus: Rc<RefCell<dyn PpuDataBus>>, joypads: Rc<RefCell<[Joypad; 2]>>) -> Self {
+        // 物理RAMの初期化。
+        // 機種によっては起動時のメモリ内容が一定でない場合もあるが、
+        // ここでは0クリアとしておく。
         MemCon {
             ppu: ppu_databus,
             ram: Box::new([0; LOGICAL_RAM_SPACE]),
+            joypads,
+            prg_rom_len: 0,
         }
     }
 
+    /// PRG-ROM を $8000 以降に展開する。
+    pub fn load_prg_rom(&mut self, rom: &rom::NesRom) {
+        let prg_rom = rom.prg_rom();
+        let len = rom::PRG_ROM_UNIT_SIZE;
+        if prg_rom.len() >= len {
+            self.raw_write(0x8000, &prg_rom[0..len]);
+        }
+        if prg_rom.len() >= (len * 2) {
+            self.raw_write(0xC000, &prg_rom[len..len*2]);
+        } else {
+            // PRG-ROMが2枚ない場合は、1枚目をコピーする。
+            // TODO: MMCによってはPRG-ROMが2つ以上載っている可能性あり。
+            self.raw_write(0xC000, &prg_rom[0..len]);
+        }
+        self.prg_rom_len = prg_rom.len().min(len * 2);
+    }
+
     /// メモリマップドI/Oやミラー領域を考慮せず、メモリに直にデータを書き込む。
     pub fn raw_write(&mut self, addr: u16, data: &[u8]) {
-        log::debug!("raw_write: addr={:#06X}, data.len={}", addr, data.len());
         let addr = addr as usize;
         self.ram[addr..addr+data.len()].copy_from_slice(data);
     }
 
     /// メモリマップドI/Oやミラー領域を考慮せず、メモリに直にデータを書き込む。
     pub fn raw_write_b(&mut self, addr: u16, data: u8) {
-        log::debug!("raw_write_b: addr={:#06X}, data={:#04X}({})", addr, data, data);
         let addr = addr as usize;
         self.ram[addr] = data;
     }
 
     /// メモリマップドI/Oやミラー領域を考慮せず、メモリに直にデータを書き込む。
     pub fn raw_fill(&mut self, range: RangeInclusive<usize>, data: u8) {
-        log::debug!("raw_fill: range=({:?}), data={:#04X}({})", range, data, data);
         self.ram[range].fill(data);
     }
+}
+
+impl CpuBus for MemCon {
+    /// 副作用なしでメモリを読む。メモリマップドI/Oのレジスタは読まず、
+    /// 最後に書き込まれた値(RAM上の値)をそのまま返す。デバッグ用途。
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    /// メモリマップドI/Oを介さずにメモリを書き換える。物理RAMはミラー領域にも反映する。
+    /// ROM領域も書き換えられる。デバッグ用途。
+    fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                let addr = (addr as usize) & (PHYSICAL_RAM_SIZE - 1);
+                for mirror in (0..0x2000).step_by(PHYSICAL_RAM_SIZE) {
+                    self.ram[mirror + addr] = data;
+                }
+            },
+            _ => self.ram[addr as usize] = data,
+        }
+    }
+
+    /// バンク切り替えには未対応なので、16KBなら $8000 と $C000 に同じ内容が見える。
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 || self.prg_rom_len == 0 {
+            return None;
+        }
+        Some((addr as usize - 0x8000) % self.prg_rom_len)
+    }
 
-    pub fn write(&mut self, addr: u16, data: u8) {
-        log::debug!("write: addr={:#06X}, data={:#04X}({})", addr, data, data);
+    fn write(&mut self, addr: u16, data: u8) {
         match addr {
             // 物理RAM領域への書き込み
             0x0000..=0x1FFF => {
                 // 物理RAMのミラー領域への反映
                 // orignal:($0000-$07FF) -> mirror:($0800-$0FFF, $1000-$17FF, $1800-$1FFF)
-                let addr = addr as usize;
-                self.ram[0x0000+addr] = data;
+                let addr = (addr as usize) & (PHYSICAL_RAM_SIZE - 1);
+                self.ram[addr] = data;
                 self.ram[0x0800+addr] = data;
                 self.ram[0x1000+addr] = data;
                 self.ram[0x1800+addr] = data;
@@ -69,12 +123,19 @@ impl MemCon {
                 // 転送が完了するまでCPUは停止する。つまり新規stateが必要？
                 // メモリ上の値もユーザーが指定したデータ(アドレス値)を書き込んでおくこと！
             },
+            // コントローラーのストローブ。1P/2Pの両方に同時に届く。
+            0x4016 => {
+                let mut joypads = self.joypads.borrow_mut();
+                joypads[0].write_strobe(data);
+                joypads[1].write_strobe(data);
+                self.ram[addr as usize] = data;
+            },
             // PPUのレジスタへの書き込み
             0x2000..=0x3FFF => {
                 // ミラー領域への反映を行う
                 // 仮にミラー領域へ書きこんでいても、まずはオリジナル領域($2000-$2007)への書き込みとみなす。
                 // ここで必要なアドレスは最後の3bitだけ。
-                let offset = (addr as usize) & 0x0111;
+                let offset = (addr as usize) & 0x0007;
                 let reg_type = FromPrimitive::from_usize(offset).unwrap();
                 self.ppu.borrow_mut().write(reg_type, data);
 
@@ -87,10 +148,9 @@ impl MemCon {
             0x8000..=0xFFFF => {
                 // TODO: MapperによってはROMへの書き込みを検出する機構がある。
 
-                // 実機ではROMへの書き込みはエラーとならないが、
-                // 現状のエミュレーター実装でROMへの書き込みが行われた場合、
-                // 命令デコードの不具合である可能性が高いため、panic させる。
-                util::panic_write_to_read_only_area(addr, data)
+                // 実機ではROMへの書き込みはエラーとならない。
+                // マッパーのレジスタへの書き込みの可能性もあるので、現状は無視する。
+                // 書き込みはイベントビューアーでマッパーへの書き込みとして確認できる。
             },
             // TODO: APUの対応が必要
             _ => {
@@ -100,23 +160,25 @@ impl MemCon {
         }
     }
     
-    pub fn read(&mut self, addr: u16) -> u8 {
-        let data = match addr {
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
             // PPUのレジスタを読む
             0x2000..=0x3FFF => {
                 // 仮にミラー領域を読み込んでいても、オリジナル領域($2000-$2007)からの読み込みとみなす。
                 // ここで必要なアドレスは最後の3bitだけ。
-                let offset = (addr as usize) & 0x0111;
+                let offset = (addr as usize) & 0x0007;
                 let reg_type = FromPrimitive::from_usize(offset).unwrap();
                 self.ppu.borrow_mut().read(reg_type)
             },
+            // コントローラーの読み込み。
+            // 上位bitはオープンバスなので、直前にバスに乗っていたアドレスの上位バイト($40)を返す。
+            0x4016 => self.joypads.borrow_mut()[0].read() | 0x40,
+            0x4017 => self.joypads.borrow_mut()[1].read() | 0x40,
             // TODO: APUの対応が必要
             _ => {
                 // デバイスではなくRAMから読み込む
                 self.ram[addr as usize]
             },
-        };
-        log::debug!("read: addr={:#06X}, data={:#04X}({})", addr, data, data);
-        data
+        }
     }
 }
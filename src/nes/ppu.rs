@@ -1,17 +1,42 @@
 //! NES PPU.
 
 mod ppu_state;
-mod vram;
+pub mod vram;
+pub mod palette;
+pub mod ntsc_filter;
+pub mod viewer;
+#[cfg(test)]
+mod tests;
 
 use bitflags::bitflags;
 use crate::nes::rom;
+use crate::nes::region::Region;
 use crate::nes::ppu_databus::*;
 use self::ppu_state::*;
 
 /// スプライト用メモリ容量(bytes)
 pub const SPR_RAM_SIZE: usize = 256;
-/// 起動後、レジスタが外部からの呼びかけに応答を開始するまでのクロック数
-const WARM_UP_TIME: u64 = 29658 * 3;
+/// OAMの1行(DRAMのリフレッシュ単位)のバイト数
+const OAM_ROW_SIZE: usize = 8;
+/// スプライトの属性(各スプライトの3バイト目)のうち、実在するbit。bit2-4は読むと常に0。
+const OAM_ATTRIBUTE_MASK: u8 = 0xE3;
+/// OAMはDRAMなので、リフレッシュされないまま放置するとこのクロック数(数ミリ秒)で値が失われる。
+const OAM_DECAY_CLOCKS: u64 = DOTS_PER_SCANLINE as u64 * 64;
+/// 値が失われたOAMのバイトの値
+const OAM_DECAYED_VALUE: u8 = 0xFF;
+/// PALのPPUは、VBlankが始まってからこのライン数が経つと、OAMを強制的にリフレッシュする。
+const PAL_OAM_REFRESH_DELAY: u16 = 24;
+/// 画面の横幅(ピクセル)
+pub const SCREEN_WIDTH: usize = 256;
+/// 画面の縦幅(ピクセル)
+pub const SCREEN_HEIGHT: usize = 240;
+/// 1スキャンラインあたりのドット(クロック)数
+pub const DOTS_PER_SCANLINE: u16 = 341;
+/// NMIの出力がこのクロック数だけ続いたら、CPUへNMIを通知する。
+/// VBlankフラグが立った直後に$2002を読むとNMIが抑止されるため、その猶予。
+const NMI_DELAY: u8 = 2;
+/// PPUのアドレスバスは14bitなので、v の上位bitはVRAMへのアクセスに使われない。
+const VRAM_ADDR_MASK: u16 = 0x3FFF;
 
 /*
 [背景の描画：大まかな流れ]
@@ -127,20 +152,122 @@ pub struct Registers {
     /// CPUとPPUのデータ転送に利用するバス。実体は8bitのラッチ。
     /// PPUSCROLLとPPUADDRに 2バイト分の書き込みを行うために存在する。
     pub latch: u8,
+    /// 内部レジスタ v: 現在のVRAMアドレス(15bit)。
+    /// PPUDATAの読み書きと、描画時のフェッチに利用される。
+    pub v: u16,
+    /// 内部レジスタ t: 一時VRAMアドレス(15bit)。
+    /// 画面左上のタイルの位置を保持し、描画中の適切なタイミングで v にコピーされる。
+    pub t: u16,
+    /// 内部レジスタ x: ファインXスクロール(3bit)
+    pub fine_x: u8,
+    /// 内部レジスタ w: PPUSCROLLとPPUADDRの書き込みトグル。次が2回目の書き込みならtrue。
+    pub w: bool,
+    /// PPUDATAの読み込みバッファ。
+    /// $0000-$3EFFの読み込みでは、前回の読み込みで取得した値が返される。
+    pub read_buffer: u8,
 }
 
+/*
+内部レジスタ v, t のビット構成：
+    yyy NN YYYYY XXXXX
+    ||| || ||||| +++++-- coarse X scroll
+    ||| || +++++-------- coarse Y scroll
+    ||| ++-------------- nametable select
+    +++----------------- fine Y scroll
+*/
+
+/// v, t のcoarse X
+const COARSE_X: u16 = 0x001F;
+/// v, t のcoarse Y
+const COARSE_Y: u16 = 0x03E0;
+/// v, t のネームテーブル選択
+const NAME_TABLE_SELECT: u16 = 0x0C00;
+/// v, t のfine Y
+const FINE_Y: u16 = 0x7000;
+
 impl Registers {
     /// PPUSTATUSの読み取りと、各種情報のリセット
-    pub fn read_status(&self) -> u8 {
-        // TODO: 読み込み時に以下が発生。
-        // ・ラッチの状態をクリア。    
-        // ・statusの7bit目を0にクリア。
-        0
+    pub fn read_status(&mut self) -> u8 {
+        // 下位5bitは何も繋がっていないので、直前にバスに乗っていた値(ラッチ)が見える。
+        let data = (self.status & 0xE0) | (self.latch & 0x1F);
+        // 読み込むとVBlankフラグはクリアされる。
+        self.status &= !StatusFlags::VBLANK_OCCURRED.bits();
+        // PPUSCROLL/PPUADDR の書き込みトグルもクリアされる。
+        self.w = false;
+        data
+    }
+
+    /// PPUCTRLへの書き込み。ネームテーブルの選択は t にも反映される。
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.ctrl = data;
+        let nt = (data & CtrlFlags::BASE_NAME_TABLE.bits()) as u16;
+        self.t = (self.t & !NAME_TABLE_SELECT) | (nt << 10);
+    }
+
+    /// PPUSCROLLへの書き込み。1回目はX、2回目はYのスクロール位置。
+    pub fn write_scroll(&mut self, data: u8) {
+        self.scroll = data;
+        let data = data as u16;
+        if !self.w {
+            self.t = (self.t & !COARSE_X) | (data >> 3);
+            self.fine_x = (data & 0x07) as u8;
+        } else {
+            self.t = (self.t & !(FINE_Y | COARSE_Y)) | ((data & 0x07) << 12) | ((data & 0xF8) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    /// PPUADDRへの書き込み。1回目は上位6bit、2回目は下位8bit。
+    /// 2回目の書き込みで t が v にコピーされる。
+    pub fn write_addr(&mut self, data: u8) {
+        self.addr = data;
+        let data = data as u16;
+        if !self.w {
+            // 最上位bit(15bit目)は0になる。
+            self.t = (self.t & 0x00FF) | ((data & 0x3F) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// v のcoarse Xを1つ進める。右端を超えたら水平方向のネームテーブルを切り替える。
+    pub fn increment_coarse_x(&mut self) {
+        if (self.v & COARSE_X) == 31 {
+            self.v &= !COARSE_X;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// v のfine Yを1つ進める。溢れたらcoarse Yを進め、
+    /// 下端(29行目)を超えたら垂直方向のネームテーブルを切り替える。
+    pub fn increment_y(&mut self) {
+        if (self.v & FINE_Y) != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let mut y = (self.v & COARSE_Y) >> 5;
+        if y == 29 {
+            y = 0;
+            self.v ^= 0x0800;
+        } else if y == 31 {
+            // 属性テーブルを指している場合は、ネームテーブルを切り替えずに0に戻る。
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.v = (self.v & !COARSE_Y) | (y << 5);
     }
 }
 
 pub struct Ppu {
     state: &'static PpuState,
+    /// 地域。1フレームのスキャンライン数やVBlankの開始位置が異なる。
+    region: Region,
     regs: Registers,
     /// スプライト用のメモリ(256バイト)。
     /// OAM(Object Attribute Memory)ともいう。
@@ -148,17 +275,45 @@ pub struct Ppu {
     spr_ram: Box<[u8]>,
     /// VRAMへのアクセスを司るコントローラ
     vram: Box<vram::MemCon>,
+    /// 描画結果。1ピクセルにつき1バイトで、パレットのインデックス(6bit)を保持する。
+    frame_buffer: Box<[u8]>,
     clock_counter: u64,
+    /// 次に処理するスキャンライン(NTSCでは0-261)
+    scanline: u16,
+    /// 次に処理するドット(0-340)
+    dot: u16,
+    /// 電源投入後に描画を完了したフレーム数
+    frame: u64,
+    /// 現在のフレームが奇数フレームならtrue
+    odd_frame: bool,
+    /// VBlankが始まる直前に$2002が読まれたため、このフレームではVBlankフラグを立てない。
+    vblank_suppressed: bool,
+    /// NMIの出力(VBLANK_OCCURRED && NMI_ON_VBRANK)が連続して有効だったクロック数
+    nmi_output_clocks: u8,
+    /// 描画が無効なまま放置されたOAMの値が失われるのを再現するならtrue。
+    oam_decay: bool,
+    /// OAMの各行が最後にリフレッシュされたクロック
+    oam_refreshed_at: [u64; SPR_RAM_SIZE / OAM_ROW_SIZE],
 }
 
 impl Ppu {
-    pub fn new(rom: &rom::NesRom) -> Ppu {
+    pub fn new(rom: &rom::NesRom, region: Region) -> Ppu {
         let mut my = Ppu {
             state: &STATE_IDLING,
+            region,
             regs: Default::default(),
             spr_ram: Box::new([0; SPR_RAM_SIZE]),
-            vram: Box::new(vram::MemCon::new(rom.mirroring_type())),
+            vram: Box::new(vram::MemCon::new(rom.mirroring_type(), rom.chr_rom().is_empty())),
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice(),
             clock_counter: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            vblank_suppressed: false,
+            nmi_output_clocks: 0,
+            oam_decay: false,
+            oam_refreshed_at: [0; SPR_RAM_SIZE / OAM_ROW_SIZE],
             //fn_step: Ppu::prepare_step,
             //state: Default::default(),
         };
@@ -173,39 +328,319 @@ impl Ppu {
             my.vram.raw_write(0, &chr_rom[0..len]);
         }
 
-        return my
+        my
     }
 
     pub fn power_on(&mut self) {
         // 電源ON時のPPU状態
         // https://wiki.nesdev.org/w/index.php/PPU_power_up_state
-
-        // TODO: 描画位置を0ピクセル目に移動する
-
-        // レジスタ等の初期化
-        // TODO: 規定クロック経過後はまた違う値を持つ可能性がある
+        // OAM、パレット、ネームテーブルの内容は不定なので、そのままにしておく。
         self.regs.ctrl = 0;
         self.regs.mask = 0;
-        self.regs.status = 0;
+        // VBlankフラグとスプライトオーバーフローは立っていることが多い(+0+x xxxx)。
+        self.regs.status = (StatusFlags::VBLANK_OCCURRED | StatusFlags::SPRITE_OVERFLOW).bits();
         self.regs.oam_addr = 0;
-        // !!!実装中!!!
+        self.regs.v = 0;
+        self.scanline = 0;
+        self.dot = 0;
+        self.frame = 0;
+        self.vblank_suppressed = false;
+        self.nmi_output_clocks = 0;
+        self.oam_refreshed_at.fill(self.clock_counter);
+        self.reset();
+    }
+
+    /// リセット。
+    /// 電源ON時と違い、PPUSTATUS、OAMADDR、v(PPUADDR)、描画位置は変化しない。
+    /// NES(前面挿入型)ではリセットボタンでPPUもリセットされるが、
+    /// ファミコンやNES(上面挿入型)ではPPUのリセット端子が繋がっておらず、リセットされない。
+    pub fn reset(&mut self) {
+        self.regs.ctrl = 0;
+        self.regs.mask = 0;
+        self.regs.t = 0;
+        self.regs.fine_x = 0;
+        self.regs.w = false;
+        self.regs.read_buffer = 0;
+        self.odd_frame = false;
+        // 次のpre-render lineが終わるまで、一部のレジスタへの書き込みを無視する。
+        self.state = &STATE_IDLING;
+    }
+
+    /// スプライト用のメモリ(OAM)。
+    pub fn spr_ram(&self) -> &[u8] {
+        &self.spr_ram
+    }
 
-        // TODO: そのままリセット処理まで実行
+    /// 描画が無効なまま数ミリ秒放置したOAMの値が失われる(DRAMの減衰)のを再現するか設定する。
+    /// 既定では再現しない。
+    pub fn set_oam_decay(&mut self, enabled: bool) {
+        self.oam_decay = enabled;
+    }
 
-        //self.fn_step = Ppu::prepare_step;
-        //self.state = Default::default();
+    /// VRAMのメモリコントローラー。
+    pub fn vram(&self) -> &vram::MemCon {
+        &self.vram
     }
-    
+
+    /// VRAMを副作用なしで書き換える。パターンテーブル(CHR-ROM)も書き換えられる。
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        self.vram.poke(addr & 0x3FFF, data);
+    }
+
+    /// OAMを副作用なしで書き換える。属性バイトの存在しないビットは0になる。
+    pub fn poke_oam(&mut self, addr: u8, data: u8) {
+        let addr = addr as usize;
+        self.spr_ram[addr] = if addr % 4 == 2 { data & OAM_ATTRIBUTE_MASK } else { data };
+    }
+
+    /// 描画結果(256x240)。各ピクセルはパレットのインデックス。
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    /// PPUMASKの現在の値。グレースケールと色強調の判定に使う。
+    pub fn mask(&self) -> u8 {
+        self.regs.mask
+    }
+
+    /// PPUCTRLの現在の値。
+    pub fn ctrl(&self) -> u8 {
+        self.regs.ctrl
+    }
+
+    /// 内部レジスタ v (現在のVRAMアドレス)。次のPPUDATAの読み書きはこのアドレスに行われる。
+    pub fn vram_addr(&self) -> u16 {
+        self.regs.v
+    }
+
+    /// 内部レジスタ t と x。フレームの先頭でのスクロール位置を表す。
+    pub fn scroll_origin(&self) -> (u16, u8) {
+        (self.regs.t, self.regs.fine_x)
+    }
+
     /// PPUを1クロック進める。
     /// NMI(vblank)が発生した場合はtrueを返す。
     pub fn step(&mut self) -> bool {
         self.clock_counter += 1;
+        let nmi = self.poll_nmi();
         //self.state.counter += 1;
         (self.state.step)(self);
+        // VBlankは起動直後(レジスタが応答しない期間)も発生する。
+        self.update_vblank();
+        self.update_oam();
+        self.next_dot();
         // print_ppu_state!(self);
-        false
+        nmi
+    }
+
+    /// NMIの出力を1クロック分サンプリングし、NMIを発生させるべきならtrueを返す。
+    /// NMIはVBlankの開始時だけでなく、VBlank中にNMI_ON_VBRANKが0から1に
+    /// 変わった場合にも発生する(出力の立ち上がりで発生する)。
+    fn poll_nmi(&mut self) -> bool {
+        let ctrl = CtrlFlags::from_bits_truncate(self.regs.ctrl);
+        let status = StatusFlags::from_bits_truncate(self.regs.status);
+        if ctrl.contains(CtrlFlags::NMI_ON_VBRANK) && status.contains(StatusFlags::VBLANK_OCCURRED) {
+            self.nmi_output_clocks = self.nmi_output_clocks.saturating_add(1);
+        } else {
+            self.nmi_output_clocks = 0;
+        }
+        self.nmi_output_clocks == NMI_DELAY
+    }
+
+    /// 現在のドットでのVBlankフラグの設定とクリア。
+    fn update_vblank(&mut self) {
+        if self.dot != 1 {
+            return;
+        }
+        match self.scanline {
+            line if line == self.region.vblank_scanline() => {
+                if !self.vblank_suppressed {
+                    self.regs.status |= StatusFlags::VBLANK_OCCURRED.bits();
+                }
+                self.vblank_suppressed = false;
+            },
+            line if line == self.region.pre_render_scanline() => {
+                self.regs.status &= !(StatusFlags::VBLANK_OCCURRED
+                    | StatusFlags::SPRITE_ZERO_HIT
+                    | StatusFlags::SPRITE_OVERFLOW).bits();
+            },
+            _ => (),
+        }
+    }
+
+    /// 描画中のOAMとOAMADDRの変化。
+    fn update_oam(&mut self) {
+        if self.is_rendering() {
+            match self.dot {
+                // 各ラインのスプライト評価でOAM全体が読まれるので、リフレッシュされる。
+                0 => self.refresh_oam(),
+                // 描画開始時にOAMADDRが8以上だと、OAMADDR & 0xF8 からの8バイトが
+                // OAMの先頭8バイトにコピーされてしまう。
+                1 if self.scanline == self.region.pre_render_scanline() => {
+                    let src = (self.regs.oam_addr & 0xF8) as usize;
+                    if src >= OAM_ROW_SIZE {
+                        self.spr_ram.copy_within(src..src + OAM_ROW_SIZE, 0);
+                    }
+                },
+                // 次のラインのスプライトのフェッチ中は、OAMADDRが0に戻される。
+                257..=320 => self.regs.oam_addr = 0,
+                _ => (),
+            }
+        } else if self.dot == 0 && self.region == Region::Pal &&
+            self.scanline >= self.region.vblank_scanline() + PAL_OAM_REFRESH_DELAY {
+            self.refresh_oam();
+        }
+    }
+
+    /// OAMの全ての行をリフレッシュする。
+    fn refresh_oam(&mut self) {
+        for row in 0..self.oam_refreshed_at.len() {
+            self.refresh_oam_row(row);
+        }
+    }
+
+    /// OAMの1行をリフレッシュする。
+    /// 前回のリフレッシュから時間が経ちすぎていた場合は、値が失われる。
+    fn refresh_oam_row(&mut self, row: usize) {
+        if self.oam_decay && self.clock_counter - self.oam_refreshed_at[row] > OAM_DECAY_CLOCKS {
+            self.spr_ram[row * OAM_ROW_SIZE..(row + 1) * OAM_ROW_SIZE].fill(OAM_DECAYED_VALUE);
+        }
+        self.oam_refreshed_at[row] = self.clock_counter;
+    }
+
+    /// 描画位置を1ドット進める。
+    /// NTSCの奇数フレームで描画が有効な場合は、pre-render lineの最後のドット(340)を飛ばす。
+    fn next_dot(&mut self) {
+        self.dot += 1;
+        let skip = self.scanline == self.region.pre_render_scanline() &&
+            self.dot == DOTS_PER_SCANLINE - 1 &&
+            self.odd_frame && self.region.skips_odd_frame_dot() && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || skip {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    /// 地域
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// 次に処理するスキャンライン(NTSCでは0-261)。最後のラインはpre-render line。
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// 次に処理するドット(0-340)。
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// 電源投入後に描画を完了したフレーム数。
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// 現在のフレームが奇数フレームならtrue。
+    pub fn is_odd_frame(&self) -> bool {
+        self.odd_frame
+    }
+
+    /// BGかスプライトの表示が有効ならtrue。
+    fn rendering_enabled(&self) -> bool {
+        let mask = MaskFlags::from_bits_truncate(self.regs.mask);
+        mask.intersects(MaskFlags::SHOW_BG | MaskFlags::SHOW_SPRITE)
+    }
+
+    /// 描画中(BGかスプライトの表示が有効で、可視スキャンラインかpre-render line)ならtrue。
+    fn is_rendering(&self) -> bool {
+        self.rendering_enabled() &&
+            (self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.region.pre_render_scanline())
+    }
+
+    /// PPUDATAの読み書き後に v を進める。
+    /// 描画中にアクセスすると、指定した増分ではなくcoarse XとYが同時に進んでしまう。
+    fn increment_vram_addr(&mut self) {
+        if self.is_rendering() {
+            self.regs.increment_coarse_x();
+            self.regs.increment_y();
+        } else {
+            let ctrl = CtrlFlags::from_bits_truncate(self.regs.ctrl);
+            let inc = if ctrl.contains(CtrlFlags::VRAM_INCREMENT) { 32 } else { 1 };
+            self.regs.v = self.regs.v.wrapping_add(inc) & 0x7FFF;
+        }
+    }
+
+    /// PPUDATAへの書き込み。v の指すVRAMに書き込む。
+    fn write_data(&mut self, data: u8) {
+        self.regs.data = data;
+        self.vram.write(self.regs.v & VRAM_ADDR_MASK, data);
+        self.increment_vram_addr();
+    }
+
+    /// PPUDATAの読み込み。
+    /// $0000-$3EFFは内部バッファを介するため、1回前の読み込みの値が返る。
+    /// パレット($3F00-$3FFF)はすぐに読めるが、バッファにはパレットの
+    /// 「下」にあるネームテーブルのミラー($2F00-$2FFF)の値が入る。
+    fn read_data(&mut self) -> u8 {
+        let addr = self.regs.v & VRAM_ADDR_MASK;
+        let data = if addr >= vram::PALETTE_BASE_ADDR {
+            // パレットは6bitなので、上位2bitはオープンバス。
+            self.regs.read_buffer = self.vram.read(addr - 0x1000);
+            (self.vram.read(addr) & 0x3F) | (self.regs.latch & 0xC0)
+        } else {
+            let data = self.regs.read_buffer;
+            self.regs.read_buffer = self.vram.read(addr);
+            data
+        };
+        self.increment_vram_addr();
+        data
+    }
+
+    /// OAMDATAへの書き込み。OAMADDRの指す位置に書き込み、OAMADDRを1進める。
+    /// 描画中は書き込まれず、OAMADDRの上位6bitだけが進む(スプライト1個分飛ぶ)。
+    fn write_oam_data(&mut self, data: u8) {
+        self.regs.oam_data = data;
+        if self.is_rendering() {
+            self.regs.oam_addr = self.regs.oam_addr.wrapping_add(4);
+            return;
+        }
+        let addr = self.regs.oam_addr as usize;
+        self.refresh_oam_row(addr / OAM_ROW_SIZE);
+        // 属性の未使用bitはOAMに存在しない。
+        self.spr_ram[addr] = if addr % 4 == 2 { data & OAM_ATTRIBUTE_MASK } else { data };
+        self.regs.oam_addr = self.regs.oam_addr.wrapping_add(1);
+    }
+
+    /// OAMDATAの読み込み。OAMADDRの指す位置の値を返す。OAMADDRは進まない。
+    /// 描画中のdot 1-64は、セカンダリOAMの初期化のため常に$FFが読める。
+    fn read_oam_data(&mut self) -> u8 {
+        if self.is_rendering() && (1..=64).contains(&self.dot) {
+            return 0xFF;
+        }
+        let addr = self.regs.oam_addr as usize;
+        self.refresh_oam_row(addr / OAM_ROW_SIZE);
+        self.spr_ram[addr]
+    }
+
+    /// PPUSTATUSの読み込み。
+    /// VBlankフラグが立つ1クロック前に読むと、フラグは0として読めて、
+    /// そのフレームではフラグもNMIも発生しない。
+    /// フラグが立ったのと同じクロックか1クロック後に読むと、フラグは1として
+    /// 読めるが、読み込みでクリアされるのでNMIは発生しない。
+    fn read_status(&mut self) -> u8 {
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.vblank_suppressed = true;
+        }
+        self.regs.read_status()
     }
 
+    #[allow(dead_code)]
     fn render() {
         // TODO: PPUはCPUと独立したクロックカウンターを持ち、
         // そのクロックを基準として動く(CPUに合わせて3倍にはしない)
@@ -262,7 +697,7 @@ impl Ppu {
         //      次のラインの最初の8ピクセル分を先読みしている。
         //      280-304ピクセルの間に、レンダリングが有効になっている場合、
         //      垂直スクロールビットがリロードされる。
-        //      TODO: 奇数フレームか偶数フレームかで処理が異なる。
+        //      奇数フレームで描画が有効な場合は、このラインの最後のドット(340)が飛ばされる。
         // [line 0-239]
         //      可視のスキャンライン。描画を行う。基本的にこの間PPUを触ってはいけない。
         // [line 240]
@@ -309,6 +744,7 @@ impl PpuDataBus for Ppu {
     }
 
     fn dma_write(&mut self, data: u8) {
-        self.dma_write(data);
+        // OAMDMAによる転送は、OAMDATAへの書き込みと同じく、OAMADDRの指す位置から順に書き込まれる。
+        self.write_oam_data(data);
     }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+//! NTSCのコンポジット映像信号を模したフィルター。
+//!
+//! PPUの出力(パレットのインデックスと色強調ビット)から、実際にPPUが出力する信号を
+//! 1ピクセルあたり8サンプル(マスタークロック単位)で合成し、それをテレビと同じように
+//! 輝度(Y)と色差(I, Q)に分離してRGBに戻す。
+//! 輝度と色差の分離が不完全なため、ドットクロールや色にじみが再現される。
+//! blargg氏の nes_ntsc と同じ考え方だが、フィルターはずっと単純な矩形窓にしている。
+
+use super::{MaskFlags, SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::palette::{self, NtscParams, Palette, PHASES};
+
+/// 1ピクセルあたりの信号のサンプル数(PPUの1ドット = マスタークロック8つ)
+const SAMPLES_PER_PIXEL: usize = 8;
+/// 1スキャンライン(341ドット)ごとに進む、色副搬送波の位相(341 * 8 % 12)
+const PHASES_PER_SCANLINE: usize = 341 * SAMPLES_PER_PIXEL % PHASES;
+/// 1ピクセルあたりの出力ピクセル数
+const OUTPUT_PER_PIXEL: usize = 2;
+/// 出力画像の横幅(ピクセル)
+pub const OUTPUT_WIDTH: usize = SCREEN_WIDTH * OUTPUT_PER_PIXEL;
+/// 出力画像の縦幅(ピクセル)
+pub const OUTPUT_HEIGHT: usize = SCREEN_HEIGHT;
+
+/// 映像の接続方法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtscPreset {
+    /// コンポジット(RCA)。輝度と色差が1本の信号に混ざっているため、
+    /// ドットクロールや色にじみが最も強く出る。
+    Composite,
+    /// S端子。輝度と色差は別々の信号なので、ドットクロールは出ないが色はにじむ。
+    SVideo,
+    /// RGB。信号の劣化がなく、パレットの色がそのまま出る。
+    Rgb,
+}
+
+impl NtscPreset {
+    /// "composite", "svideo", "rgb" から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "composite" => Some(NtscPreset::Composite),
+            "svideo" | "s-video" => Some(NtscPreset::SVideo),
+            "rgb" => Some(NtscPreset::Rgb),
+            _ => None,
+        }
+    }
+
+    /// 輝度と色差を取り出す際の窓の幅(サンプル数)。
+    /// 輝度の窓が色副搬送波の1周期(12サンプル)より短いと、色信号が輝度に漏れる。
+    fn windows(self) -> (usize, usize) {
+        match self {
+            NtscPreset::Composite => (6, 24),
+            NtscPreset::SVideo => (4, 24),
+            NtscPreset::Rgb => (1, 1),
+        }
+    }
+}
+
+pub struct NtscFilter {
+    preset: NtscPreset,
+    params: NtscParams,
+    /// RGB用。信号を経由せずに色を引く。
+    palette: Palette,
+    /// 色ごと((強調ビット << 6) | インデックス)、位相ごとの信号レベル
+    levels: Vec<[f32; PHASES]>,
+    /// 位相ごとの復調の角度の cos と sin
+    carrier: [(f32, f32); PHASES],
+    /// 1スキャンライン分の作業領域
+    signal: Vec<f32>,
+    luma: Vec<f32>,
+    /// 累積和(窓の平均を求めるため)
+    sum_y: Vec<f32>,
+    sum_i: Vec<f32>,
+    sum_q: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(preset: NtscPreset, params: NtscParams) -> Self {
+        let samples = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+        let levels = (0..palette::EMPHASIS_COLORS)
+            .map(|i| {
+                let (index, emphasis) = ((i % palette::COLORS) as u8, (i / palette::COLORS) as u8);
+                let mut levels = [0.0; PHASES];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = palette::composite_level(index, emphasis, phase);
+                }
+                levels
+            })
+            .collect();
+        let mut carrier = [(0.0, 0.0); PHASES];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle = palette::chroma_angle(phase, &params);
+            *c = (angle.cos(), angle.sin());
+        }
+        NtscFilter {
+            preset,
+            params,
+            palette: Palette::generate(&params),
+            levels,
+            carrier,
+            signal: vec![0.0; samples],
+            luma: vec![0.0; samples],
+            sum_y: vec![0.0; samples + 1],
+            sum_i: vec![0.0; samples + 1],
+            sum_q: vec![0.0; samples + 1],
+        }
+    }
+
+    pub fn preset(&self) -> NtscPreset {
+        self.preset
+    }
+
+    /// フレームバッファ(パレットのインデックス)を、RGBA(1ピクセル4バイト、
+    /// OUTPUT_WIDTH x OUTPUT_HEIGHT)に変換する。
+    /// 色副搬送波の位相はフレームごとに3通りに変わるので、frame_count で指定する。
+    pub fn filter(&mut self, frame: &[u8], mask: u8, frame_count: u64, out: &mut [u8]) {
+        let emphasis = mask >> 5;
+        // グレースケールでは、色相(下位4bit)が0の列だけが使われる。
+        let index_mask = if MaskFlags::from_bits_truncate(mask).contains(MaskFlags::GRAYSCALE) { 0x30 } else { 0x3F };
+        let frame_phase = (frame_count % 3) as usize * PHASES_PER_SCANLINE;
+        let rows = frame.chunks_exact(SCREEN_WIDTH).zip(out.chunks_exact_mut(OUTPUT_WIDTH * 4));
+        for (line, (pixels, out)) in rows.enumerate() {
+            if self.preset == NtscPreset::Rgb {
+                self.filter_rgb(pixels, mask, out);
+            } else {
+                let phase = (frame_phase + line * PHASES_PER_SCANLINE) % PHASES;
+                self.filter_line(pixels, index_mask, emphasis, phase, out);
+            }
+        }
+    }
+
+    /// RGBでは信号の劣化がないので、パレットの色を引き伸ばすだけ。
+    fn filter_rgb(&self, pixels: &[u8], mask: u8, out: &mut [u8]) {
+        for (&index, out) in pixels.iter().zip(out.chunks_exact_mut(OUTPUT_PER_PIXEL * 4)) {
+            let [r, g, b] = self.palette.color(index, mask);
+            for pixel in out.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[r, g, b, 0xFF]);
+            }
+        }
+    }
+
+    /// 1スキャンライン分の信号を合成し、復調する。
+    /// index_mask はパレットのインデックスに適用するマスク(グレースケールなら0x30)。
+    fn filter_line(&mut self, pixels: &[u8], index_mask: u8, emphasis: u8, line_phase: usize, out: &mut [u8]) {
+        // 信号の合成
+        for (x, &index) in pixels.iter().enumerate() {
+            let levels = &self.levels[((emphasis as usize) << 6) | (index & index_mask) as usize];
+            let start = x * SAMPLES_PER_PIXEL;
+            for k in 0..SAMPLES_PER_PIXEL {
+                self.signal[start + k] = levels[(start + k + line_phase) % PHASES];
+            }
+            // S端子の輝度信号は色信号を含まない。色副搬送波の1周期分の平均レベルとなる。
+            let luma = levels.iter().sum::<f32>() / PHASES as f32;
+            self.luma[start..start + SAMPLES_PER_PIXEL].fill(luma);
+        }
+
+        // 輝度と色差の累積和
+        let svideo = self.preset == NtscPreset::SVideo;
+        for (s, (&signal, &luma)) in self.signal.iter().zip(self.luma.iter()).enumerate() {
+            let (cos, sin) = self.carrier[(s + line_phase) % PHASES];
+            // コンポジットでは信号全体を、S端子では輝度と色を別々に復調する。
+            let (y, chroma) = if svideo { (luma, signal - luma) } else { (signal, signal) };
+            self.sum_y[s + 1] = self.sum_y[s] + y;
+            self.sum_i[s + 1] = self.sum_i[s] + chroma * cos;
+            self.sum_q[s + 1] = self.sum_q[s] + chroma * sin;
+        }
+
+        // 復調。出力ピクセルの中心のサンプルを中心とした窓で平均を取る。
+        let (luma_window, chroma_window) = self.preset.windows();
+        let step = SAMPLES_PER_PIXEL / OUTPUT_PER_PIXEL;
+        for (j, pixel) in out.chunks_exact_mut(4).enumerate() {
+            let center = j * step + step / 2;
+            let y = window_mean(&self.sum_y, center, luma_window);
+            let i = window_mean(&self.sum_i, center, chroma_window);
+            let q = window_mean(&self.sum_q, center, chroma_window);
+            let [r, g, b] = palette::yiq_to_rgb(y, i, q, &self.params);
+            pixel.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+}
+
+/// 累積和 sum から、center を中心とした幅 width の窓の平均を求める。
+/// 窓がスキャンラインからはみ出す場合は、内側に寄せる。
+fn window_mean(sum: &[f32], center: usize, width: usize) -> f32 {
+    let len = sum.len() - 1;
+    let start = center.saturating_sub(width / 2).min(len - width);
+    (sum[start + width] - sum[start]) / width as f32
+}
@@ -0,0 +1,251 @@
+//! パレットのインデックスからRGBへの変換。
+//!
+//! PPUが出力するのは6bitのパレットのインデックス($00-$3F)で、実際の色は
+//! PPUの出力するNTSC信号をテレビがどう解釈するかで決まる。
+//! そのため「正しい」パレットは存在せず、エミュレーターやキャプチャーボードによって
+//! 色味が異なる。ここでは組み込みの2C02用パレットに加え、一般的な .pal ファイルを読み込める。
+
+use std::error::Error;
+use std::fs;
+
+use crate::nes::util;
+use super::MaskFlags;
+
+/// RGBの1色
+pub type Rgb = [u8; 3];
+
+/// パレットの色数(6bit)
+pub const COLORS: usize = 64;
+/// 色強調ビット(3bit)を含めた色数
+pub const EMPHASIS_COLORS: usize = COLORS * 8;
+
+/// 色強調を行った際、強調されない側の色の減衰率。
+/// 64色のパレットから、512色分の色を作るのに利用する。
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// 組み込みの2C02用パレット。
+/// https://wiki.nesdev.org/w/index.php/PPU_palettes
+const PALETTE_2C02: [Rgb; COLORS] = [
+    [ 84,  84,  84], [  0,  30, 116], [  8,  16, 144], [ 48,   0, 136],
+    [ 68,   0, 100], [ 92,   0,  48], [ 84,   4,   0], [ 60,  24,   0],
+    [ 32,  42,   0], [  8,  58,   0], [  0,  64,   0], [  0,  60,   0],
+    [  0,  50,  60], [  0,   0,   0], [  0,   0,   0], [  0,   0,   0],
+
+    [152, 150, 152], [  8,  76, 196], [ 48,  50, 236], [ 92,  30, 228],
+    [136,  20, 176], [160,  20, 100], [152,  34,  32], [120,  60,   0],
+    [ 84,  90,   0], [ 40, 114,   0], [  8, 124,   0], [  0, 118,  40],
+    [  0, 102, 120], [  0,   0,   0], [  0,   0,   0], [  0,   0,   0],
+
+    [236, 238, 236], [ 76, 154, 236], [120, 124, 236], [176,  98, 236],
+    [228,  84, 236], [236,  88, 180], [236, 106, 100], [212, 136,  32],
+    [160, 170,   0], [116, 196,   0], [ 76, 208,  32], [ 56, 204, 108],
+    [ 56, 180, 204], [ 60,  60,  60], [  0,   0,   0], [  0,   0,   0],
+
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236],
+    [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180],
+    [160, 214, 228], [160, 162, 160], [  0,   0,   0], [  0,   0,   0],
+];
+
+/// パレットのインデックスと、PPUMASKの色強調ビットから色を引く変換表。
+/// 並びは .pal ファイルと同じで、(強調ビット << 6) | インデックス の順。
+/// 強調ビットは bit0 = 赤、bit1 = 緑、bit2 = 青。
+pub struct Palette {
+    colors: Box<[Rgb]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_colors(&PALETTE_2C02).expect("the built-in palette has 64 colors")
+    }
+}
+
+impl Palette {
+    /// 組み込みの2C02用パレット。
+    pub fn ntsc_2c02() -> Self {
+        Default::default()
+    }
+
+    /// 64色または512色の色からパレットを作る。
+    /// 64色の場合、色強調時の色は強調されない色を減衰させて作る。
+    pub fn from_colors(colors: &[Rgb]) -> Result<Self, Box<dyn Error>> {
+        let colors = match colors.len() {
+            EMPHASIS_COLORS => colors.to_vec(),
+            COLORS => (0..EMPHASIS_COLORS)
+                .map(|i| emphasize(colors[i % COLORS], (i / COLORS) as u8))
+                .collect(),
+            len => return Err(util::Error::new(format!(
+                "Invalid palette size: {} colors (expected {} or {})", len, COLORS, EMPHASIS_COLORS))),
+        };
+        Ok(Palette { colors: colors.into_boxed_slice() })
+    }
+
+    /// .pal ファイルの内容(RGBの3バイトが64個または512個並んだもの)からパレットを作る。
+    pub fn from_pal(bin: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bin.len() != COLORS * 3 && bin.len() != EMPHASIS_COLORS * 3 {
+            return Err(util::Error::new(format!(
+                "Invalid palette size: {} bytes (expected {} or {})",
+                bin.len(), COLORS * 3, EMPHASIS_COLORS * 3)));
+        }
+        let colors: Vec<Rgb> = bin.chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        Self::from_colors(&colors)
+    }
+
+    /// .pal ファイルを読み込む。
+    pub fn load_from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let bin = match fs::read(path) {
+            Ok(bin) => bin,
+            Err(err) => return Err(util::Error::new(format!("[{}] {}", path, err))),
+        };
+        Self::from_pal(&bin).map_err(|err| util::Error::new(format!("[{}] {}", path, err)))
+    }
+
+    /// パレットのインデックスを、PPUMASKのグレースケール・色強調ビットを適用した色に変換する。
+    pub fn color(&self, index: u8, mask: u8) -> Rgb {
+        let mut index = index & 0x3F;
+        if MaskFlags::from_bits_truncate(mask).contains(MaskFlags::GRAYSCALE) {
+            // グレースケールでは、色相(下位4bit)が0の列だけが使われる。
+            index &= 0x30;
+        }
+        let emphasis = (mask >> 5) as usize;
+        self.colors[(emphasis << 6) | index as usize]
+    }
+
+    /// フレームバッファ(パレットのインデックス)を、RGBA(1ピクセル4バイト)に変換する。
+    pub fn to_rgba(&self, frame: &[u8], mask: u8, out: &mut [u8]) {
+        for (&index, pixel) in frame.iter().zip(out.chunks_exact_mut(4)) {
+            let [r, g, b] = self.color(index, mask);
+            pixel.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+}
+
+/// 色強調ビットに応じて、強調されない色を減衰させる。
+fn emphasize(color: Rgb, emphasis: u8) -> Rgb {
+    let mut rgb = color.map(|c| c as f32);
+    for channel in 0..3 {
+        if emphasis & (1 << channel) != 0 {
+            for (i, c) in rgb.iter_mut().enumerate() {
+                if i != channel {
+                    *c *= EMPHASIS_ATTENUATION;
+                }
+            }
+        }
+    }
+    rgb.map(|c| c.round() as u8)
+}
+
+//////////////////////////////////////////////
+// NTSC信号からのパレットの生成
+//////////////////////////////////////////////
+
+/// 2C02の出力する信号の電圧。輝度(インデックスの上位2bit)ごとの、矩形波の低い側と高い側。
+/// https://wiki.nesdev.org/w/index.php/NTSC_video
+const SIGNAL_LOW: [f32; 4] = [0.228, 0.312, 0.552, 0.880];
+const SIGNAL_HIGH: [f32; 4] = [0.616, 0.840, 1.100, 1.100];
+/// 黒($0F)の電圧
+const SIGNAL_BLACK: f32 = 0.312;
+/// 白($20)の電圧
+const SIGNAL_WHITE: f32 = 1.100;
+/// 色強調ビットによる信号の減衰率
+const SIGNAL_ATTENUATION: f32 = 0.746;
+/// 色副搬送波1周期あたりのサンプル数(2C02は12相で色相を作る)
+pub(super) const PHASES: usize = 12;
+/// 色副搬送波の位相とYIQの軸とのずれ(相の数)。色相$6が赤、$A が緑、$2 が青になるように合わせる。
+const PHASE_OFFSET: f32 = 4.0;
+
+/// パレットを生成する際の、テレビの調整つまみに相当するパラメータ。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscParams {
+    /// 色相の回転(度)
+    pub hue: f32,
+    /// 彩度の倍率
+    pub saturation: f32,
+    /// コントラストの倍率
+    pub contrast: f32,
+    /// 明るさの加算値(-1.0〜1.0)
+    pub brightness: f32,
+    /// ガンマ値。1.0なら補正しない。大きいほど中間色が明るくなる。
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Palette {
+    /// 2C02の出力するコンポジット信号をデコードして、512色のパレットを生成する。
+    pub fn generate(params: &NtscParams) -> Self {
+        let colors: Vec<Rgb> = (0..EMPHASIS_COLORS)
+            .map(|i| decode_ntsc((i % COLORS) as u8, (i / COLORS) as u8, params))
+            .collect();
+        Self::from_colors(&colors).expect("generated palettes have 512 colors")
+    }
+}
+
+/// 1色分の信号を12相でサンプリングし、YIQを経由してRGBに変換する。
+fn decode_ntsc(index: u8, emphasis: u8, params: &NtscParams) -> Rgb {
+    let (mut y, mut i, mut q) = (0.0f32, 0.0f32, 0.0f32);
+    for phase in 0..PHASES {
+        let level = composite_level(index, emphasis, phase);
+        let angle = chroma_angle(phase, params);
+        y += level;
+        i += level * angle.cos();
+        q += level * angle.sin();
+    }
+    let n = PHASES as f32;
+    yiq_to_rgb(y / n, i / n, q / n, params)
+}
+
+/// パレットのインデックスの色を、色副搬送波の位相 phase (0-11) で出力したときの信号レベル。
+/// 黒が0.0、白が1.0になるように正規化している。
+pub(super) fn composite_level(index: u8, emphasis: u8, phase: usize) -> f32 {
+    let hue = (index & 0x0F) as usize;
+    let luma = ((index >> 4) & 0x03) as usize;
+    // 色相0は高い側、色相13は低い側の電圧のみ。色相14, 15は黒。
+    let (low, high) = match hue {
+        0x00 => (SIGNAL_HIGH[luma], SIGNAL_HIGH[luma]),
+        0x0D => (SIGNAL_LOW[luma], SIGNAL_LOW[luma]),
+        0x0E | 0x0F => (SIGNAL_BLACK, SIGNAL_BLACK),
+        _ => (SIGNAL_LOW[luma], SIGNAL_HIGH[luma]),
+    };
+    let in_color_phase = |color: usize| (color + phase) % PHASES < PHASES / 2;
+
+    let mut signal = if in_color_phase(hue) { high } else { low };
+    // 色強調ビットは、赤・緑・青それぞれの色相(12, 4, 8)の位相で信号を減衰させる。
+    let attenuated = hue < 0x0E && [12, 4, 8].iter().enumerate()
+        .any(|(bit, &color)| emphasis & (1 << bit) != 0 && in_color_phase(color));
+    if attenuated {
+        signal *= SIGNAL_ATTENUATION;
+    }
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// 位相 phase (0-11) のサンプルから色差(I, Q)を取り出す際の、復調の角度。
+pub(super) fn chroma_angle(phase: usize, params: &NtscParams) -> f32 {
+    std::f32::consts::PI * (phase as f32 + PHASE_OFFSET) / 6.0 + params.hue.to_radians()
+}
+
+/// YIQをRGBに変換する。彩度・コントラスト・明るさ・ガンマはここで適用する。
+pub(super) fn yiq_to_rgb(y: f32, i: f32, q: f32, params: &NtscParams) -> Rgb {
+    let y = y * params.contrast + params.brightness;
+    let i = i * params.saturation * params.contrast;
+    let q = q * params.saturation * params.contrast;
+
+    let rgb = [
+        y + 0.946882 * i + 0.623557 * q,
+        y - 0.274788 * i - 0.635691 * q,
+        y - 1.108545 * i + 1.709007 * q,
+    ];
+    rgb.map(|c| (c.clamp(0.0, 1.0).powf(1.0 / params.gamma) * 255.0).round() as u8)
+}
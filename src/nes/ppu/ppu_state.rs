@@ -1,6 +1,6 @@
 //! PPUの内部状態
 
-use super::{Ppu, WARM_UP_TIME};
+use super::{Ppu, DOTS_PER_SCANLINE};
 use crate::nes::ppu_databus::*;
 
 pub struct PpuState {
@@ -22,8 +22,11 @@ pub const STATE_READY: PpuState = PpuState {
 };
 
 impl Ppu {
+    /// 電源ON・リセット後、最初のpre-render lineが終わるまでは書き込みを受け付けない。
+    /// 電源ONからはCPUで約29658クロック。
+    /// (この間は描画が無効なので、奇数フレームでも最後のドットは飛ばされない)
     pub fn step_idling(&mut self) {
-        if self.clock_counter > WARM_UP_TIME {
+        if self.scanline == self.region.pre_render_scanline() && self.dot == DOTS_PER_SCANLINE - 1 {
             self.state = &STATE_READY;
         }
     }
@@ -32,8 +35,9 @@ impl Ppu {
         
     }
 
-    /// 起動直後のPPUレジスタへの書き込み。
+    /// 起動直後(電源ON・リセットから最初のpre-render lineの終わりまで)のPPUレジスタへの書き込み。
     /// PPUCTRL, PPUMASK, PPUSCROLL, PPUADDR への書き込みは無視される。
+    /// (PPUDATAへの書き込みは、その時点の v の指す位置に行われる。)
     pub fn write_idling(&mut self, reg_type: PpuRegs, data: u8) {
         // バスを介した書き込みを行うと、ラッチも必ず更新される。
         self.regs.latch = data;
@@ -41,12 +45,12 @@ impl Ppu {
         match reg_type {
             PpuRegs::Status => (), // PPUSTATUSは読み込み専用
             PpuRegs::OamAddr => self.regs.oam_addr = data,
-            PpuRegs::OamData => self.regs.oam_data = data,
-            PpuRegs::PpuAddr => (),
+            PpuRegs::OamData => self.write_oam_data(data),
+            PpuRegs::PpuData => self.write_data(data),
             PpuRegs::Ctrl |
             PpuRegs::Mask |
             PpuRegs::Scroll |
-            PpuRegs::PpuData => (),
+            PpuRegs::PpuAddr => (),
         };
     }
 
@@ -57,14 +61,14 @@ impl Ppu {
         self.regs.latch = data;
         // PPUのレジスタへの値の設定、かつミラー領域への反映
         match reg_type {
-            PpuRegs::Ctrl => self.regs.ctrl = data,
+            PpuRegs::Ctrl => self.regs.write_ctrl(data),
             PpuRegs::Mask => self.regs.mask = data,
             PpuRegs::Status => (), // PPUSTATUSは読み込み専用
             PpuRegs::OamAddr => self.regs.oam_addr = data,
-            PpuRegs::OamData => self.regs.oam_data = data,
-            PpuRegs::Scroll => self.regs.scroll = data,
-            PpuRegs::PpuAddr => self.regs.addr = data,
-            PpuRegs::PpuData => self.regs.data = data,
+            PpuRegs::OamData => self.write_oam_data(data),
+            PpuRegs::Scroll => self.regs.write_scroll(data),
+            PpuRegs::PpuAddr => self.regs.write_addr(data),
+            PpuRegs::PpuData => self.write_data(data),
         };
     }
 
@@ -75,12 +79,12 @@ impl Ppu {
         self.regs.latch = match reg_type {
             PpuRegs::Ctrl => self.regs.latch,
             PpuRegs::Mask => self.regs.latch,
-            PpuRegs::Status => self.regs.status,
+            PpuRegs::Status => self.read_status(),
             PpuRegs::OamAddr => self.regs.latch,
-            PpuRegs::OamData => self.regs.oam_data,
+            PpuRegs::OamData => self.read_oam_data(),
             PpuRegs::Scroll => self.regs.latch,
             PpuRegs::PpuAddr => self.regs.latch,
-            PpuRegs::PpuData => self.regs.data,
+            PpuRegs::PpuData => self.read_data(),
         };
         self.regs.latch
     }
@@ -89,12 +93,12 @@ impl Ppu {
         self.regs.latch = match reg_type {
             PpuRegs::Ctrl => self.regs.latch,
             PpuRegs::Mask => self.regs.latch,
-            PpuRegs::Status => self.regs.read_status(),
+            PpuRegs::Status => self.read_status(),
             PpuRegs::OamAddr => self.regs.latch,
-            PpuRegs::OamData => self.regs.oam_data,
+            PpuRegs::OamData => self.read_oam_data(),
             PpuRegs::Scroll => self.regs.latch,
             PpuRegs::PpuAddr => self.regs.latch,
-            PpuRegs::PpuData => self.regs.data,
+            PpuRegs::PpuData => self.read_data(),
         };
         self.regs.latch
     }
@@ -0,0 +1,534 @@
+//! PPUのテスト。
+
+use super::*;
+
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const SCANLINES_PER_FRAME: u16 = 262;
+
+/// NROM(PRG-ROM 16KB, CHR-ROM 8KB)の空のROMでPPUを作り、
+/// レジスタが応答するようになった後のフレームの先頭まで進める。
+fn ready_ppu() -> Ppu {
+    ready_ppu_in(Region::Ntsc)
+}
+
+fn ready_ppu_in(region: Region) -> Ppu {
+    make_ready(new_ppu(region))
+}
+
+/// ヘッダのフラグ6とCHR-ROMのバンク数を指定したNROMで、ready_ppu と同じ状態のPPUを作る。
+fn ready_ppu_with(flags6: u8, chr_banks: u8) -> Ppu {
+    make_ready(new_ppu_with(Region::Ntsc, flags6, chr_banks))
+}
+
+fn make_ready(mut ppu: Ppu) -> Ppu {
+    ppu.power_on();
+    // 最初のpre-render lineが終わると、レジスタが応答するようになる。
+    while ppu.frame() == 0 {
+        ppu.step();
+    }
+    ppu
+}
+
+fn new_ppu(region: Region) -> Ppu {
+    new_ppu_with(region, 0, 1)
+}
+
+fn new_ppu_with(region: Region, flags6: u8, chr_banks: u8) -> Ppu {
+    let mut bin = vec![0x4E, 0x45, 0x53, 0x1A, 1, chr_banks, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    bin.resize(16 + rom::PRG_ROM_UNIT_SIZE + rom::CHR_ROM_UNIT_SIZE * chr_banks as usize, 0);
+    let rom = rom::parse(&bin).unwrap();
+    Ppu::new(&rom, region)
+}
+
+/// 次に処理する位置が (scanline, dot) になるまで進める。途中でNMIが発生したらtrueを返す。
+fn run_to(ppu: &mut Ppu, scanline: u16, dot: u16) -> bool {
+    let mut nmi = false;
+    while (ppu.scanline, ppu.dot) != (scanline, dot) {
+        nmi |= ppu.step();
+    }
+    nmi
+}
+
+fn vblank(ppu: &Ppu) -> bool {
+    ppu.regs.status & StatusFlags::VBLANK_OCCURRED.bits() != 0
+}
+
+#[test]
+fn vblank_is_set_at_241_1_and_cleared_on_pre_render_line() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+
+    assert!(!run_to(&mut ppu, VBLANK_SCANLINE, 1));
+    assert!(!vblank(&ppu));
+    ppu.step();
+    assert!(vblank(&ppu));
+    assert!(run_to(&mut ppu, VBLANK_SCANLINE + 1, 0));
+
+    run_to(&mut ppu, PRE_RENDER_SCANLINE, 1);
+    assert!(vblank(&ppu));
+    ppu.step();
+    assert!(!vblank(&ppu));
+}
+
+#[test]
+fn no_nmi_while_disabled() {
+    let mut ppu = ready_ppu();
+    assert!(!run_to(&mut ppu, VBLANK_SCANLINE + 1, 0));
+    assert!(vblank(&ppu));
+}
+
+#[test]
+fn enabling_nmi_during_vblank_raises_nmi() {
+    let mut ppu = ready_ppu();
+    run_to(&mut ppu, VBLANK_SCANLINE + 5, 0);
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert!(run_to(&mut ppu, VBLANK_SCANLINE + 5, 10));
+
+    // 一度0に戻してから再度1にすると、もう一度NMIが発生する。
+    ppu.write(PpuRegs::Ctrl, 0);
+    ppu.step();
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert!(run_to(&mut ppu, VBLANK_SCANLINE + 5, 20));
+}
+
+#[test]
+fn reading_status_clears_vblank() {
+    let mut ppu = ready_ppu();
+    ppu.regs.latch = 0x1F;
+    run_to(&mut ppu, VBLANK_SCANLINE + 1, 0);
+    assert_eq!(ppu.read(PpuRegs::Status), 0x80 | 0x1F);
+    assert_eq!(ppu.read(PpuRegs::Status) & 0x80, 0);
+}
+
+#[test]
+fn reading_status_just_before_vblank_suppresses_flag_and_nmi() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    run_to(&mut ppu, VBLANK_SCANLINE, 1);
+    assert_eq!(ppu.read(PpuRegs::Status) & 0x80, 0);
+    assert!(!run_to(&mut ppu, VBLANK_SCANLINE + 1, 0));
+    assert!(!vblank(&ppu));
+
+    // 次のフレームでは通常通り発生する。
+    run_to(&mut ppu, VBLANK_SCANLINE, 0);
+    assert!(run_to(&mut ppu, VBLANK_SCANLINE + 1, 0));
+}
+
+#[test]
+fn reading_status_on_vblank_dot_suppresses_nmi() {
+    for delay in [2, 3] {
+        let mut ppu = ready_ppu();
+        ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+        run_to(&mut ppu, VBLANK_SCANLINE, delay);
+        assert_eq!(ppu.read(PpuRegs::Status) & 0x80, 0x80);
+        assert!(!run_to(&mut ppu, VBLANK_SCANLINE + 1, 0));
+    }
+
+    // それより後に読んだ場合はNMIが発生する。
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert!(run_to(&mut ppu, VBLANK_SCANLINE, 4));
+    assert_eq!(ppu.read(PpuRegs::Status) & 0x80, 0x80);
+}
+
+fn set_vram_addr(ppu: &mut Ppu, addr: u16) {
+    ppu.write(PpuRegs::PpuAddr, (addr >> 8) as u8);
+    ppu.write(PpuRegs::PpuAddr, addr as u8);
+}
+
+#[test]
+fn ppudata_reads_are_buffered() {
+    let mut ppu = ready_ppu();
+    set_vram_addr(&mut ppu, 0x2100);
+    for data in [0x11, 0x22, 0x33] {
+        ppu.write(PpuRegs::PpuData, data);
+    }
+    set_vram_addr(&mut ppu, 0x2100);
+    // 最初の読み込みでは、前回バッファに入った値(ここでは0)が返る。
+    assert_eq!(ppu.read(PpuRegs::PpuData), 0x00);
+    assert_eq!(ppu.read(PpuRegs::PpuData), 0x11);
+    assert_eq!(ppu.read(PpuRegs::PpuData), 0x22);
+    assert_eq!(ppu.regs.v, 0x2103);
+}
+
+#[test]
+fn vram_increment_by_32() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::VRAM_INCREMENT.bits());
+    set_vram_addr(&mut ppu, 0x2000);
+    ppu.write(PpuRegs::PpuData, 0xAA);
+    ppu.write(PpuRegs::PpuData, 0xBB);
+    assert_eq!(ppu.regs.v, 0x2040);
+    assert_eq!(ppu.vram.read(0x2000), 0xAA);
+    assert_eq!(ppu.vram.read(0x2020), 0xBB);
+}
+
+#[test]
+fn chr_ram_is_writable_and_chr_rom_is_not() {
+    // CHR-ROMが無ければCHR-RAM
+    let mut ppu = ready_ppu_with(0, 0);
+    set_vram_addr(&mut ppu, 0x1FF0);
+    ppu.write(PpuRegs::PpuData, 0x3C);
+    assert_eq!(ppu.vram.read(0x1FF0), 0x3C);
+
+    let mut ppu = ready_ppu();
+    set_vram_addr(&mut ppu, 0x1FF0);
+    ppu.write(PpuRegs::PpuData, 0x3C);
+    assert_eq!(ppu.vram.read(0x1FF0), 0x00);
+}
+
+#[test]
+fn horizontal_and_vertical_nametables_are_mirrored() {
+    // フラグ6のbit0が0なら水平ミラーリング、1なら垂直ミラーリング
+    for (flags6, expected) in [(0x00, [0x11, 0x11, 0x13, 0x13]), (0x01, [0x12, 0x13, 0x12, 0x13])] {
+        let mut ppu = ready_ppu_with(flags6, 1);
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            set_vram_addr(&mut ppu, addr + 5);
+            ppu.write(PpuRegs::PpuData, 0x10 + i as u8);
+        }
+        for (addr, expected) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().zip(expected) {
+            assert_eq!(ppu.vram.read(addr + 5), expected, "flags6 = {:02X}, ${:04X}", flags6, addr);
+            assert_eq!(ppu.vram.read(addr + 0x1000 + 5), expected, "flags6 = {:02X}, ${:04X}", flags6, addr + 0x1000);
+        }
+    }
+}
+
+#[test]
+fn four_screen_nametables_are_not_mirrored() {
+    // フラグ6のbit3が4画面ミラーリング
+    let mut ppu = ready_ppu_with(0x08, 1);
+    for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+        set_vram_addr(&mut ppu, addr + 5);
+        ppu.write(PpuRegs::PpuData, 0x10 + i as u8);
+    }
+    for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+        assert_eq!(ppu.vram.read(addr + 5), 0x10 + i as u8);
+        // $3000-$3EFF は $2000-$2EFF のミラー
+        assert_eq!(ppu.vram.read(addr + 0x1000 + 5), 0x10 + i as u8);
+    }
+}
+
+#[test]
+fn poke_vram_edits_chr_rom_and_four_screen_nametables() {
+    let mut ppu = ready_ppu_with(0x08, 1);
+    ppu.poke_vram(0x0010, 0x55);
+    ppu.poke_vram(0x2C05, 0x77);
+    ppu.poke_vram(0x3F10, 0x0F);
+    assert_eq!(ppu.vram.peek(0x0010), 0x55);
+    assert_eq!(ppu.vram.peek(0x2C05), 0x77);
+    assert_eq!(ppu.vram.peek(0x3C05), 0x77);
+    assert_eq!(ppu.vram.peek(0x2405), 0x00);
+    assert_eq!(ppu.vram.peek(0x3F00), 0x0F);
+}
+
+#[test]
+fn palette_reads_are_immediate_and_fill_buffer_from_nametable() {
+    let mut ppu = ready_ppu();
+    set_vram_addr(&mut ppu, 0x2F05);
+    ppu.write(PpuRegs::PpuData, 0x5A);
+    set_vram_addr(&mut ppu, 0x3F05);
+    ppu.write(PpuRegs::PpuData, 0x2C);
+
+    set_vram_addr(&mut ppu, 0x3F05);
+    assert_eq!(ppu.read(PpuRegs::PpuData) & 0x3F, 0x2C);
+    assert_eq!(ppu.regs.read_buffer, 0x5A);
+    // $3F20-$3FFF はミラー
+    set_vram_addr(&mut ppu, 0x3FE5);
+    assert_eq!(ppu.read(PpuRegs::PpuData) & 0x3F, 0x2C);
+}
+
+#[test]
+fn palette_background_entries_are_mirrored() {
+    let mut ppu = ready_ppu();
+    set_vram_addr(&mut ppu, 0x3F10);
+    ppu.write(PpuRegs::PpuData, 0x0F);
+    assert_eq!(ppu.vram.read(0x3F00), 0x0F);
+    set_vram_addr(&mut ppu, 0x3F11);
+    ppu.write(PpuRegs::PpuData, 0x16);
+    assert_eq!(ppu.vram.read(0x3F01), 0x00);
+    assert_eq!(ppu.vram.read(0x3F11), 0x16);
+}
+
+#[test]
+fn ppudata_access_during_rendering_increments_coarse_x_and_y() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    // coarse X=31, coarse Y=29, fine Y=7 からの増加
+    ppu.regs.v = 0x7000 | (29 << 5) | 31;
+    run_to(&mut ppu, 10, 0);
+    ppu.read(PpuRegs::PpuData);
+    // 水平・垂直の両方のネームテーブルが切り替わる。
+    assert_eq!(ppu.regs.v, 0x0C00);
+}
+
+/// 次のフレームの先頭まで進め、そのフレームのクロック数を返す。
+fn frame_length(ppu: &mut Ppu) -> u64 {
+    let start = ppu.clock_counter;
+    let frame = ppu.frame();
+    while ppu.frame() == frame {
+        ppu.step();
+    }
+    assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+    ppu.clock_counter - start
+}
+
+#[test]
+fn odd_frames_skip_a_dot_when_rendering() {
+    let mut ppu = ready_ppu();
+    let odd = ppu.is_odd_frame();
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    let lengths = [frame_length(&mut ppu), frame_length(&mut ppu)];
+    let full = (DOTS_PER_SCANLINE as u64) * (SCANLINES_PER_FRAME as u64);
+    if odd {
+        assert_eq!(lengths, [full - 1, full]);
+    } else {
+        assert_eq!(lengths, [full, full - 1]);
+    }
+    assert_eq!(ppu.is_odd_frame(), odd);
+
+    // 描画が無効ならドットは飛ばされない。
+    ppu.write(PpuRegs::Mask, 0);
+    assert_eq!([frame_length(&mut ppu), frame_length(&mut ppu)], [full, full]);
+}
+
+#[test]
+fn pal_frames_have_312_lines_and_no_skipped_dot() {
+    let mut ppu = ready_ppu_in(Region::Pal);
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    let full = DOTS_PER_SCANLINE as u64 * 312;
+    assert_eq!([frame_length(&mut ppu), frame_length(&mut ppu)], [full, full]);
+}
+
+#[test]
+fn dendy_vblank_starts_50_lines_later() {
+    let mut ppu = ready_ppu_in(Region::Dendy);
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert!(!run_to(&mut ppu, 291, 1));
+    assert!(!vblank(&ppu));
+    assert!(run_to(&mut ppu, 292, 0));
+    run_to(&mut ppu, 311, 1);
+    assert!(vblank(&ppu));
+    ppu.step();
+    assert!(!vblank(&ppu));
+}
+
+#[test]
+fn power_on_ignores_writes_until_first_pre_render_line_ends() {
+    let mut ppu = new_ppu(Region::Ntsc);
+    ppu.power_on();
+    // 電源ON直後はVBlankフラグとスプライトオーバーフローが立っている。
+    assert_eq!(ppu.read(PpuRegs::Status) & 0xE0, 0xA0);
+
+    run_to(&mut ppu, PRE_RENDER_SCANLINE, DOTS_PER_SCANLINE - 1);
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    assert_eq!((ppu.regs.ctrl, ppu.regs.mask), (0, 0));
+    // OAMADDRとPPUDATAへの書き込みは受け付ける。
+    ppu.write(PpuRegs::OamAddr, 0x12);
+    assert_eq!(ppu.regs.oam_addr, 0x12);
+
+    ppu.step();
+    assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert_eq!(ppu.regs.ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+}
+
+#[test]
+fn reset_clears_ctrl_and_mask_but_keeps_v_and_oam_addr() {
+    let mut ppu = ready_ppu();
+    run_to(&mut ppu, 100, 0);
+    set_vram_addr(&mut ppu, 0x2345);
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    ppu.write(PpuRegs::OamAddr, 0x40);
+    ppu.write(PpuRegs::Scroll, 0xFF);
+
+    ppu.reset();
+    assert_eq!((ppu.regs.ctrl, ppu.regs.mask), (0, 0));
+    assert_eq!((ppu.regs.t, ppu.regs.fine_x, ppu.regs.w), (0, 0, false));
+    assert_eq!((ppu.regs.v, ppu.regs.oam_addr), (0x2345, 0x40));
+    // 描画位置はそのまま。
+    assert_eq!((ppu.scanline, ppu.dot), (100, 0));
+
+    // 次のpre-render lineが終わるまで、PPUCTRLへの書き込みは無視される。
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert_eq!(ppu.regs.ctrl, 0);
+    run_to(&mut ppu, 0, 0);
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+    assert_eq!(ppu.regs.ctrl, CtrlFlags::NMI_ON_VBRANK.bits());
+}
+
+#[test]
+fn oamdata_writes_increment_oamaddr_and_mask_attribute_bits() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::OamAddr, 0x10);
+    for data in [0x20, 0x01, 0xFF, 0x30] {
+        ppu.write(PpuRegs::OamData, data);
+    }
+    assert_eq!(&ppu.spr_ram()[0x10..0x14], &[0x20, 0x01, 0xE3, 0x30]);
+    assert_eq!(ppu.regs.oam_addr, 0x14);
+
+    // 読み込みではOAMADDRは進まない。
+    ppu.write(PpuRegs::OamAddr, 0x12);
+    assert_eq!(ppu.read(PpuRegs::OamData), 0xE3);
+    assert_eq!(ppu.read(PpuRegs::OamData), 0xE3);
+    assert_eq!(ppu.regs.oam_addr, 0x12);
+}
+
+#[test]
+fn oam_access_during_rendering() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_SPRITE.bits());
+    run_to(&mut ppu, 10, 100);
+    // 描画中の書き込みはOAMに届かず、OAMADDRは4進む。
+    ppu.write(PpuRegs::OamAddr, 0x21);
+    ppu.write(PpuRegs::OamData, 0x55);
+    assert_eq!(ppu.spr_ram()[0x21], 0);
+    assert_eq!(ppu.regs.oam_addr, 0x25);
+
+    // スプライトのフェッチ中はOAMADDRが0に戻る。
+    run_to(&mut ppu, 10, 258);
+    assert_eq!(ppu.regs.oam_addr, 0);
+
+    // dot 1-64では$FFが読める。
+    run_to(&mut ppu, 11, 10);
+    assert_eq!(ppu.read(PpuRegs::OamData), 0xFF);
+}
+
+#[test]
+fn oamaddr_at_render_start_copies_a_row_to_the_top_of_oam() {
+    let mut ppu = ready_ppu();
+    ppu.write(PpuRegs::OamAddr, 0x18);
+    for data in 1..=8 {
+        ppu.write(PpuRegs::OamData, data);
+    }
+    // VBlank中に描画を有効にしておく。
+    run_to(&mut ppu, VBLANK_SCANLINE, 10);
+    ppu.write(PpuRegs::OamAddr, 0x1A);
+    ppu.write(PpuRegs::Mask, MaskFlags::SHOW_BG.bits());
+    run_to(&mut ppu, PRE_RENDER_SCANLINE, 2);
+    assert_eq!(&ppu.spr_ram()[0..8], &ppu.spr_ram()[0x18..0x20]);
+    assert_eq!(ppu.spr_ram()[0], 1);
+}
+
+#[test]
+fn oam_decays_when_rendering_is_disabled_for_too_long() {
+    let mut ppu = ready_ppu();
+    ppu.set_oam_decay(true);
+    ppu.write(PpuRegs::OamAddr, 0);
+    ppu.write(PpuRegs::OamData, 0x12);
+
+    // VBlankの間くらいなら値は保たれる。
+    run_to(&mut ppu, 20, 0);
+    ppu.write(PpuRegs::OamAddr, 0);
+    assert_eq!(ppu.read(PpuRegs::OamData), 0x12);
+
+    // 1フレーム放置すると失われる。
+    run_to(&mut ppu, 19, 0);
+    assert_eq!(ppu.read(PpuRegs::OamData), 0xFF);
+}
+
+/// パターンテーブルの $0010 (タイル1) の左上に、値3のピクセルを1つ置く。
+/// パレットは背景色 $0F、各パレットの色 n は $10 * パレット番号 + n にする。
+fn setup_views(ppu: &mut Ppu) {
+    ppu.vram.raw_write(0x0010, &[0x80, 0, 0, 0, 0, 0, 0, 0, 0x80]);
+    set_vram_addr(ppu, 0x3F00);
+    for i in 0..32u8 {
+        ppu.write(PpuRegs::PpuData, if i % 4 == 0 { 0x0F } else { (i / 4) * 0x10 + i % 4 });
+    }
+}
+
+#[test]
+fn pattern_table_view_uses_the_selected_palette() {
+    let mut ppu = ready_ppu();
+    setup_views(&mut ppu);
+    let image = ppu.pattern_table_view(0, 2);
+    assert_eq!((image.width, image.height), (128, 128));
+    assert_eq!(image.pixels[8], 0x23);
+    assert_eq!(image.pixels[9], 0x0F);
+    assert!(ppu.pattern_table_view(1, 2).pixels.iter().all(|&pixel| pixel == 0x0F));
+}
+
+#[test]
+fn nametable_view_applies_attributes_mirroring_and_scroll() {
+    let mut ppu = ready_ppu();
+    setup_views(&mut ppu);
+    // 右下の 2x2 タイルのパレットを3にして、その左上にタイル1を置く。
+    set_vram_addr(&mut ppu, 0x2042);
+    ppu.write(PpuRegs::PpuData, 0x01);
+    set_vram_addr(&mut ppu, 0x23C0);
+    ppu.write(PpuRegs::PpuData, 0b1100_0000);
+
+    let image = ppu.nametable_view();
+    assert_eq!((image.width, image.height), (512, 480));
+    let pixel = |x: usize, y: usize| image.pixels[y * image.width + x];
+    assert_eq!(pixel(16, 16), 0x33);
+    assert_eq!(pixel(17, 16), 0x0F);
+    // このROMは水平ミラーリングなので、$2400 が $2000 のミラー。
+    assert_eq!(pixel(256 + 16, 16), 0x33);
+    assert_eq!(pixel(16, 240 + 16), 0x0F);
+
+    ppu.write(PpuRegs::Ctrl, 0x01);
+    ppu.write(PpuRegs::Scroll, 12);
+    ppu.write(PpuRegs::Scroll, 20);
+    assert_eq!(ppu.scroll_window(), (256 + 12, 20));
+}
+
+#[test]
+fn oam_view_draws_flipped_and_tall_sprites() {
+    let mut ppu = ready_ppu();
+    setup_views(&mut ppu);
+    ppu.write(PpuRegs::OamAddr, 4);
+    // スプライト1: タイル1、パレット5、左右反転
+    for data in [0x10, 0x01, 0x41, 0x20] {
+        ppu.write(PpuRegs::OamData, data);
+    }
+    let sprite = ppu.sprites()[1];
+    assert_eq!((sprite.x, sprite.y, sprite.palette(), sprite.flip_h()), (0x20, 0x10, 5, true));
+
+    let image = ppu.oam_view();
+    assert_eq!((image.width, image.height), (64, 128));
+    assert_eq!(image.pixels[8 + 7], 0x13);
+    assert_eq!(ppu.sprite_view(1).pixels[7], 0x13);
+
+    // 8x16では、上下反転で下のタイルが上に来る。
+    ppu.write(PpuRegs::Ctrl, CtrlFlags::SPRITE_SIZE.bits());
+    ppu.write(PpuRegs::OamAddr, 6);
+    ppu.write(PpuRegs::OamData, 0xC1);
+    let view = ppu.sprite_view(1);
+    assert_eq!((view.width, view.height), (8, 16));
+    // タイル1は8x16ではパターンテーブル$1000のタイル0と1なので、何も描かれない。
+    assert!(view.pixels.iter().all(|&pixel| pixel == 0x0F));
+    ppu.write(PpuRegs::OamAddr, 5);
+    ppu.write(PpuRegs::OamData, 0x00);
+    let view = ppu.sprite_view(1);
+    assert_eq!(view.pixels[8 * 15 + 7], 0x0F);
+    assert_eq!(view.pixels[8 * 7 + 7], 0x13);
+}
+
+#[test]
+fn four_screen_nametable_view_shows_each_nametable() {
+    let mut ppu = ready_ppu_with(0x08, 1);
+    // タイル1の左上のピクセルだけが色1
+    ppu.poke_vram(0x0010, 0x80);
+    ppu.poke_vram(0x3F00, 0x0F);
+    ppu.poke_vram(0x3F01, 0x16);
+    // 右下のネームテーブル($2C00)の左上だけがタイル1
+    ppu.poke_vram(0x2C00, 0x01);
+    let view = ppu.nametable_view();
+    assert_eq!(view.pixels[240 * viewer::NAMETABLES_WIDTH + 256], 0x16);
+    for (x, y) in [(0, 0), (256, 0), (0, 240)] {
+        assert_eq!(view.pixels[y * viewer::NAMETABLES_WIDTH + x], 0x0F);
+    }
+}
+
+#[test]
+fn png_round_trips() {
+    let rgba = [0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x80];
+    let png = viewer::encode_png(2, 1, &rgba);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(decoded.dimensions(), (2, 1));
+    assert_eq!(decoded.into_raw(), rgba);
+}
@@ -0,0 +1,258 @@
+//! グラフィックの不具合を調べるためのデバッグ表示。
+//!
+//! VRAM(vram::MemCon)とOAM(Ppu::spr_ram)の現在の内容から、以下の画像を作る。
+//! * パターンテーブル: 2枚それぞれ 128x128。パレットを選んで着色する。
+//! * ネームテーブル: 4画面分を並べた 512x480。ミラーリングを反映し、スクロール位置の枠を重ねられる。
+//! * OAM: 64個のスプライトの一覧と、各スプライトのプレビュー。
+//! * パレット: 32色。
+//!
+//! 画像はパレットのインデックス(IndexedImage)で作り、Palette でRGBAに変換する。
+//! PNG形式でも書き出せる(encode_png)ので、ウィンドウの無い fami-headless からも保存できる。
+
+use image::ColorType;
+use image::codecs::png::PngEncoder;
+
+use super::{CtrlFlags, Ppu, SPR_RAM_SIZE};
+use super::palette::Palette;
+use super::vram::PALETTE_BASE_ADDR;
+
+/// パターンテーブル1枚の幅と高さ(ピクセル)。16x16タイル。
+pub const PATTERN_TABLE_SIZE: usize = 128;
+/// 4画面分のネームテーブルの幅(ピクセル)
+pub const NAMETABLES_WIDTH: usize = 512;
+/// 4画面分のネームテーブルの高さ(ピクセル)
+pub const NAMETABLES_HEIGHT: usize = 480;
+/// スプライトの数
+pub const SPRITE_COUNT: usize = SPR_RAM_SIZE / 4;
+/// OAMのプレビューの1行あたりのスプライト数
+const OAM_VIEW_COLUMNS: usize = 8;
+/// パレットの表示での1色の大きさ(ピクセル)
+const PALETTE_SWATCH_SIZE: usize = 8;
+/// パターンテーブルの1タイルのバイト数
+const TILE_SIZE: u16 = 16;
+/// スプライトの属性: 左右反転
+const SPRITE_FLIP_H: u8 = 0x40;
+/// スプライトの属性: 上下反転
+const SPRITE_FLIP_V: u8 = 0x80;
+/// スプライトの属性: BGの後ろに表示
+const SPRITE_BEHIND_BG: u8 = 0x20;
+
+/// パレットのインデックス(6bit)を1ピクセル1バイトで並べた画像。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl IndexedImage {
+    fn new(width: usize, height: usize) -> Self {
+        IndexedImage { width, height, pixels: vec![0; width * height] }
+    }
+
+    /// RGBA(1ピクセル4バイト)に変換する。グレースケールと色強調は適用しない。
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut rgba = vec![0; self.pixels.len() * 4];
+        palette.to_rgba(&self.pixels, 0, &mut rgba);
+        rgba
+    }
+
+    /// (x, y) にパターンテーブルのタイルを描く。colors はピクセルの値(0-3)ごとの色。
+    fn draw_tile(&mut self, ppu: &Ppu, addr: u16, x: usize, y: usize, colors: [u8; 4], flip: (bool, bool)) {
+        for row in 0..8 {
+            let low = ppu.vram.peek(addr + row);
+            let high = ppu.vram.peek(addr + row + 8);
+            let dy = if flip.1 { 7 - row as usize } else { row as usize };
+            for bit in 0..8 {
+                let value = ((low >> (7 - bit)) & 1) | (((high >> (7 - bit)) & 1) << 1);
+                let dx = if flip.0 { 7 - bit } else { bit };
+                self.pixels[(y + dy) * self.width + x + dx] = colors[value as usize];
+            }
+        }
+    }
+}
+
+/// OAMの1スプライト
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteEntry {
+    pub index: usize,
+    /// 画面上のX座標
+    pub x: u8,
+    /// OAMのY座標。実際には1ライン下に表示される。
+    pub y: u8,
+    pub tile: u8,
+    pub attr: u8,
+}
+
+impl SpriteEntry {
+    /// パレット番号(4-7)
+    pub fn palette(&self) -> usize {
+        4 + (self.attr & 0x03) as usize
+    }
+
+    pub fn flip_h(&self) -> bool {
+        self.attr & SPRITE_FLIP_H != 0
+    }
+
+    pub fn flip_v(&self) -> bool {
+        self.attr & SPRITE_FLIP_V != 0
+    }
+
+    pub fn behind_bg(&self) -> bool {
+        self.attr & SPRITE_BEHIND_BG != 0
+    }
+}
+
+impl Ppu {
+    /// パレットRAM(32色)の現在の値。
+    pub fn palette_ram(&self) -> [u8; 32] {
+        let mut colors = [0; 32];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = self.vram.peek(PALETTE_BASE_ADDR + i as u16) & 0x3F;
+        }
+        colors
+    }
+
+    /// パレット番号(0-3 = BG, 4-7 = スプライト)の4色。0番目の色は共通の背景色になる。
+    fn palette_colors(&self, palette: usize) -> [u8; 4] {
+        let ram = self.palette_ram();
+        let base = (palette & 0x07) * 4;
+        [ram[0], ram[base + 1], ram[base + 2], ram[base + 3]]
+    }
+
+    /// パターンテーブル(0 = $0000, 1 = $1000)を palette で着色した 128x128 の画像。
+    pub fn pattern_table_view(&self, table: usize, palette: usize) -> IndexedImage {
+        let mut image = IndexedImage::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+        let colors = self.palette_colors(palette);
+        let base = (table as u16 & 0x01) * 0x1000;
+        for tile in 0..256 {
+            let (x, y) = ((tile % 16) * 8, (tile / 16) * 8);
+            image.draw_tile(self, base + tile as u16 * TILE_SIZE, x, y, colors, (false, false));
+        }
+        image
+    }
+
+    /// 4画面分のネームテーブルを、PPUCTRLで選択されたBGのパターンテーブルで描いた 512x480 の画像。
+    /// ミラーリングされた画面には、ミラー元と同じ内容が表示される。
+    pub fn nametable_view(&self) -> IndexedImage {
+        let mut image = IndexedImage::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+        let ctrl = CtrlFlags::from_bits_truncate(self.regs.ctrl);
+        let table = if ctrl.contains(CtrlFlags::BG_PATTERN_TABLE) { 0x1000 } else { 0x0000 };
+        for screen in 0..4u16 {
+            let base = 0x2000 + screen * 0x400;
+            let (left, top) = ((screen as usize & 1) * 256, (screen as usize >> 1) * 240);
+            for row in 0..30u16 {
+                for column in 0..32u16 {
+                    let tile = self.vram.peek(base + row * 32 + column) as u16;
+                    // 属性テーブルの1バイトは 4x4 タイル、その2bitずつが 2x2 タイルのパレットを表す。
+                    let attr = self.vram.peek(base + 0x3C0 + (row / 4) * 8 + column / 4);
+                    let shift = ((row & 0x02) << 1) | (column & 0x02);
+                    let colors = self.palette_colors(((attr >> shift) & 0x03) as usize);
+                    let (x, y) = (left + column as usize * 8, top + row as usize * 8);
+                    image.draw_tile(self, table + tile * TILE_SIZE, x, y, colors, (false, false));
+                }
+            }
+        }
+        image
+    }
+
+    /// 画面の左上が、4画面分のネームテーブル(512x480)のどこに当たるか。
+    /// フレームの先頭でのスクロール位置(内部レジスタ t と x)から求める。
+    pub fn scroll_window(&self) -> (usize, usize) {
+        let (t, fine_x) = (self.regs.t as usize, self.regs.fine_x as usize);
+        let x = ((t >> 10) & 0x01) * 256 + (t & 0x1F) * 8 + fine_x;
+        let y = ((t >> 11) & 0x01) * 240 + ((t >> 5) & 0x1F) * 8 + ((t >> 12) & 0x07);
+        (x % NAMETABLES_WIDTH, y % NAMETABLES_HEIGHT)
+    }
+
+    /// OAMの64個のスプライト。
+    pub fn sprites(&self) -> Vec<SpriteEntry> {
+        self.spr_ram.chunks_exact(4).enumerate()
+            .map(|(index, bytes)| SpriteEntry { index, y: bytes[0], tile: bytes[1], attr: bytes[2], x: bytes[3] })
+            .collect()
+    }
+
+    /// 8x16のスプライトならtrue。
+    pub fn sprite_8x16(&self) -> bool {
+        CtrlFlags::from_bits_truncate(self.regs.ctrl).contains(CtrlFlags::SPRITE_SIZE)
+    }
+
+    /// 1つのスプライトのプレビュー(8x8 または 8x16)。反転を反映し、透明な部分は背景色になる。
+    pub fn sprite_view(&self, index: usize) -> IndexedImage {
+        let sprite = self.sprites()[index];
+        let height = if self.sprite_8x16() { 16 } else { 8 };
+        let mut image = IndexedImage::new(8, height);
+        self.draw_sprite(&mut image, &sprite, 0, 0);
+        image
+    }
+
+    /// 64個のスプライトのプレビューを 8x8 個並べた画像。1つの枠は 8x16。
+    pub fn oam_view(&self) -> IndexedImage {
+        let rows = SPRITE_COUNT / OAM_VIEW_COLUMNS;
+        let mut image = IndexedImage::new(OAM_VIEW_COLUMNS * 8, rows * 16);
+        image.pixels.fill(self.palette_ram()[0]);
+        for sprite in self.sprites() {
+            let (x, y) = ((sprite.index % OAM_VIEW_COLUMNS) * 8, (sprite.index / OAM_VIEW_COLUMNS) * 16);
+            self.draw_sprite(&mut image, &sprite, x, y);
+        }
+        image
+    }
+
+    /// パレットRAMの32色を 16x2 個並べた画像。上の行がBG、下の行がスプライト。
+    pub fn palette_view(&self) -> IndexedImage {
+        let mut image = IndexedImage::new(16 * PALETTE_SWATCH_SIZE, 2 * PALETTE_SWATCH_SIZE);
+        let ram = self.palette_ram();
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let i = (y / PALETTE_SWATCH_SIZE) * 16 + x / PALETTE_SWATCH_SIZE;
+                image.pixels[y * image.width + x] = ram[i];
+            }
+        }
+        image
+    }
+
+    fn draw_sprite(&self, image: &mut IndexedImage, sprite: &SpriteEntry, x: usize, y: usize) {
+        let ctrl = CtrlFlags::from_bits_truncate(self.regs.ctrl);
+        let colors = self.palette_colors(sprite.palette());
+        let flip = (sprite.flip_h(), sprite.flip_v());
+        let tile = sprite.tile as u16;
+        if self.sprite_8x16() {
+            // 8x16では、タイル番号のbit0でパターンテーブルを選ぶ。上下反転では上下のタイルも入れ替わる。
+            let top = (tile & 0x01) * 0x1000 + (tile & 0xFE) * TILE_SIZE;
+            let (upper, lower) = if flip.1 { (top + TILE_SIZE, top) } else { (top, top + TILE_SIZE) };
+            image.draw_tile(self, upper, x, y, colors, flip);
+            image.draw_tile(self, lower, x, y + 8, colors, flip);
+        } else {
+            let table = if ctrl.contains(CtrlFlags::SPRITE_PATTERN_TABLE) { 0x1000 } else { 0x0000 };
+            image.draw_tile(self, table + tile * TILE_SIZE, x, y, colors, flip);
+        }
+    }
+}
+
+/// RGBAの画像(幅 width)に、(x, y) から w x h の枠を描く。画像の端を越えた部分は反対側に回り込む。
+/// ネームテーブルの画像にスクロール位置の枠を重ねるのに使う。
+pub fn draw_wrapping_rect(rgba: &mut [u8], width: usize, (x, y, w, h): (usize, usize, usize, usize), color: [u8; 4]) {
+    let height = rgba.len() / 4 / width;
+    let mut plot = |px: usize, py: usize| {
+        let offset = ((py % height) * width + px % width) * 4;
+        rgba[offset..offset + 4].copy_from_slice(&color);
+    };
+    for dx in 0..w {
+        plot(x + dx, y);
+        plot(x + dx, y + h - 1);
+    }
+    for dy in 0..h {
+        plot(x, y + dy);
+        plot(x + w - 1, y + dy);
+    }
+}
+
+/// RGBA(1ピクセル4バイト)の画像をPNG形式に変換する。
+/// rgba の大きさが width x height に合わない場合はpanicする。
+pub fn encode_png(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(rgba, width as u32, height as u32, ColorType::Rgba8)
+        .expect("failed to encode PNG");
+    png
+}
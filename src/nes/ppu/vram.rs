@@ -1,19 +1,20 @@
 //! PPUのVRAMを管理する Memory Controller。
 //! ミラー領域への値の反映など、メモリへの読み書きを仲介する。
 
-use crate::util;
 use crate::nes::rom::MirroringType;
 
 /// PPUに搭載されているVRAM容量(bytes)
-const REAL_VRAM_SIZE: usize = 0x800;
+pub const REAL_VRAM_SIZE: usize = 0x800;
 /// メモリ空間の広さ(bytes)
 const VRAM_SPACE: usize = 0x4000;
 
 const NAMETABLE_BASE_ADDR: u16 = 0x2000;
 const NAMETABLE_MIRROR_BASE_ADDR: u16 = 0x3000;
+/// パレット領域の先頭アドレス
+pub const PALETTE_BASE_ADDR: u16 = 0x3F00;
 
-const NAMETABLE_HORIZONTAL_OFFSET: u16 = 0x800;
-const NAMETABLE_VERTICAL_OFFSET: u16 = 0x400;
+const NAMETABLE_HORIZONTAL_OFFSET: u16 = 0x400;
+const NAMETABLE_VERTICAL_OFFSET: u16 = 0x800;
 
 
 /// 16KB(14bit)のメモリ空間を持ち、物理的には2KBの容量を持つVRAMのメモリコントローラー。
@@ -22,6 +23,8 @@ const NAMETABLE_VERTICAL_OFFSET: u16 = 0x400;
 pub struct MemCon {
     vram: Box<[u8]>,
     mirroring_type: MirroringType,
+    /// パターンテーブルがCHR-RAM(書き込み可能)か。ROMがCHR-ROMを持たない場合はCHR-RAMになる。
+    chr_ram: bool,
 }
 
 /*
@@ -50,10 +53,12 @@ $3F20-$3FFF $00E0(224B) Mirrors of $3F00-$3F1F
 ・パターンテーブル($0000-$1FFF)は、通常ROM側のCHR-ROMがマッピングされている。
   カートリッジがパターンテーブルを1枚のみ持っている場合は、1枚目が2枚目にミラーリングされる。
   マッパーによってはパターンテーブルの内容が動的に切り替わる場合がある。
+・パターンテーブルは、ROMがCHR-ROMを持たない場合はカートリッジ上の8KBのCHR-RAMになり、書き込みができる。
 ・ネームテーブル($2000-$2FFF)は2枚あり、通常2KBのRAM(物理)が利用される。
   全体としては4枚あるのであと2KB足りないが、残り2枚は物理RAMのミラー領域となる。
-  水平ミラーリングの場合は $2000(左上/物理) = $2800(左下/ミラー), $2400(右上/物理) = $2C00(右下/ミラー) となる。
-  垂直ミラーリングの場合は $2000(左上/物理) = $2400(右上/ミラー), $2800(左下/物理) = $2C00(右下/ミラー) となる。
+  4画面ミラーリングの場合は、カートリッジ上の2KBのRAMと合わせた4KBで、4枚がそれぞれ独立している。
+  水平ミラーリングの場合は $2000(左上/物理) = $2400(右上/ミラー), $2800(左下/物理) = $2C00(右下/ミラー) となる。
+  垂直ミラーリングの場合は $2000(左上/物理) = $2800(左下/ミラー), $2400(右上/物理) = $2C00(右下/ミラー) となる。
 ・ネームテーブルのミラー領域は $3000-$3EFF の3840バイトで、オリジナルの4KBが
   丸ごとミラーリングされているわけではない。(具体的には$100=256バイト足りない)
 ・$3000-$3EFF は $2000-$2EFF の内容が丸々ミラーリングされている。
@@ -144,129 +149,34 @@ $3F1D-$3F1F Sprite palette 3
 */
 
 impl MemCon {
-    pub fn new(mirroring_type: MirroringType) -> Self {
+    pub fn new(mirroring_type: MirroringType, chr_ram: bool) -> Self {
         Self {
             vram: Box::new([0; VRAM_SPACE]),
             mirroring_type,
+            chr_ram,
         }
     }
 
     /// ミラーリング等を考慮せず、メモリに直にデータを書き込む。
     /// 主に初期化処理に利用する。
     pub fn raw_write(&mut self, addr: u16, data: &[u8]) {
-        log::debug!("raw_write: addr={:#06X}, data.len={}", addr, data.len());
-        debug_assert!(addr < 0x3FFF);
+        debug_assert!(addr <= 0x3FFF);
         let addr = addr as usize;
         self.vram[addr..addr+data.len()].copy_from_slice(data);
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
-        log::debug!("write: addr={:#06X}, data={:#04X}({})", addr, data, data);
-        debug_assert!(addr < 0x3FFF);
+        debug_assert!(addr <= 0x3FFF);
         
         match addr {
             0x0000..=0x1FFF => {
-                // パターンテーブル(CHR-ROM)への書き込みはひとまずエラーとしておく。
-                // TODO: CHR-"RAM" の場合は書き込みに対応する必要あり。
-                util::panic_write_to_read_only_area(addr, data)
-            },
-            0x2000..=0x3EFF => {
-                // ネームテーブル(またはそこへのミラー領域)への書き込み。
-                
-                // 「ネームテーブル自体のミラーリング(2枚が4枚として扱われる仕組み)」と、
-                // 「4枚のネームテーブルがVRAM上にもう1つ存在する」という意味のミラーリングの
-                //  2つの仕組みがあってややこしい。
-                // 前者は「垂直ミラーリング」または「水平ミラーリング」と表記する。
-
-                // テーブル中の「どの位置への書き込み」かを取得。
-                // ここで必要なのはアドレスの下位12bit。
-                let pos = addr & 0x0FFF;
-                // 水平 or 垂直ミラーの書き込みを実現するため、
-                // 指定されたアドレスに加算するオフセット値。
-                let offset = match self.mirroring_type {
-                    MirroringType::Horizontal => NAMETABLE_HORIZONTAL_OFFSET,
-                    MirroringType::Vertical => NAMETABLE_VERTICAL_OFFSET,
-                    _ => unreachable!()
-                };
-
-                // ここから書き込み。
-                // 指定されたアドレスに書き込んだあと、水平ミラーリングの場合は 0x800 を加算、
-                // 垂直ミラーリングの場合は 0x400 を加算し、溢れたビットは無視すれば、
-                // オリジナル領域と垂直 or 水平ミラー領域の両方へ書き込みが可能。
-                // ただし、$3F00-$3F1F がパレット用に利用されている(つまり、全体がミラーされていない)
-                // ことに注意が必要。
-                
-                // 水平 or 垂直にミラーされている領域の、どちらかへの書き込み
-                let addr = NAMETABLE_BASE_ADDR | pos;
-                self.vram[addr as usize] = data;
-                // 水平 or 垂直にミラーされている領域の、さっきとは違う側への書き込み
-                let addr = NAMETABLE_BASE_ADDR | pos.wrapping_add(offset);
-                self.vram[addr as usize] = data;
-                // ここからVRAM上のミラー領域への書き込み
-                if pos > 0xEFF {
-                    // ミラーのオリジナル領域にだけ書き込む
-                    let addr = NAMETABLE_MIRROR_BASE_ADDR | pos.wrapping_add(offset);
-                    self.vram[addr as usize] = data;
-                } else {
-                    // 水平 or 垂直にミラーされている領域の、どちらかへの書き込み
-                    let addr = NAMETABLE_MIRROR_BASE_ADDR | pos;
-                    self.vram[addr as usize] = data;
-                    // 水平 or 垂直にミラーされている領域の、さっきとは違う側への書き込み
-                    let addr = NAMETABLE_MIRROR_BASE_ADDR | pos.wrapping_add(offset);
-                    self.vram[addr as usize] = data;
-                }
-            },
-            0x3F00..=0x3F1F => {
-                // パレット(またはそこへのミラー領域)への書き込み。
-                
-                // オリジナルとミラー領域の対応表は以下の通り。
-                // これにより分かるのは、以下の2つの事実。
-                // (1) アドレスの末尾2bitが 00 の場合、ミラー領域が存在する。
-                // (2) (1)が成り立つ場合、4bit目を反転させればオリジナルとミラーを切り替え可能。
-
-                // [オリジナルとミラーの対応表]
-                // $3F00: 0011111100000000 (=$3F10)
-                // $3F01: 0011111100000001
-                // $3F02: 0011111100000010
-                // $3F03: 0011111100000011
-                // $3F04: 0011111100000100 (=$3F14)
-                // $3F05: 0011111100000101
-                // $3F06: 0011111100000110
-                // $3F07: 0011111100000111
-                // $3F08: 0011111100001000 (=$3F18)
-                // $3F09: 0011111100001001
-                // $3F0A: 0011111100001010
-                // $3F0B: 0011111100001011
-                // $3F0C: 0011111100001100 (=$3F1C)
-                // $3F0D: 0011111100001101
-                // $3F0E: 0011111100001110
-                // $3F0F: 0011111100001111
-                // $3F10: 0011111100010000 (=$3F00s)
-                // $3F11: 0011111100010001
-                // $3F12: 0011111100010010
-                // $3F13: 0011111100010011
-                // $3F14: 0011111100010100 (=$3F04)
-                // $3F15: 0011111100010101
-                // $3F16: 0011111100010110
-                // $3F17: 0011111100010111
-                // $3F18: 0011111100011000 (=$3F08)
-                // $3F19: 0011111100011001
-                // $3F1A: 0011111100011010
-                // $3F1B: 0011111100011011
-                // $3F1C: 0011111100011100 (=$3F0C)
-                // $3F1D: 0011111100011101
-                // $3F1E: 0011111100011110
-                // $3F1F: 0011111100011111
-                
-                // 末尾2bitが 00 ならミラーへの反映が必要
-                if (addr & 0b11) == 0 {
-                    // オリジナルかミラーのどちらかへ書き込む
-                    self.vram[addr as usize] = data;
-                    // 4bit目を反転させて、先ほどとは逆の領域に書き込む
-                    let addr = addr ^ (1 << 4);
+                // 実機ではCHR-ROMへの書き込みはエラーとならず、単に無視される。
+                if self.chr_ram {
                     self.vram[addr as usize] = data;
                 }
             },
+            0x2000..=0x3EFF => self.write_nametable(addr, data),
+            0x3F00..=0x3FFF => self.write_palette(addr, data),
             _ => {
                 // ミラーを考慮せずに書き込み
                 self.vram[addr as usize] = data;
@@ -274,7 +184,126 @@ impl MemCon {
         }
     }
 
+    /// ネームテーブル(またはそこへのミラー領域)への書き込み。
+    fn write_nametable(&mut self, addr: u16, data: u8) {
+        // 「ネームテーブル自体のミラーリング(2枚が4枚として扱われる仕組み)」と、
+        // 「4枚のネームテーブルがVRAM上にもう1つ存在する」という意味のミラーリングの
+        //  2つの仕組みがあってややこしい。
+        // 前者は「垂直ミラーリング」または「水平ミラーリング」と表記する。
+
+        // テーブル中の「どの位置への書き込み」かを取得。
+        // ここで必要なのはアドレスの下位12bit。
+        let pos = addr & 0x0FFF;
+        // 水平 or 垂直ミラーの書き込みを実現するため、
+        // 指定されたアドレスのビットを反転させるオフセット値。
+        // 4画面ミラーリングの場合はミラーされないので、同じ位置に2回書き込むことになる。
+        let offset = match self.mirroring_type {
+            MirroringType::Horizontal => NAMETABLE_HORIZONTAL_OFFSET,
+            MirroringType::Vertical => NAMETABLE_VERTICAL_OFFSET,
+            MirroringType::FourScreen => 0,
+        };
+
+        // ここから書き込み。
+        // 指定されたアドレスに書き込んだあと、水平ミラーリングの場合は 0x400 のビットを、
+        // 垂直ミラーリングの場合は 0x800 のビットを反転させれば、
+        // オリジナル領域と垂直 or 水平ミラー領域の両方へ書き込みが可能。
+        // ただし、$3F00-$3F1F がパレット用に利用されている(つまり、全体がミラーされていない)
+        // ことに注意が必要。
+        
+        // 水平 or 垂直にミラーされている領域の、どちらかへの書き込み
+        let addr = NAMETABLE_BASE_ADDR | pos;
+        self.vram[addr as usize] = data;
+        // 水平 or 垂直にミラーされている領域の、さっきとは違う側への書き込み
+        let mirrored_pos = pos ^ offset;
+        let addr = NAMETABLE_BASE_ADDR | mirrored_pos;
+        self.vram[addr as usize] = data;
+        // ここからVRAM上のミラー領域($3000-$3EFF)への書き込み。
+        // $3F00以降はパレットなので、そこに当たる側には書き込まない。
+        for pos in [pos, mirrored_pos] {
+            if pos <= 0xEFF {
+                let addr = NAMETABLE_MIRROR_BASE_ADDR | pos;
+                self.vram[addr as usize] = data;
+            }
+        }
+    }
+
+    /// パレット(またはそこへのミラー領域)への書き込み。
+    fn write_palette(&mut self, addr: u16, data: u8) {
+        // $3F20-$3FFF は $3F00-$3F1F のミラー。
+        let addr = palette_addr(addr);
+        
+        // オリジナルとミラー領域の対応表は以下の通り。
+        // これにより分かるのは、以下の2つの事実。
+        // (1) アドレスの末尾2bitが 00 の場合、ミラー領域が存在する。
+        // (2) (1)が成り立つ場合、4bit目を反転させればオリジナルとミラーを切り替え可能。
+
+        // [オリジナルとミラーの対応表]
+        // $3F00: 0011111100000000 (=$3F10)
+        // $3F01: 0011111100000001
+        // $3F02: 0011111100000010
+        // $3F03: 0011111100000011
+        // $3F04: 0011111100000100 (=$3F14)
+        // $3F05: 0011111100000101
+        // $3F06: 0011111100000110
+        // $3F07: 0011111100000111
+        // $3F08: 0011111100001000 (=$3F18)
+        // $3F09: 0011111100001001
+        // $3F0A: 0011111100001010
+        // $3F0B: 0011111100001011
+        // $3F0C: 0011111100001100 (=$3F1C)
+        // $3F0D: 0011111100001101
+        // $3F0E: 0011111100001110
+        // $3F0F: 0011111100001111
+        // $3F10: 0011111100010000 (=$3F00s)
+        // $3F11: 0011111100010001
+        // $3F12: 0011111100010010
+        // $3F13: 0011111100010011
+        // $3F14: 0011111100010100 (=$3F04)
+        // $3F15: 0011111100010101
+        // $3F16: 0011111100010110
+        // $3F17: 0011111100010111
+        // $3F18: 0011111100011000 (=$3F08)
+        // $3F19: 0011111100011001
+        // $3F1A: 0011111100011010
+        // $3F1B: 0011111100011011
+        // $3F1C: 0011111100011100 (=$3F0C)
+        // $3F1D: 0011111100011101
+        // $3F1E: 0011111100011110
+        // $3F1F: 0011111100011111
+        
+        // オリジナルかミラーのどちらかへ書き込む
+        self.vram[addr as usize] = data;
+        // 末尾2bitが 00 ならミラーへの反映が必要
+        if (addr & 0b11) == 0 {
+            // 4bit目を反転させて、先ほどとは逆の領域に書き込む
+            let addr = addr ^ (1 << 4);
+            self.vram[addr as usize] = data;
+        }
+    }
+
+    /// ミラー領域への反映は write と同じだが、パターンテーブル(CHR-ROM)も書き換えられる。デバッグ用途。
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        debug_assert!(addr <= 0x3FFF);
+        match addr {
+            0x0000..=0x1FFF => self.vram[addr as usize] = data,
+            0x2000..=0x3EFF => self.write_nametable(addr, data),
+            _ => self.write_palette(addr, data),
+        }
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    /// 副作用なしで読む。
+    pub fn peek(&self, addr: u16) -> u8 {
+        debug_assert!(addr <= 0x3FFF);
+        let addr = if addr >= PALETTE_BASE_ADDR { palette_addr(addr) } else { addr };
         self.vram[addr as usize]
     }
 }
+
+/// パレットのミラー領域($3F20-$3FFF)のアドレスを、$3F00-$3F1F に変換する。
+fn palette_addr(addr: u16) -> u16 {
+    PALETTE_BASE_ADDR | (addr & 0x1F)
+}
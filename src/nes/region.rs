@@ -0,0 +1,117 @@
+//! 地域(テレビ方式)ごとのタイミングの違い。
+//!
+//! NTSC(日本・北米)、PAL(欧州)、Dendy(旧ソ連圏の互換機)では、
+//! CPUのクロック周波数、CPUとPPUのクロック比、1フレームのスキャンライン数、
+//! PPUMASKの色強調ビットの並び等が異なる。
+//! https://wiki.nesdev.org/w/index.php/Cycle_reference_chart
+
+use crate::nes::cpu::{CLOCK_FREQ_NTSC, CLOCK_FREQ_PAL, CLOCK_FREQ_DENDY};
+use crate::nes::ppu::MaskFlags;
+use crate::nes::rom::{NesRom, TvFormat, CPUTiming};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// PAL用のPPUのタイミング(312ライン)を持つが、CPUとPPUのクロック比はNTSCと同じ。
+    /// VBlankはPALより50ライン遅れて始まる。
+    Dendy,
+}
+
+impl Region {
+    /// ROMのヘッダから地域を決める。
+    /// NES2.0のCPU/PPUタイミングを優先し、iNESではTVシステムのビットを見る。
+    /// 複数地域に対応したROMはNTSCとして扱う。
+    pub fn from_rom(rom: &NesRom) -> Self {
+        match rom.cpu_timing() {
+            CPUTiming::PAL => Region::Pal,
+            CPUTiming::Dendy => Region::Dendy,
+            CPUTiming::NTSC | CPUTiming::MultiRegion => match rom.tv_format() {
+                TvFormat::PAL => Region::Pal,
+                TvFormat::NTSC => Region::Ntsc,
+            },
+        }
+    }
+
+    /// "ntsc", "pal", "dendy" から変換する。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    /// 1フレームあたりのスキャンライン数
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// VBlankが始まるスキャンライン
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// pre-render line (フレームの最後のスキャンライン)
+    pub fn pre_render_scanline(self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    /// 奇数フレームでpre-render lineの最後のドットを飛ばすならtrue。NTSCのみ。
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// CPUの1クロックあたりのPPUクロック数を、分数 (分子, 分母) で返す。
+    /// NTSC, Dendyは3、PALは3.2(16/5)。
+    pub fn ppu_clocks_per_cpu_clock(self) -> (u8, u8) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    /// CPUのクロック周波数(Hz)
+    pub fn cpu_clock_freq(self) -> u32 {
+        match self {
+            Region::Ntsc => CLOCK_FREQ_NTSC,
+            Region::Pal => CLOCK_FREQ_PAL,
+            Region::Dendy => CLOCK_FREQ_DENDY,
+        }
+    }
+
+    /// 1秒あたりのフレーム数
+    pub fn frame_rate(self) -> f64 {
+        let (num, den) = self.ppu_clocks_per_cpu_clock();
+        let ppu_freq = self.cpu_clock_freq() as f64 * num as f64 / den as f64;
+        let mut dots = 341.0 * self.scanlines_per_frame() as f64;
+        if self.skips_odd_frame_dot() {
+            // 描画が有効なら2フレームに1回、1ドット短い。
+            dots -= 0.5;
+        }
+        ppu_freq / dots
+    }
+
+    /// PPUMASKを、NTSCのPPUでのビットの並び(bit5 = 赤, bit6 = 緑)に直す。
+    /// PALとDendyのPPUでは、赤と緑の色強調ビットが入れ替わっている。
+    pub fn ntsc_mask(self, mask: u8) -> u8 {
+        match self {
+            Region::Ntsc => mask,
+            Region::Pal | Region::Dendy => {
+                let flags = MaskFlags::from_bits_truncate(mask);
+                let mut swapped = flags - (MaskFlags::EMPHASIZE_RED | MaskFlags::EMPHASIZE_GREEN);
+                swapped.set(MaskFlags::EMPHASIZE_RED, flags.contains(MaskFlags::EMPHASIZE_GREEN));
+                swapped.set(MaskFlags::EMPHASIZE_GREEN, flags.contains(MaskFlags::EMPHASIZE_RED));
+                swapped.bits()
+            },
+        }
+    }
+}
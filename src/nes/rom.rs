@@ -10,6 +10,8 @@ use crate::nes::util;
 
 pub const PRG_ROM_UNIT_SIZE: usize = 0x4000;
 pub const CHR_ROM_UNIT_SIZE: usize = 0x2000;
+/// 実装済みのマッパー番号。現状はNROM(マッパー0)のみ。
+pub const SUPPORTED_MAPPERS: [u16; 1] = [0];
 
 /// NESのROM(バイナリ)情報を保存する構造体。
 /// バイナリの構成については以下を参照。
@@ -23,6 +25,7 @@ pub struct NesRom {
     battery_backed: bool,
     console_type: ConsoleType,
     mapper_no: u16,
+    submapper_no: u8,
     prg_ram_size: u32,   // TODO:最大サイズは？
     eeprom_size: u32,
     tv_format: TvFormat,
@@ -42,19 +45,88 @@ impl NesRom {
         &self.chr_rom
     }
 
+    pub fn trainer(&self) -> Option<&[u8]> {
+        self.trainer.as_deref()
+    }
+
     pub fn mirroring_type(&self) -> MirroringType {
         self.mirroring_type
     }
+
+    pub fn battery_backed(&self) -> bool {
+        self.battery_backed
+    }
+
+    pub fn console_type(&self) -> ConsoleType {
+        self.console_type
+    }
+
+    pub fn mapper_no(&self) -> u16 {
+        self.mapper_no
+    }
+
+    pub fn submapper_no(&self) -> u8 {
+        self.submapper_no
+    }
+
+    /// このROMのマッパーが実装済みか。未実装のマッパーでは、バンク切り替えが行われずに正しく動作しない。
+    pub fn is_mapper_supported(&self) -> bool {
+        SUPPORTED_MAPPERS.contains(&self.mapper_no)
+    }
+
+    /// このROMのマッパーが実装済みでなければ、エラーにする。ROMを実行する前に確認すること。
+    pub fn check_mapper_supported(&self) -> Result<(), Box<dyn Error>> {
+        if self.is_mapper_supported() {
+            Ok(())
+        } else {
+            Err(util::Error::new(format!(
+                "Unsupported mapper: {} (only NROM, mapper 0, is implemented)", self.mapper_no)))
+        }
+    }
+
+    pub fn prg_ram_size(&self) -> u32 {
+        self.prg_ram_size
+    }
+
+    pub fn eeprom_size(&self) -> u32 {
+        self.eeprom_size
+    }
+
+    pub fn tv_format(&self) -> TvFormat {
+        self.tv_format
+    }
+
+    pub fn chr_ram_size(&self) -> u32 {
+        self.chr_ram_size
+    }
+
+    pub fn chr_nvram_size(&self) -> u32 {
+        self.chr_nvram_size
+    }
+
+    pub fn cpu_timing(&self) -> CPUTiming {
+        self.cpu_timing
+    }
+
+    pub fn vssystem_type(&self) -> u8 {
+        self.vssystem_type
+    }
+
+    pub fn vshardware_type(&self) -> u8 {
+        self.vshardware_type
+    }
 }
 
 /// ネームテーブルのミラーリングタイプ
-#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum MirroringType {
-    None,
+    /// カートリッジ側に追加の2KBのVRAMを持ち、4枚のネームテーブルがミラーされない。
+    FourScreen,
     Horizontal,
     Vertical,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum ConsoleType {
     Nes,
     VsSystem,
@@ -62,11 +134,13 @@ pub enum ConsoleType {
     Extended,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum TvFormat {
     NTSC,
     PAL,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum CPUTiming {
     NTSC,
     PAL,
@@ -91,7 +165,8 @@ pub fn load_from_file(path: &str) -> Result<Box<NesRom>, Box<dyn Error>> {
     }
 }
 
-fn parse(rom_bin: &Vec<u8>) -> Result<Box<NesRom>, &str>
+/// メモリ上のROMイメージ(NES2.0/iNES形式)を解析する。
+pub fn parse(rom_bin: &[u8]) -> Result<Box<NesRom>, &str>
  {
     // NESファイルを読み込んで解析する
     // 対応するファイルのフォーマットは NES2.0 とする(つまりiNESもサポート)。
@@ -201,18 +276,18 @@ fn parse(rom_bin: &Vec<u8>) -> Result<Box<NesRom>, &str>
     
     // 当面は無視
     // バイト 14
-    let misc_rom_count = 
+    let _misc_rom_count =
         if is_nes_2_0 {
-            parse_flag14_v2(header[13])
+            parse_flag14_v2(header[14])
         } else {
             0
         };
 
     // 当面は無視
     // バイト 15
-    let expansion_device =
+    let _expansion_device =
         if is_nes_2_0 {
-            parse_flag15_v2(header[13])
+            parse_flag15_v2(header[15])
         } else {
             0
         };
@@ -225,10 +300,10 @@ fn parse(rom_bin: &Vec<u8>) -> Result<Box<NesRom>, &str>
             const TRAINLER_LEN: usize = 512;
             let start = index;
             index += TRAINLER_LEN;
-            let mut dst = Vec::<u8>::with_capacity(TRAINLER_LEN);
-            dst.resize(TRAINLER_LEN, 0);
-            dst.copy_from_slice(&rom_bin[start..TRAINLER_LEN+start]);
-            Some(dst)
+            match rom_bin.get(start..TRAINLER_LEN+start) {
+                Some(trainer) => Some(trainer.to_vec()),
+                None => return Err("Trainer is truncated."),
+            }
         } else {
             None
         };
@@ -237,20 +312,19 @@ fn parse(rom_bin: &Vec<u8>) -> Result<Box<NesRom>, &str>
     let prg_rom: Vec<u8> = {
         let start = index;
         index += prg_rom_size;
-        let mut dst = Vec::<u8>::with_capacity(prg_rom_size);
-        dst.resize(prg_rom_size, 0);
-        dst.copy_from_slice(&rom_bin[start..prg_rom_size+start]);
-        dst
+        match rom_bin.get(start..prg_rom_size+start) {
+            Some(prg_rom) => prg_rom.to_vec(),
+            None => return Err("PRG-ROM is truncated."),
+        }
     };
         
     // CHR-ROM領域
     let chr_rom: Vec<u8> = {
         let start = index;
-        index += chr_rom_size;
-        let mut dst = Vec::<u8>::with_capacity(chr_rom_size);
-        dst.resize(chr_rom_size, 0);
-        dst.copy_from_slice(&rom_bin[start..chr_rom_size+start]);
-        dst
+        match rom_bin.get(start..chr_rom_size+start) {
+            Some(chr_rom) => chr_rom.to_vec(),
+            None => return Err("CHR-ROM is truncated."),
+        }
     };
 
     // これ以降はPlayChoice用のデータ等が存在する場合がある。
@@ -264,6 +338,7 @@ fn parse(rom_bin: &Vec<u8>) -> Result<Box<NesRom>, &str>
         battery_backed,
         console_type,
         mapper_no,
+        submapper_no: submapper,
         prg_ram_size,
         eeprom_size,
         tv_format,
@@ -296,7 +371,7 @@ fn parse_flag6(flags: u8) -> (MirroringType, bool, bool, u8) {
 
     let mirroring_type = 
         if (flags & 0b0000_1000) != 0 {
-            MirroringType::None
+            MirroringType::FourScreen
         } else if (flags & 0b0000_0001) != 0 {
             MirroringType::Vertical
         } else {
@@ -343,9 +418,9 @@ fn parse_flag8(flags: u8) -> u32 {
     // ++++++++- PRG RAM size (Value 0 infers 8 KB for compatibility)
     
     if flags == 0 {
-        return 0x2000
+        0x2000
     } else {
-        return 0x2000 * (flags as u32)
+        0x2000 * (flags as u32)
     }
 }
 
@@ -358,9 +433,9 @@ fn parse_flag8_v2(flags: u8) -> (u8, u8) {
     //   ++++------ Submapper number
 
     let mapper_upper = flags & 0b0000_1111;
-    let submapper = flags & 0b0000_1111;
+    let submapper = (flags >> 4) & 0b0000_1111;
 
-    return (mapper_upper, submapper)
+    (mapper_upper, submapper)
 }
 
 fn parse_flag9(flags: u8) -> TvFormat {
@@ -369,10 +444,10 @@ fn parse_flag9(flags: u8) -> TvFormat {
     // |||||||+- TV system (0: NTSC; 1: PAL)
     // +++++++-- Reserved, set to zero
 
-    if (flags & 0x0000_0001) == 0 {
-        return TvFormat::NTSC
+    if (flags & 0b0000_0001) == 0 {
+        TvFormat::NTSC
     } else {
-        return TvFormat::PAL
+        TvFormat::PAL
     }
 }
 
@@ -385,8 +460,8 @@ fn parse_flag9_v2(flags: u8) -> (u8, u8) {
     // ++++------ CHR-ROM size MSB
 
     let prg_rom_size = flags & 0b0000_1111;
-    let chr_rom_size = flags & 0b1111_0000;
-    return (prg_rom_size, chr_rom_size)
+    let chr_rom_size = (flags >> 4) & 0b0000_1111;
+    (prg_rom_size, chr_rom_size)
 }
 
 /*
@@ -420,7 +495,7 @@ fn parse_flag10_v2(flags: u8) -> (u32, u32) {
     let prg_shift: u32 = (flags >> 4) & 0b0000_1111;
     let eep_shift: u32 = flags & 0b0000_1111;
 
-    return (64 << prg_shift, 64 << eep_shift);
+    (64 << prg_shift, 64 << eep_shift)
 }
 
 fn parse_flag11_v2(flags: u8) -> (u32, u32) {
@@ -438,7 +513,7 @@ fn parse_flag11_v2(flags: u8) -> (u32, u32) {
     let chr_shift: u32 = (flags >> 4) & 0b0000_1111;
     let chr_nv_shift: u32 = flags & 0b0000_1111;
 
-    return (64 << chr_shift, 64 << chr_nv_shift);
+    (64 << chr_shift, 64 << chr_nv_shift)
 }
 
 fn parse_flag12_v2(flags: u8) -> CPUTiming {
@@ -452,7 +527,7 @@ fn parse_flag12_v2(flags: u8) -> CPUTiming {
     //              2: Multiple-region
     //              3: UMC 6527P ("Dendy")
 
-    match flags | 0b0000_0011 {
+    match flags & 0b0000_0011 {
         0b00 => CPUTiming::NTSC,
         0b01 => CPUTiming::PAL,
         0b10 => CPUTiming::MultiRegion,
@@ -479,7 +554,7 @@ fn parse_flag13_v2(flags: u8) -> (u8, u8) {
     let vssystem_type = flags & 0b0000_1111;
     let vshardware_type = (flags >> 4) & 0b0000_1111;
 
-    return (vssystem_type, vshardware_type)
+    (vssystem_type, vshardware_type)
 }
 
 // 当面は無視
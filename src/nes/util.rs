@@ -8,6 +8,7 @@ pub enum Error {
 }
 
 impl Error {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(msg: String) -> Box<dyn std::error::Error> {
         Box::new(Error::Msg(msg))
     }
@@ -23,11 +24,6 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
-pub fn err_exit(msg: &str) -> ! {
-    eprintln!("{}", msg);
-    std::process::exit(1);
-}
-
 pub fn panic_write_to_read_only_area(addr: u16, data: u8) -> ! {
     panic!("Error: Write to read-only area. addr={:#06X}, data={:#04X}", addr, data)
 }
@@ -0,0 +1,110 @@
+//! 画面表示の配置計算。
+//!
+//! PPUの出力(256x240)のうち、どの範囲をウィンドウのどこにどの大きさで描くかを決める。
+//! ウィンドウのライブラリには依存しないので、フロントエンドごとに使い回せる。
+//!
+//! [オーバースキャン]
+//! ブラウン管のテレビでは、画面の上下左右の端は枠に隠れて見えない。
+//! NTSCでは上下8ライン程度が見えないことが多く、ゲームもそこにゴミが出ることを前提に作られている。
+//! (詳細は `Ppu::render` のコメントを参照)
+//!
+//! [ピクセルのアスペクト比]
+//! NESのピクセルは正方形ではなく、NTSCのテレビでは横長(8:7)で表示される。
+
+use crate::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+/// NTSCでのピクセルのアスペクト比(横:縦 = 8:7)
+pub const PIXEL_ASPECT_RATIO: f64 = 8.0 / 7.0;
+
+/// 画面の上下左右から切り取るピクセル数(PPUの出力のピクセル単位)。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Default for Overscan {
+    /// NTSCのテレビで一般的な、上下8ラインを切り取る設定。
+    fn default() -> Self {
+        Overscan { top: 8, bottom: 8, left: 0, right: 0 }
+    }
+}
+
+impl Overscan {
+    /// 切り取りなし。
+    pub fn none() -> Self {
+        Overscan { top: 0, bottom: 0, left: 0, right: 0 }
+    }
+}
+
+/// 画面の描き方の設定。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Presentation {
+    pub overscan: Overscan,
+    /// 拡大率を整数倍に限定する(ドットの大きさが揃う)。
+    pub integer_scaling: bool,
+    /// ピクセルのアスペクト比(8:7)を補正する。
+    pub aspect_correction: bool,
+}
+
+impl Default for Presentation {
+    fn default() -> Self {
+        Presentation {
+            overscan: Default::default(),
+            integer_scaling: true,
+            aspect_correction: true,
+        }
+    }
+}
+
+/// 矩形 [x, y, 幅, 高さ]。piston の `Image` と同じ並び。
+pub type Rect = [f64; 4];
+
+/// 描画の配置。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    /// テクスチャのどこを切り出すか(テクスチャのピクセル単位)
+    pub src: Rect,
+    /// ウィンドウのどこに描くか(ウィンドウの座標)
+    pub dst: Rect,
+}
+
+impl Presentation {
+    /// オーバースキャンを除いた、表示するPPUのピクセル数(幅, 高さ)。
+    pub fn visible_size(&self) -> (u32, u32) {
+        let o = &self.overscan;
+        let w = (SCREEN_WIDTH as u32).saturating_sub(o.left.saturating_add(o.right)).max(1);
+        let h = (SCREEN_HEIGHT as u32).saturating_sub(o.top.saturating_add(o.bottom)).max(1);
+        (w, h)
+    }
+
+    /// 拡大率1倍での表示サイズ(幅, 高さ)。アスペクト比の補正を含む。
+    pub fn base_size(&self) -> (f64, f64) {
+        let (w, h) = self.visible_size();
+        let par = if self.aspect_correction { PIXEL_ASPECT_RATIO } else { 1.0 };
+        (w as f64 * par, h as f64)
+    }
+
+    /// 幅 texture_width のテクスチャ(PPUの出力を横に引き伸ばしたもの。NTSCフィルター等)を、
+    /// 大きさ (window_w, window_h) のウィンドウの中央に、縦横比を保って描く配置を求める。
+    pub fn layout(&self, texture_width: u32, window_w: f64, window_h: f64) -> Layout {
+        let (w, h) = self.visible_size();
+        let o = &self.overscan;
+        let tex_scale = texture_width as f64 / SCREEN_WIDTH as f64;
+        // 切り取りが画面より大きい場合も、切り出す範囲はテクスチャの内側に収める。
+        let left = o.left.min(SCREEN_WIDTH as u32 - w);
+        let top = o.top.min(SCREEN_HEIGHT as u32 - h);
+        let src = [left as f64 * tex_scale, top as f64, w as f64 * tex_scale, h as f64];
+
+        let (base_w, base_h) = self.base_size();
+        let mut scale = (window_w / base_w).min(window_h / base_h);
+        if self.integer_scaling && scale >= 1.0 {
+            scale = scale.floor();
+        }
+        let (dst_w, dst_h) = (base_w * scale, base_h * scale);
+        let dst = [((window_w - dst_w) / 2.0).floor(), ((window_h - dst_h) / 2.0).floor(), dst_w, dst_h];
+        Layout { src, dst }
+    }
+}
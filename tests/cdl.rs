@@ -0,0 +1,101 @@
+//! コード/データロガーのテスト。
+
+mod common;
+
+use fami_rust::nes::debugger::cdl::{ChrAccess, CodeDataLogger, PrgAccess};
+use fami_rust::nes::rom;
+
+#[test]
+fn code_operands_and_data_are_logged() {
+    // C000  LDX #$00
+    // C002  LDA $C020,X
+    // C005  JMP ($C022)
+    // C008  (未使用)
+    // C010  JMP $C010
+    // C020  .byte $AA, $BB, $10, $C0
+    let mut program = vec![0; 0x24];
+    program[0x00..0x08].copy_from_slice(&[0xA2, 0x00, 0xBD, 0x20, 0xC0, 0x6C, 0x22, 0xC0]);
+    program[0x10..0x13].copy_from_slice(&[0x4C, 0x10, 0xC0]);
+    program[0x20..0x24].copy_from_slice(&[0xAA, 0xBB, 0x10, 0xC0]);
+    let mut console = common::console(&program, &[], |console, rom| console.set_cdl(Some(CodeDataLogger::new(rom))));
+    for _ in 0..6 {
+        console.step_instruction();
+    }
+
+    let cdl = console.cdl().unwrap();
+    let prg = cdl.prg();
+    assert_eq!(prg[0x00], PrgAccess::OPCODE);
+    assert_eq!(prg[0x01], PrgAccess::OPERAND);
+    assert_eq!(prg[0x08], PrgAccess::empty());
+    assert_eq!(prg[0x10], PrgAccess::OPCODE | PrgAccess::INDIRECT_CODE);
+    assert_eq!(&prg[0x20..0x24], [PrgAccess::DATA, PrgAccess::empty(), PrgAccess::DATA, PrgAccess::DATA]);
+    // リセットベクタ
+    assert_eq!(prg[0x3FFC], PrgAccess::DATA);
+
+    // $C000-$DFFF は8KBの窓 2 に当たる。
+    let fceux = cdl.to_fceux();
+    assert_eq!(fceux.len(), rom::PRG_ROM_UNIT_SIZE + rom::CHR_ROM_UNIT_SIZE);
+    assert_eq!(&fceux[0x00..0x03], [0x09, 0x09, 0x09]);
+    assert_eq!(fceux[0x10], 0x19);
+    assert_eq!(fceux[0x20], 0x0A);
+    assert_eq!(fceux[0x3FFC], 0x0E);
+    assert_eq!(cdl.summary().code, 11);
+}
+
+#[test]
+fn rendered_and_read_tiles_are_logged() {
+    // 電源投入時のVBlankフラグを読み捨て、さらに2回VBlankを待ってPPUが応答するようになってから、
+    // BGのパターンテーブルを$1000にして描画を有効にし、PPUDATAから$0000を読む。
+    let program = [
+        0x2C, 0x02, 0x20,       // C000  BIT $2002
+        0x2C, 0x02, 0x20,       // C003  BIT $2002
+        0x10, 0xFB,             // C006  BPL $C003
+        0x2C, 0x02, 0x20,       // C008  BIT $2002
+        0x10, 0xFB,             // C00B  BPL $C008
+        0xA9, 0x10,             // C00D  LDA #$10
+        0x8D, 0x00, 0x20,       // C00F  STA $2000
+        0xA9, 0x18,             // C012  LDA #$18
+        0x8D, 0x01, 0x20,       // C014  STA $2001
+        0xA9, 0x00,             // C017  LDA #$00
+        0x8D, 0x06, 0x20,       // C019  STA $2006
+        0x8D, 0x06, 0x20,       // C01C  STA $2006
+        0xAD, 0x07, 0x20,       // C01F  LDA $2007
+        0x4C, 0x22, 0xC0,       // C022  JMP $C022
+    ];
+    let mut console = common::console(&program, &[], |console, rom| {
+        console.set_cdl(Some(CodeDataLogger::new(rom)));
+        let mut ppu = console.ppu().borrow_mut();
+        // ネームテーブルの左上にタイル$05を置き、スプライト0だけをタイル$07で表示する。
+        ppu.poke_vram(0x2000, 0x05);
+        for sprite in 0..64u8 {
+            ppu.poke_oam(sprite * 4, if sprite == 0 { 0x10 } else { 0xFF });
+        }
+        ppu.poke_oam(1, 0x07);
+    });
+    while console.cpu().regs().pc != 0xC022 {
+        console.step_instruction();
+    }
+    console.step_frame();
+
+    let cdl = console.cdl().unwrap();
+    let chr = cdl.chr();
+    // BGは$1000、スプライトは$0000のパターンテーブルを使う。
+    assert_eq!(chr[0x1050], ChrAccess::BACKGROUND);
+    assert_eq!(chr[0x105F], ChrAccess::BACKGROUND);
+    // ネームテーブルの残りはタイル$00
+    assert_eq!(chr[0x1000], ChrAccess::BACKGROUND);
+    assert_eq!(chr[0x0070], ChrAccess::SPRITE);
+    assert_eq!(chr[0x0000], ChrAccess::READ);
+    // 使われていないタイル
+    assert_eq!(chr[0x0090], ChrAccess::empty());
+    assert_eq!(chr[0x1090], ChrAccess::empty());
+
+    let fceux = cdl.to_fceux();
+    let chr_start = rom::PRG_ROM_UNIT_SIZE;
+    assert_eq!(fceux[chr_start], 0x02);
+    assert_eq!(fceux[chr_start + 0x0070], 0x01);
+    assert_eq!(fceux[chr_start + 0x1050], 0x01);
+    assert_eq!(fceux[chr_start + 0x1090], 0x00);
+    let summary = cdl.summary();
+    assert_eq!((summary.rendered, summary.chr_read), (48, 1));
+}
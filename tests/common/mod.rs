@@ -0,0 +1,49 @@
+//! 結合テストで共有する、テスト用のROMとConsoleを作る関数。
+#![allow(dead_code)]
+
+use fami_rust::nes::rom::{self, NesRom};
+use fami_rust::Console;
+
+/// サブルーチンを呼び続けるプログラム。$C000 に置く。
+/// ```text
+/// C000  LDX #$00
+/// C002  JSR $C010
+/// C005  INX
+/// C006  STX $10
+/// C008  JMP $C002
+/// C010  LDA $10
+/// C012  RTS
+/// ```
+pub const PROGRAM: [u8; 0x13] = [
+    0xA2, 0x00, 0x20, 0x10, 0xC0, 0xE8, 0x86, 0x10, 0x4C, 0x02, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xA5, 0x10, 0x60,
+];
+
+/// program を $C000 に、nmi を $C020 に置いたNROMのROMイメージを作る。
+/// リセットベクタは $C000、NMIベクタは $C020 を指す。
+pub fn nrom_bin(program: &[u8], nmi: &[u8]) -> Vec<u8> {
+    let mut bin = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    bin.resize(16 + rom::PRG_ROM_UNIT_SIZE + rom::CHR_ROM_UNIT_SIZE, 0);
+    let prg = &mut bin[16..16 + rom::PRG_ROM_UNIT_SIZE];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x20..0x20 + nmi.len()].copy_from_slice(nmi);
+    prg[0x3FFA..0x3FFE].copy_from_slice(&[0x20, 0xC0, 0x00, 0xC0]);
+    bin
+}
+
+/// nrom_bin のROMで電源を入れたConsoleを作る。
+/// setup は電源を入れる前に呼ばれるので、ここでテスト対象の機能を設定する。
+pub fn console(program: &[u8], nmi: &[u8], setup: impl FnOnce(&mut Console, &NesRom)) -> Console {
+    let rom = rom::parse(&nrom_bin(program, nmi)).unwrap();
+    let mut console = Console::new(&rom);
+    setup(&mut console, &rom);
+    console.power_on();
+    console
+}
+
+/// PROGRAM のROMで、リセット直後の最初の命令($C000)で止めたConsoleを作る。
+pub fn console_at_reset(setup: impl FnOnce(&mut Console, &NesRom)) -> Console {
+    let mut console = console(&PROGRAM, &[], setup);
+    console.step_instruction();
+    console
+}
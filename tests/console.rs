@@ -0,0 +1,55 @@
+//! ROMの読み込みと、Consoleの入出力のテスト。
+
+mod common;
+
+use fami_rust::nes::joypad::Buttons;
+use fami_rust::nes::rom;
+
+#[test]
+fn truncated_roms_are_rejected() {
+    let bin = common::nrom_bin(&[], &[]);
+    // CHR-ROMの途中まで
+    assert!(rom::parse(&bin[..bin.len() - 1]).is_err());
+    // PRG-ROMの途中まで
+    assert!(rom::parse(&bin[..16 + rom::PRG_ROM_UNIT_SIZE - 1]).is_err());
+    // トレーナーがあるはずのヘッダだけ
+    let mut header = bin[..16].to_vec();
+    header[6] |= 0x04;
+    assert!(rom::parse(&header).is_err());
+    assert!(rom::parse(&bin).is_ok());
+}
+
+#[test]
+fn only_nrom_is_supported() {
+    let mut bin = common::nrom_bin(&[], &[]);
+    assert!(rom::parse(&bin).unwrap().is_mapper_supported());
+    // フラグ6の上位4bitがマッパー番号の下位4bit (1 = MMC1)
+    bin[6] |= 0x10;
+    let mmc1 = rom::parse(&bin).unwrap();
+    assert_eq!(mmc1.mapper_no(), 1);
+    assert!(!mmc1.is_mapper_supported());
+    assert_eq!(mmc1.check_mapper_supported().unwrap_err().to_string(),
+        "Unsupported mapper: 1 (only NROM, mapper 0, is implemented)");
+}
+
+#[test]
+fn writes_to_rom_are_ignored() {
+    let program = [
+        0xA9, 0x55,             // C000  LDA #$55
+        0x8D, 0x00, 0x80,       // C002  STA $8000
+        0x4C, 0x05, 0xC0,       // C005  JMP $C005
+    ];
+    let mut console = common::console(&program, &[], |_, _| {});
+    for _ in 0..4 {
+        console.step_instruction();
+    }
+    assert_eq!(console.cpu().regs().pc, 0xC005);
+    assert_eq!(console.peek(0x8000), 0xA9);
+}
+
+#[test]
+fn buttons_of_unknown_ports_are_ignored() {
+    let mut console = common::console(&[], &[], |_, _| {});
+    console.set_buttons(0, Buttons::A);
+    console.set_buttons(2, Buttons::A);
+}
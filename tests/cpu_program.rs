@@ -0,0 +1,136 @@
+//! フラットなRAMに置いたプログラムを、公開APIだけを使ってCPUで実行するテスト。
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use fami_rust::nes::cpu::Cpu;
+use fami_rust::nes::cpu_bus::{CpuBus, FlatBus};
+
+/// 1クロックごとに tick が呼ばれた回数を数えるバス。
+struct CountingBus {
+    ram: FlatBus,
+    ticks: Rc<Cell<u64>>,
+}
+
+impl CpuBus for CountingBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.ram.write(addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    fn tick(&mut self) {
+        self.ticks.set(self.ticks.get() + 1);
+    }
+}
+
+fn cpu_with(program: &[(u16, &[u8])]) -> Cpu {
+    let mut bus = FlatBus::new();
+    for (addr, bytes) in program {
+        bus.load(*addr, bytes);
+    }
+    Cpu::new(Box::new(bus))
+}
+
+/// PCが指定のアドレスに到達するまで、1命令ずつ実行する。
+fn run_until(cpu: &mut Cpu, pc: u16, max_cycles: u64) {
+    while cpu.regs().pc != pc || !cpu.is_instruction_boundary() {
+        assert!(cpu.clock_counter() < max_cycles, "PC={:#06X}", cpu.regs().pc);
+        cpu.step();
+    }
+}
+
+#[test]
+fn reset_sequence_jumps_to_reset_vector() {
+    let mut cpu = cpu_with(&[(0xFFFC, &[0x00, 0x06])]);
+    cpu.power_on();
+    run_until(&mut cpu, 0x0600, 100);
+
+    assert_eq!(cpu.clock_counter(), 7);
+    assert_eq!(cpu.regs().s, 0xFD);
+    assert_eq!(cpu.regs().p, 0x24);
+}
+
+#[test]
+fn flat_bus_load_wraps_around() {
+    let mut ram = FlatBus::new();
+    ram.load(0xFFFF, &[0x01, 0x02]);
+    assert_eq!(ram.peek(0xFFFF), 0x01);
+    assert_eq!(ram.peek(0x0000), 0x02);
+    ram.poke(0x0000, 0x03);
+    assert_eq!(ram.peek(0x0000), 0x03);
+
+    // poke を実装していないバスは、書き換えられない。
+    let mut bus = CountingBus { ram, ticks: Rc::new(Cell::new(0)) };
+    bus.poke(0x0000, 0xFF);
+    assert_eq!(bus.peek(0x0000), 0x03);
+}
+
+#[test]
+fn bus_is_ticked_every_cycle() {
+    let mut ram = FlatBus::new();
+    ram.load(0xFFFC, &[0x00, 0x06]);
+    // 0600: JMP $0600
+    ram.load(0x0600, &[0x4C, 0x00, 0x06]);
+    let ticks = Rc::new(Cell::new(0));
+    let mut cpu = Cpu::new(Box::new(CountingBus { ram, ticks: Rc::clone(&ticks) }));
+    cpu.power_on();
+    for _ in 0..100 {
+        cpu.step();
+    }
+    assert_eq!(ticks.get(), 100);
+    assert_eq!(cpu.clock_counter(), 100);
+}
+
+#[test]
+fn subroutine_loop_and_software_interrupt() {
+    let mut cpu = cpu_with(&[
+        (0x0600, &[
+            0xA2, 0x0A,       // 0600: LDX #$0A
+            0xA9, 0x00,       // 0602: LDA #$00
+            0x18,             // 0604: CLC
+            0x20, 0x20, 0x06, // 0605: JSR $0620
+            0xCA,             // 0608: DEX
+            0xD0, 0xF9,       // 0609: BNE $0604
+            0x8D, 0x00, 0x02, // 060B: STA $0200
+            0x00, 0xEA,       // 060E: BRK
+            0x4C, 0x10, 0x06, // 0610: JMP $0610
+        ]),
+        (0x0620, &[
+            0x86, 0x10,       // 0620: STX $10
+            0x65, 0x10,       // 0622: ADC $10
+            0x60,             // 0624: RTS
+        ]),
+        (0x0700, &[
+            0x8D, 0x01, 0x02, // 0700: STA $0201
+            0x08,             // 0703: PHP
+            0x68,             // 0704: PLA
+            0x8D, 0x02, 0x02, // 0705: STA $0202
+            0x40,             // 0708: RTI
+        ]),
+        // IRQ/BRKベクタ
+        (0xFFFE, &[0x00, 0x07]),
+    ]);
+    {
+        let regs = cpu.regs_mut();
+        regs.s = 0xFD;
+        regs.p = 0x24;
+    }
+    cpu.jump_to(0x0600);
+    run_until(&mut cpu, 0x0610, 10_000);
+
+    // 10 + 9 + ... + 1
+    assert_eq!(cpu.mem().peek(0x0200), 55);
+    assert_eq!(cpu.mem().peek(0x0201), 55);
+    // 割り込みハンドラ内のPHPでは、Breakフラグと予約ビットが立つ。
+    assert_eq!(cpu.mem().peek(0x0202), 0x36);
+    // RTIで割り込み前の状態に戻る。
+    assert_eq!(cpu.regs().s, 0xFD);
+    assert_eq!(cpu.regs().p, 0x26);
+}
@@ -0,0 +1,156 @@
+//! デバッガーのテスト。
+
+mod common;
+
+use fami_rust::nes::debugger::{CompareOp, Condition, Debugger, Register, StopReason};
+use fami_rust::nes::debugger::command::{Command, SearchCommand};
+use fami_rust::nes::debugger::memory::MemorySpace;
+use fami_rust::nes::debugger::ram_search::{Candidate, RamSearch, SearchFilter, ValueType};
+use fami_rust::nes::debugger::watch::Access;
+use fami_rust::Console;
+
+/// common::PROGRAM の、リセット直後の最初の命令で止めたConsoleを作る。
+fn console() -> Console {
+    common::console_at_reset(|_, _| {})
+}
+
+fn pc(console: &Console) -> u16 {
+    console.cpu().regs().pc
+}
+
+#[test]
+fn breakpoints_stop_before_the_instruction() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    let id = debugger.add_breakpoint(0xC005, None);
+    assert_eq!(debugger.run(&mut console, 1), StopReason::Breakpoint(id));
+    assert_eq!(pc(&console), 0xC005);
+
+    // 条件付きブレークポイント
+    debugger.remove(id);
+    let id = debugger.add_breakpoint(0xC006, Condition::parse("x==3"));
+    assert_eq!(debugger.run(&mut console, 1), StopReason::Breakpoint(id));
+    assert_eq!(console.cpu().regs().x, 3);
+}
+
+#[test]
+fn step_over_and_step_out() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    debugger.step(&mut console);
+    assert_eq!(pc(&console), 0xC002);
+    assert_eq!(debugger.step_over(&mut console, 1), StopReason::Step);
+    assert_eq!(pc(&console), 0xC005);
+
+    let id = debugger.add_breakpoint(0xC010, None);
+    assert_eq!(debugger.run(&mut console, 1), StopReason::Breakpoint(id));
+    assert_eq!(debugger.step_out(&mut console, 1), StopReason::Step);
+    assert_eq!(pc(&console), 0xC005);
+}
+
+#[test]
+fn watchpoints_report_the_access() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    let id = debugger.add_watchpoint(0x0010..=0x0010, Access::WRITE);
+    match debugger.run(&mut console, 1) {
+        StopReason::Watchpoint(hit) => {
+            assert_eq!((hit.id, hit.addr, hit.access, hit.data), (id, 0x0010, Access::WRITE, 1));
+        },
+        reason => panic!("unexpected stop: {:?}", reason),
+    }
+    // 書き込んだ命令の直後で止まる。
+    assert_eq!(pc(&console), 0xC008);
+
+    debugger.remove(id);
+    let id = debugger.add_watchpoint(0xC010..=0xC012, Access::EXECUTE);
+    match debugger.run(&mut console, 1) {
+        StopReason::Watchpoint(hit) => assert_eq!((hit.id, hit.addr), (id, 0xC010)),
+        reason => panic!("unexpected stop: {:?}", reason),
+    }
+    assert_eq!(pc(&console), 0xC010);
+}
+
+#[test]
+fn run_to_scanline_stops_when_the_line_starts() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    assert_eq!(debugger.run_to_scanline(&mut console, 241, 1), StopReason::Scanline(241));
+    assert_eq!(console.ppu_position().0, 241);
+}
+
+#[test]
+fn commands_edit_registers_and_memory() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    assert_eq!(Command::parse("set a $7f"), Ok(Command::Set(Register::A, 0x7F)));
+    debugger.execute(&mut console, &Command::parse("set a 7f").unwrap());
+    assert_eq!(console.cpu().regs().a, 0x7F);
+
+    debugger.execute(&mut console, &Command::parse("poke 0 12 34").unwrap());
+    // 物理RAMのミラーにも反映される。
+    assert_eq!((console.peek(0x0800), console.peek(0x0801)), (0x12, 0x34));
+    assert_eq!(debugger.execute(&mut console, &Command::parse("m 800 2").unwrap()), "0800: 12 34");
+
+    debugger.execute(&mut console, &Command::parse("set pc c010").unwrap());
+    assert_eq!(pc(&console), 0xC010);
+    // 8bitのレジスタとメモリに、1バイトを超える値は書けない。
+    assert_eq!(Command::parse("set a 1ff"), Err("set: value out of range".to_string()));
+    assert_eq!(Command::parse("poke 300 1234"), Err("poke: value out of range".to_string()));
+    assert_eq!(Command::parse("set pc 1ff"), Ok(Command::Set(Register::Pc, 0x1FF)));
+    assert!(Command::parse("w 10-8").is_err());
+    assert!(Command::parse("b c000 if q==1").is_err());
+}
+
+#[test]
+fn memory_spaces_can_be_viewed_and_edited() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    // パレットの $3F10 は $3F00 のミラー
+    debugger.execute(&mut console, &Command::parse("poke ppu:3f10 0f 21").unwrap());
+    assert_eq!(debugger.execute(&mut console, &Command::parse("m ppu:3f00 2").unwrap()), "3F00: 0F 00");
+    assert_eq!(MemorySpace::Ppu.peek(&console, 0x3F11), 0x21);
+    // OAMの属性バイトには存在しないビットがある。
+    debugger.execute(&mut console, &Command::parse("poke oam:4 10 20 ff").unwrap());
+    assert_eq!(console.ppu().borrow().spr_ram()[4..7], [0x10, 0x20, 0xE3]);
+    debugger.execute(&mut console, &Command::parse("poke sram:1 5a").unwrap());
+    assert_eq!(console.peek(0x6001), 0x5A);
+    assert!(Command::parse("m oam:100").is_err());
+    assert!(Command::parse("m rom:0").is_err());
+
+    // 表示は止まるたびに作り直され、変化したバイトに印が付く。
+    assert_eq!(Command::parse("disp 10 2"), Ok(Command::Display(MemorySpace::Cpu, 0x10, 2)));
+    assert_eq!(debugger.execute(&mut console, &Command::parse("disp 10 2").unwrap()), "display #1\n0010: 00 00");
+    let id = debugger.add_breakpoint(0xC008, None);
+    let out = debugger.execute(&mut console, &Command::Continue(1));
+    assert!(out.ends_with("\n#1 cpu:0010\n0010: 01* 00"), "{}", out);
+    debugger.remove(id);
+    assert!(debugger.execute(&mut console, &Command::Step(1)).ends_with("\n#1 cpu:0010\n0010: 01 00"));
+    assert!(debugger.remove(1));
+}
+
+#[test]
+fn ram_search_narrows_down_the_candidates() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    debugger.add_breakpoint(0xC008, None);
+    debugger.run(&mut console, 1);
+    assert_eq!(debugger.execute(&mut console, &Command::parse("sr new").unwrap()), "10240 candidate(s) (u8)");
+    debugger.run(&mut console, 1);
+    // JSRで積んだ戻りアドレスは変化しないので、カウンターだけが残る。
+    assert_eq!(debugger.execute(&mut console, &Command::parse("sr >").unwrap()), "1 candidate(s) (u8)\n$0010 = 2 ($02)");
+    assert_eq!(debugger.execute(&mut console, &Command::parse("sr unchanged").unwrap()), "1 candidate(s) (u8)\n$0010 = 2 ($02)");
+    assert_eq!(debugger.execute(&mut console, &Command::parse("sr != 2").unwrap()), "0 candidate(s) (u8)");
+
+    // 16bitの符号付きの値
+    console.poke(0x0020, 0xFF);
+    console.poke(0x0021, 0xFF);
+    let mut search = RamSearch::new(&console, ValueType::S16);
+    assert_eq!(search.candidates().len(), 0x7FF + 0x1FFF);
+    assert_eq!(search.filter(&console, SearchFilter::Value(CompareOp::Eq, -1)), 1);
+    assert_eq!(search.candidates()[0], Candidate { addr: 0x0020, previous: -1 });
+    console.poke(0x0021, 0x00);
+    assert_eq!(search.filter(&console, SearchFilter::Previous(CompareOp::Gt)), 1);
+    assert_eq!(Command::parse("sr == -1"), Ok(Command::Search(SearchCommand::Filter(SearchFilter::Value(CompareOp::Eq, -1)))));
+    assert!(Command::parse("sr new u32").is_err());
+}
@@ -0,0 +1,57 @@
+//! 逆アセンブラのテスト。
+
+use fami_rust::nes::cpu::disasm::{self, DisasmLine};
+
+fn texts(lines: &[DisasmLine]) -> Vec<&str> {
+    lines.iter().map(|line| line.text.as_str()).collect()
+}
+
+#[test]
+fn formats_every_addressing_mode() {
+    let bin = [
+        0xEA,             // NOP
+        0x0A,             // ASL A
+        0xA9, 0x12,       // LDA #$12
+        0xA5, 0x12,       // LDA $12
+        0xB5, 0x12,       // LDA $12,X
+        0xB6, 0x12,       // LDX $12,Y
+        0xAD, 0x34, 0x12, // LDA $1234
+        0xBD, 0x34, 0x12, // LDA $1234,X
+        0xB9, 0x34, 0x12, // LDA $1234,Y
+        0xA1, 0x12,       // LDA ($12,X)
+        0xB1, 0x12,       // LDA ($12),Y
+        0x6C, 0x34, 0x12, // JMP ($1234)
+        0x00,             // BRK
+    ];
+    let lines = disasm::disasm_bytes(&bin, 0x8000);
+    assert_eq!(texts(&lines), [
+        "NOP", "ASL A", "LDA #$12", "LDA $12", "LDA $12,X", "LDX $12,Y",
+        "LDA $1234", "LDA $1234,X", "LDA $1234,Y", "LDA ($12,X)", "LDA ($12),Y",
+        "JMP ($1234)", "BRK",
+    ]);
+    assert_eq!(lines[6].addr, 0x800A);
+    assert_eq!(lines[6].bytes, [0xAD, 0x34, 0x12]);
+}
+
+#[test]
+fn branch_targets_are_absolute() {
+    // C0EF: BNE $C0F3 (前方), C0F1: BEQ $C0EF (後方)
+    let lines = disasm::disasm_bytes(&[0xD0, 0x02, 0xF0, 0xFC], 0xC0EF);
+    assert_eq!(texts(&lines), ["BNE $C0F3", "BEQ $C0EF"]);
+}
+
+#[test]
+fn unknown_and_truncated_bytes_are_data() {
+    // 0x02は未定義、最後のLDA absはオペランドが途切れている。
+    let lines = disasm::disasm_bytes(&[0x02, 0xAD, 0x34], 0x8000);
+    assert_eq!(texts(&lines), [".byte $02", ".byte $AD", ".byte $34"]);
+    assert_eq!(lines[0].to_string(), "8000  02        .byte $02");
+}
+
+#[test]
+fn disassembles_a_memory_range() {
+    let mem = [0x4C, 0xF5, 0xC5, 0x60];
+    let lines = disasm::disasm_range(|addr| mem[(addr - 0xC000) as usize], 0xC000, 0xC003);
+    assert_eq!(texts(&lines), ["JMP $C5F5", "RTS"]);
+    assert_eq!(lines[0].to_string(), "C000  4C F5 C5  JMP $C5F5");
+}
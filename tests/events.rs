@@ -0,0 +1,96 @@
+//! イベントビューアーのテスト。
+
+mod common;
+
+use fami_rust::nes::debugger::events::{self, EventKind, GRID_WIDTH};
+
+/// 2回VBlankを待ってから、NMIを有効にして無限ループする。
+const PROGRAM: [u8; 21] = [
+    0x2C, 0x02, 0x20,       // C000  BIT $2002
+    0x2C, 0x02, 0x20,       // C003  BIT $2002
+    0x10, 0xFB,             // C006  BPL $C003
+    0x2C, 0x02, 0x20,       // C008  BIT $2002
+    0x10, 0xFB,             // C00B  BPL $C008
+    0xA9, 0x80,             // C00D  LDA #$80
+    0x8D, 0x00, 0x20,       // C00F  STA $2000
+    0x4C, 0x12, 0xC0,       // C012  JMP $C012
+];
+
+/// NMIハンドラ。PPUSCROLL、APU、OAMDMAのレジスタに書き込む。
+const NMI: [u8; 15] = [
+    0xA9, 0x00,             // C020  LDA #$00
+    0x8D, 0x05, 0x20,       // C022  STA $2005
+    0x8D, 0x0D, 0x20,       // C025  STA $200D
+    0x8D, 0x15, 0x40,       // C028  STA $4015
+    0x8D, 0x14, 0x40,       // C02B  STA $4014
+    0x40,                   // C02E  RTI
+];
+
+#[test]
+fn writes_are_recorded_with_the_ppu_position() {
+    let mut console = common::console(&PROGRAM, &NMI, |console, _| console.set_event_logging(true));
+    for _ in 0..4 {
+        console.step_frame();
+    }
+
+    let log = console.event_log();
+    let events = log.last_frame();
+    let kinds: Vec<EventKind> = events.iter().map(|event| event.kind).collect();
+    assert_eq!(kinds, [EventKind::PpuScroll, EventKind::PpuScroll, EventKind::Apu, EventKind::OamDma]);
+    // $2005 のミラーへの書き込み
+    assert_eq!((events[1].addr, events[1].pc), (0x200D, 0xC025));
+    // VBlankの開始(241ライン目)のNMIで書き込んでいる。
+    assert!(events.iter().all(|event| event.scanline == 241));
+    assert!(events.windows(2).all(|pair| pair[0].dot < pair[1].dot));
+    assert!(console.event_log().current().is_empty());
+}
+
+#[test]
+fn events_are_drawn_on_the_grid_and_listed() {
+    let mut console = common::console(&PROGRAM, &NMI, |console, _| console.set_event_logging(true));
+    for _ in 0..4 {
+        console.step_frame();
+    }
+    let log = console.event_log();
+    let events = log.last_frame();
+
+    let rgba = events::render_grid(events, 262, None);
+    assert_eq!(rgba.len(), GRID_WIDTH * 262 * 4);
+    let pixel = |x: usize, y: usize| &rgba[(y * GRID_WIDTH + x) * 4..(y * GRID_WIDTH + x + 1) * 4];
+    let last = events[3];
+    assert_eq!(pixel(last.dot as usize, 241), EventKind::OamDma.color());
+    assert_eq!(pixel(last.dot as usize + 1, 242), EventKind::OamDma.color());
+    assert_eq!(pixel(100, 100), [0x20, 0x20, 0x20, 0xFF]);
+
+    let list = events::format_list(events);
+    assert_eq!(list.lines().count(), 4);
+    assert_eq!(list.lines().nth(2).unwrap(),
+        format!("241,{:>3}  $4015 = $00  APU        PC:$C028", events[2].dot));
+
+    // 記録をやめると捨てる。
+    drop(log);
+    console.set_event_logging(false);
+    console.step_frame();
+    assert!(console.event_log().last_frame().is_empty());
+}
+
+#[test]
+fn mapper_writes_are_recorded() {
+    // NROMにマッパーのレジスタは無いが、$8000-$FFFF への書き込みは記録され、ROMは変わらない。
+    let program = [
+        0xA9, 0x01,             // C000  LDA #$01
+        0x8D, 0x00, 0x80,       // C002  STA $8000
+        0x4C, 0x05, 0xC0,       // C005  JMP $C005
+    ];
+    let mut console = common::console(&program, &[], |console, _| console.set_event_logging(true));
+    for _ in 0..3 {
+        console.step_instruction();
+    }
+
+    let log = console.event_log();
+    let events = log.current();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].kind, events[0].addr, events[0].data, events[0].pc), (EventKind::Mapper, 0x8000, 0x01, 0xC002));
+    drop(log);
+    assert_eq!(console.peek(0xC000), 0xA9);
+}
@@ -0,0 +1,150 @@
+//! GDBのリモートプロトコルのスタブのテスト。
+
+mod common;
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use fami_rust::nes::debugger::gdb::{Connection, GdbStub};
+use fami_rust::Console;
+
+/// パケットを1つ処理し、返答を返す。実行中の中断要求は来ないものとする。
+fn request(stub: &mut GdbStub, console: &mut Console, packet: &str) -> String {
+    stub.handle(console, packet, &mut || false).expect("connection closed")
+}
+
+/// 受信するバイト列を事前に用意し、送信したバイト列を記録する接続。
+struct ScriptedConnection {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for ScriptedConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for ScriptedConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for ScriptedConnection {}
+
+#[test]
+fn registers_are_read_and_written_in_gdb_order() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    let p = console.cpu().regs().p;
+    let s = console.cpu().regs().s;
+    assert_eq!(request(&mut stub, &mut console, "g"), format!("000000{:02x}{:02x}00c0", s, p));
+
+    assert_eq!(request(&mut stub, &mut console, "G1122330424"), "E01");
+    assert_eq!(request(&mut stub, &mut console, "G11223304240cc0"), "OK");
+    let regs = console.cpu().regs();
+    assert_eq!((regs.a, regs.x, regs.y, regs.s, regs.p, regs.pc), (0x11, 0x22, 0x33, 0x04, 0x24, 0xC00C));
+
+    assert_eq!(request(&mut stub, &mut console, "P5=05c0"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "p5"), "05c0");
+    assert_eq!(request(&mut stub, &mut console, "p0"), "11");
+}
+
+#[test]
+fn memory_is_read_and_written() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    assert_eq!(request(&mut stub, &mut console, "mc000,5"), "a2002010c0");
+    assert_eq!(request(&mut stub, &mut console, "M0010,2:abcd"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "m10,2"), "abcd");
+    // 書き込むバイト数が一致しない
+    assert_eq!(request(&mut stub, &mut console, "M0010,2:ab"), "E01");
+    // 1回に読み書きできるのは、16進数でパケットに収まる 0x800 バイトまで。
+    assert_eq!(request(&mut stub, &mut console, "m0,ffffffff").len(), 0x1000);
+    let data = "00".repeat(0x801);
+    assert_eq!(request(&mut stub, &mut console, &format!("M0,801:{}", data)), "E01");
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    assert_eq!(request(&mut stub, &mut console, "s"), "S05");
+    assert_eq!(console.cpu().regs().pc, 0xC002);
+
+    assert_eq!(request(&mut stub, &mut console, "Z0,c005,1"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "c"), "S05");
+    assert_eq!(console.cpu().regs().pc, 0xC005);
+
+    // ブレークポイントを外すと、中断されるまで止まらない。
+    assert_eq!(request(&mut stub, &mut console, "z0,c005,1"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "z0,c005,1"), "E01");
+    let mut polls = 0;
+    let reply = stub.handle(&mut console, "c", &mut || { polls += 1; polls == 3 });
+    assert_eq!(reply.as_deref(), Some("S02"));
+    assert!(console.frame_count() >= 3);
+}
+
+#[test]
+fn watchpoints_report_the_accessed_address() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    assert_eq!(request(&mut stub, &mut console, "Z2,10,1"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "c"), "T05watch:0010;");
+    assert_eq!(console.cpu().regs().pc, 0xC008);
+    assert_eq!(request(&mut stub, &mut console, "z2,10,1"), "OK");
+
+    assert_eq!(request(&mut stub, &mut console, "Z3,10,1"), "OK");
+    assert_eq!(request(&mut stub, &mut console, "c"), "T05rwatch:0010;");
+    assert_eq!(console.cpu().regs().pc, 0xC012);
+}
+
+#[test]
+fn packets_are_framed_and_acknowledged() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    let mut conn = ScriptedConnection {
+        // チェックサムが誤ったパケットには '-' を返す。
+        input: Cursor::new(b"+$qSupported:multiprocess+#c6$?#00$?#3f$QStartNoAckMode#b0$m0,1#fa$D#44".to_vec()),
+        output: Vec::new(),
+    };
+    stub.serve(&mut console, &mut conn).unwrap();
+    let output = String::from_utf8(conn.output).unwrap();
+    assert_eq!(output, "+$PacketSize=1000;qXfer:features:read+;QStartNoAckMode+#e2-+$S05#b8+$OK#9a$00#60$OK#9a");
+}
+
+#[test]
+fn unknown_packets_get_an_empty_reply() {
+    let mut console = common::console_at_reset(|_, _| {});
+    let mut stub = GdbStub::new(&console);
+    assert_eq!(request(&mut stub, &mut console, "vMustReplyEmpty"), "");
+    assert_eq!(stub.handle(&mut console, "k", &mut || false), None);
+}
+
+#[test]
+fn only_interrupts_are_consumed_while_running() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let mut gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    // 届くまで待つ(ブロックするpeek)。
+    let wait = |stream: &TcpStream| assert_eq!(stream.peek(&mut [0]).unwrap(), 1);
+
+    assert!(!stream.interrupt_requested().unwrap());
+    gdb.write_all(b"+").unwrap();
+    wait(&stream);
+    // 中断要求でないバイトは読み捨てない。
+    assert!(!stream.interrupt_requested().unwrap());
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"+");
+
+    gdb.write_all(&[0x03]).unwrap();
+    wait(&stream);
+    assert!(stream.interrupt_requested().unwrap());
+    assert!(!stream.interrupt_requested().unwrap());
+}
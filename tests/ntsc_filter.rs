@@ -0,0 +1,77 @@
+//! NTSCフィルターのテスト。
+
+use fami_rust::nes::ppu::{MaskFlags, SCREEN_WIDTH, SCREEN_HEIGHT};
+use fami_rust::nes::ppu::ntsc_filter::{NtscFilter, NtscPreset, OUTPUT_WIDTH, OUTPUT_HEIGHT};
+use fami_rust::nes::ppu::palette::{NtscParams, Palette};
+
+/// 全面を1色で塗ったフレームを、フィルターに通す。
+fn filter_solid(preset: NtscPreset, index: u8, frame_count: u64) -> Vec<u8> {
+    filter_solid_with_mask(preset, index, 0, frame_count)
+}
+
+fn filter_solid_with_mask(preset: NtscPreset, index: u8, mask: u8, frame_count: u64) -> Vec<u8> {
+    let frame = vec![index; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut out = vec![0; OUTPUT_WIDTH * OUTPUT_HEIGHT * 4];
+    NtscFilter::new(preset, NtscParams::default()).filter(&frame, mask, frame_count, &mut out);
+    out
+}
+
+/// 画面中央付近のピクセルの色
+fn center(out: &[u8]) -> [u8; 3] {
+    let i = ((OUTPUT_HEIGHT / 2) * OUTPUT_WIDTH + OUTPUT_WIDTH / 2) * 4;
+    [out[i], out[i + 1], out[i + 2]]
+}
+
+fn close(a: [u8; 3], b: [u8; 3]) -> bool {
+    a.iter().zip(b.iter()).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 2)
+}
+
+#[test]
+fn rgb_preset_matches_palette() {
+    let palette = Palette::generate(&NtscParams::default());
+    let out = filter_solid(NtscPreset::Rgb, 0x16, 0);
+    assert_eq!(center(&out), palette.color(0x16, 0));
+    assert_eq!(out[3], 0xFF);
+}
+
+#[test]
+fn svideo_reproduces_solid_colors() {
+    let palette = Palette::generate(&NtscParams::default());
+    for index in [0x16, 0x1A, 0x21, 0x30, 0x0F] {
+        let actual = center(&filter_solid(NtscPreset::SVideo, index, 0));
+        let expected = palette.color(index, 0);
+        assert!(close(actual, expected), "{:02X}: {:?} != {:?}", index, actual, expected);
+    }
+}
+
+#[test]
+fn composite_dot_crawl_changes_between_frames() {
+    // 色のある面では、輝度に漏れた色信号のパターンがフレームごとに動く。
+    let frames: Vec<Vec<u8>> = (0..3).map(|n| filter_solid(NtscPreset::Composite, 0x16, n)).collect();
+    assert_ne!(frames[0], frames[1]);
+    assert_ne!(frames[1], frames[2]);
+    // 3フレームで一巡する。
+    assert_eq!(frames[0], filter_solid(NtscPreset::Composite, 0x16, 3));
+
+    // S端子ではドットクロールは発生しない。
+    assert_eq!(center(&filter_solid(NtscPreset::SVideo, 0x16, 0)),
+               center(&filter_solid(NtscPreset::SVideo, 0x16, 1)));
+}
+
+#[test]
+fn composite_gray_has_no_artifacts() {
+    let palette = Palette::generate(&NtscParams::default());
+    let out = filter_solid(NtscPreset::Composite, 0x10, 0);
+    let [r, g, b] = center(&out);
+    assert!(r == g && g == b);
+    assert!(close([r, g, b], palette.color(0x10, 0)));
+}
+
+#[test]
+fn grayscale_drops_the_hue_in_every_preset() {
+    let grayscale = MaskFlags::GRAYSCALE.bits();
+    for preset in [NtscPreset::Composite, NtscPreset::SVideo, NtscPreset::Rgb] {
+        let gray = filter_solid_with_mask(preset, 0x16, grayscale, 0);
+        assert_eq!(gray, filter_solid(preset, 0x10, 0), "{:?}", preset);
+    }
+}
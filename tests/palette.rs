@@ -0,0 +1,114 @@
+//! パレットのインデックスからRGBへの変換のテスト。
+
+use fami_rust::nes::ppu::palette::{NtscParams, Palette, COLORS, EMPHASIS_COLORS};
+use fami_rust::nes::ppu::MaskFlags;
+
+/// インデックス i の色が (i, i/2, i/4) になる .pal ファイルの内容
+fn ramp_pal(colors: usize) -> Vec<u8> {
+    (0..colors).flat_map(|i| [i as u8, (i >> 1) as u8, (i >> 2) as u8]).collect()
+}
+
+#[test]
+fn builtin_palette_has_black_and_white() {
+    let palette = Palette::ntsc_2c02();
+    assert_eq!(palette.color(0x0F, 0), [0, 0, 0]);
+    assert_eq!(palette.color(0x30, 0), [236, 238, 236]);
+    // 上位2bitは無視される
+    assert_eq!(palette.color(0xF0, 0), palette.color(0x30, 0));
+}
+
+#[test]
+fn loads_64_and_512_color_pal_files() {
+    let palette = Palette::from_pal(&ramp_pal(COLORS)).unwrap();
+    assert_eq!(palette.color(0x21, 0), [0x21, 0x10, 0x08]);
+
+    let palette = Palette::from_pal(&ramp_pal(EMPHASIS_COLORS)).unwrap();
+    // 512色のパレットでは、強調ビットの組み合わせごとに色が指定されている。
+    let mask = (MaskFlags::EMPHASIZE_RED | MaskFlags::EMPHASIZE_BLUE).bits();
+    assert_eq!(palette.color(0x01, mask), [0x41, 0xA0, 0x50]);
+
+    assert!(Palette::from_pal(&[0; 100]).is_err());
+}
+
+#[test]
+fn from_colors_rejects_other_sizes() {
+    assert!(Palette::from_colors(&[[0x21, 0x10, 0x08]; COLORS]).is_ok());
+    assert!(Palette::from_colors(&[[0, 0, 0]; 100]).is_err());
+    assert!(Palette::from_colors(&[]).is_err());
+}
+
+#[test]
+fn grayscale_uses_the_first_column() {
+    let palette = Palette::ntsc_2c02();
+    let mask = MaskFlags::GRAYSCALE.bits();
+    assert_eq!(palette.color(0x16, mask), palette.color(0x10, 0));
+    assert_eq!(palette.color(0x2A, mask), palette.color(0x20, 0));
+}
+
+#[test]
+fn emphasis_darkens_the_other_channels() {
+    let palette = Palette::ntsc_2c02();
+    let [r, g, b] = palette.color(0x30, 0);
+    let [er, eg, eb] = palette.color(0x30, MaskFlags::EMPHASIZE_RED.bits());
+    assert_eq!(er, r);
+    assert!(eg < g && eb < b);
+}
+
+#[test]
+fn converts_frame_to_rgba() {
+    let palette = Palette::ntsc_2c02();
+    let mut out = [0; 8];
+    palette.to_rgba(&[0x0F, 0x30], 0, &mut out);
+    assert_eq!(out, [0, 0, 0, 0xFF, 236, 238, 236, 0xFF]);
+}
+
+#[test]
+fn generated_palette_has_expected_hues() {
+    let palette = Palette::generate(&NtscParams::default());
+    let [r, g, b] = palette.color(0x16, 0);
+    assert!(r > g && r > b, "$16 should be red: {:?}", [r, g, b]);
+    let [r, g, b] = palette.color(0x1A, 0);
+    assert!(g > r && g > b, "$1A should be green: {:?}", [r, g, b]);
+    let [r, g, b] = palette.color(0x12, 0);
+    assert!(b > r && b > g, "$12 should be blue: {:?}", [r, g, b]);
+    assert_eq!(palette.color(0x0F, 0), [0, 0, 0]);
+    assert_eq!(palette.color(0x1D, 0), [0, 0, 0]);
+}
+
+#[test]
+fn generated_palette_grays_are_neutral_and_ordered() {
+    let palette = Palette::generate(&NtscParams::default());
+    let grays = [0x0D, 0x00, 0x10, 0x3D].map(|i| palette.color(i, 0));
+    for [r, g, b] in grays {
+        assert!(r == g && g == b);
+    }
+    assert!(grays.windows(2).all(|w| w[0][0] <= w[1][0]));
+}
+
+#[test]
+fn generated_palette_tunables() {
+    let base = Palette::generate(&NtscParams::default());
+    let dark = Palette::generate(&NtscParams { brightness: -0.2, ..Default::default() });
+    assert!(dark.color(0x10, 0)[0] < base.color(0x10, 0)[0]);
+
+    let gray = Palette::generate(&NtscParams { saturation: 0.0, ..Default::default() });
+    let [r, g, b] = gray.color(0x16, 0);
+    assert!(r == g && g == b);
+
+    // 色相を180度回すと、赤は青緑寄りになる。
+    let rotated = Palette::generate(&NtscParams { hue: 180.0, ..Default::default() });
+    let [r, g, b] = rotated.color(0x16, 0);
+    assert!(r < g && r < b);
+
+    let bright = Palette::generate(&NtscParams { gamma: 2.0, ..Default::default() });
+    assert!(bright.color(0x10, 0)[0] > base.color(0x10, 0)[0]);
+}
+
+#[test]
+fn generated_palette_emphasis() {
+    let palette = Palette::generate(&NtscParams::default());
+    let [r, g, b] = palette.color(0x30, MaskFlags::EMPHASIZE_RED.bits());
+    assert!(r > g && r > b);
+    let [r, g, b] = palette.color(0x30, MaskFlags::EMPHASIZE_BLUE.bits());
+    assert!(b > r && b > g);
+}
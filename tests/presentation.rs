@@ -0,0 +1,52 @@
+//! 画面表示の配置計算のテスト。
+
+use fami_rust::presentation::{Overscan, Presentation};
+
+fn plain() -> Presentation {
+    Presentation { overscan: Overscan::none(), integer_scaling: true, aspect_correction: false }
+}
+
+#[test]
+fn integer_scaling_centers_the_image() {
+    let layout = plain().layout(256, 800.0, 600.0);
+    assert_eq!(layout.src, [0.0, 0.0, 256.0, 240.0]);
+    // 600 / 240 = 2.5 なので2倍
+    assert_eq!(layout.dst, [144.0, 60.0, 512.0, 480.0]);
+
+    let free = Presentation { integer_scaling: false, ..plain() };
+    assert_eq!(free.layout(256, 800.0, 600.0).dst, [80.0, 0.0, 640.0, 600.0]);
+}
+
+#[test]
+fn small_windows_are_scaled_down() {
+    let layout = plain().layout(256, 128.0, 120.0);
+    assert_eq!(layout.dst, [0.0, 0.0, 128.0, 120.0]);
+}
+
+#[test]
+fn aspect_correction_widens_pixels() {
+    let p = Presentation { aspect_correction: true, ..plain() };
+    let (w, h) = p.base_size();
+    assert!((w - 256.0 * 8.0 / 7.0).abs() < 1e-9);
+    assert_eq!(h, 240.0);
+    let layout = p.layout(256, 1000.0, 480.0);
+    assert_eq!(layout.dst[3], 480.0);
+    assert!((layout.dst[2] - 512.0 * 8.0 / 7.0).abs() < 1e-9);
+}
+
+#[test]
+fn overscan_crops_the_source() {
+    let p = Presentation { overscan: Overscan { top: 8, bottom: 8, left: 8, right: 0 }, ..plain() };
+    assert_eq!(p.visible_size(), (248, 224));
+    assert_eq!(p.layout(256, 496.0, 448.0).src, [8.0, 8.0, 248.0, 224.0]);
+    // 横に引き伸ばされたテクスチャ(NTSCフィルター)では、横方向の切り出し位置も引き伸ばす。
+    assert_eq!(p.layout(512, 496.0, 448.0).src, [16.0, 8.0, 496.0, 224.0]);
+    assert_eq!(Overscan::default(), Overscan { top: 8, bottom: 8, left: 0, right: 0 });
+}
+
+#[test]
+fn huge_overscan_does_not_overflow() {
+    let p = Presentation { overscan: Overscan { top: u32::MAX, bottom: 1, left: u32::MAX, right: u32::MAX }, ..plain() };
+    assert_eq!(p.visible_size(), (1, 1));
+    assert_eq!(p.layout(256, 496.0, 448.0).src, [255.0, 239.0, 1.0, 1.0]);
+}
@@ -0,0 +1,96 @@
+//! サブルーチン単位のサイクルプロファイラーのテスト。
+
+mod common;
+
+use fami_rust::nes::debugger::profiler::{Profiler, RoutineKind};
+
+#[test]
+fn cycles_are_attributed_to_subroutines() {
+    let mut console = common::console(&common::PROGRAM, &[], |console, _| console.set_profiler(Some(Profiler::new())));
+    console.step_frame();
+    console.step_frame();
+    // サブルーチンから戻った直後で止める。
+    while console.cpu().regs().pc != 0xC005 {
+        console.step_instruction();
+    }
+
+    let profiler = console.profiler().unwrap();
+    assert_eq!(profiler.frames(), 2);
+    let sub = *profiler.stats_at(0xC010).unwrap();
+    assert!(sub.calls > 0);
+    // LDA zp (3) + RTS (6)
+    assert_eq!(sub.exclusive, sub.calls * 9);
+    assert_eq!(sub.inclusive, sub.exclusive);
+    assert!((1..sub.inclusive).contains(&sub.max_frame_inclusive));
+    let reset = *profiler.stats_at(0xC000).unwrap();
+    assert_eq!(reset.calls, 1);
+    assert_eq!(reset.inclusive, reset.exclusive + sub.inclusive);
+
+    let collapsed = profiler.collapsed(&console);
+    assert!(collapsed.contains(&format!("RESET:$C000;$C010 {}\n", sub.inclusive)));
+    let report = profiler.report(&console);
+    assert!(report.starts_with("frames: 2, cycles/frame: 29780.7, vblank: 2273.3 cycles\n"));
+}
+
+#[test]
+fn interrupts_and_rts_jumps_keep_the_stack_balanced() {
+    // 2回VBlankを待ってから、NMIを有効にして無限ループする。
+    let program = [
+        0x2C, 0x02, 0x20,       // C000  BIT $2002
+        0x2C, 0x02, 0x20,       // C003  BIT $2002
+        0x10, 0xFB,             // C006  BPL $C003
+        0x2C, 0x02, 0x20,       // C008  BIT $2002
+        0x10, 0xFB,             // C00B  BPL $C008
+        0xA9, 0x80,             // C00D  LDA #$80
+        0x8D, 0x00, 0x20,       // C00F  STA $2000
+        0x4C, 0x12, 0xC0,       // C012  JMP $C012
+    ];
+    // NMIハンドラは、スタックに積んだアドレスへの RTS で $C030 に飛んでから RTI で戻る。
+    let mut nmi = vec![0; 0x13];
+    nmi[0x00..0x07].copy_from_slice(&[
+        0xA9, 0xC0,             // C020  LDA #$C0
+        0x48,                   // C022  PHA
+        0xA9, 0x2F,             // C023  LDA #$2F
+        0x48,                   // C025  PHA
+        0x60,                   // C026  RTS
+    ]);
+    nmi[0x10..0x13].copy_from_slice(&[
+        0xE6, 0x20,             // C030  INC $20
+        0x40,                   // C032  RTI
+    ]);
+    let mut console = common::console(&program, &nmi, |console, _| console.set_profiler(Some(Profiler::new())));
+    for _ in 0..6 {
+        console.step_frame();
+    }
+
+    let profiler = console.profiler().unwrap();
+    let (routine, stats) = profiler.routines().find(|(routine, _)| routine.kind == RoutineKind::Nmi).unwrap();
+    assert_eq!(routine.entry, 0xC020);
+    assert_eq!(stats.calls, console.peek(0x0020) as u64);
+    assert_eq!(stats.exclusive, stats.calls * 27);
+    assert_eq!(stats.max_frame_inclusive, 27);
+    // RTS で飛んだ先はサブルーチンとして扱わない。
+    assert!(profiler.stats_at(0xC030).is_none());
+    assert!(profiler.report(&console).contains("NMI:$C020: max 27 cycles/frame = 1.2% of vblank\n"));
+}
+
+#[test]
+fn discarded_return_addresses_do_not_grow_the_stack() {
+    // サブルーチンは戻りアドレスを PLA で捨て、JMP で呼び出し元に戻る。
+    let program = [
+        0x20, 0x10, 0xC0,       // C000  JSR $C010
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0x68,                   // C010  PLA
+        0x68,                   // C011  PLA
+        0x4C, 0x00, 0xC0,       // C012  JMP $C000
+    ];
+    let mut console = common::console(&program, &[], |console, _| console.set_profiler(Some(Profiler::new())));
+    console.step_frame();
+
+    let profiler = console.profiler().unwrap();
+    let sub = *profiler.stats_at(0xC010).unwrap();
+    assert!(sub.calls > 1);
+    // 前の呼び出しの中に次の呼び出しが入れ子にならない。
+    assert_eq!(sub.inclusive, sub.exclusive);
+    assert!(!profiler.collapsed(&console).contains("$C010;$C010"));
+}
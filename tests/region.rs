@@ -0,0 +1,74 @@
+//! 地域(NTSC/PAL/Dendy)ごとのタイミングのテスト。
+
+use fami_rust::nes::ppu::MaskFlags;
+use fami_rust::nes::region::Region;
+use fami_rust::nes::rom;
+use fami_rust::Console;
+
+/// NROMの空のROM。header_patch で ヘッダの (位置, 値) を上書きする。
+fn rom_bin(header_patch: &[(usize, u8)]) -> Vec<u8> {
+    let mut bin = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    for &(i, v) in header_patch {
+        bin[i] = v;
+    }
+    bin.resize(16 + rom::PRG_ROM_UNIT_SIZE + rom::CHR_ROM_UNIT_SIZE, 0);
+    bin
+}
+
+#[test]
+fn region_from_header() {
+    let ntsc = rom::parse(&rom_bin(&[])).unwrap();
+    assert_eq!(Region::from_rom(&ntsc), Region::Ntsc);
+    // iNES: フラグ9のbit0がPAL
+    let pal = rom::parse(&rom_bin(&[(9, 1)])).unwrap();
+    assert_eq!(Region::from_rom(&pal), Region::Pal);
+    // NES2.0: バイト12がCPU/PPUタイミング
+    let dendy = rom::parse(&rom_bin(&[(7, 0x08), (12, 3)])).unwrap();
+    assert_eq!(Region::from_rom(&dendy), Region::Dendy);
+
+    assert_eq!(Region::from_name("PAL"), Some(Region::Pal));
+    assert_eq!(Region::from_name("secam"), None);
+}
+
+#[test]
+fn frame_rates() {
+    assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+    assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+    assert!((Region::Dendy.frame_rate() - 50.0).abs() < 0.1);
+}
+
+/// 1フレーム実行した際のCPUのクロック数
+fn cpu_clocks_per_frame(region: Region) -> u64 {
+    let rom = rom::parse(&rom_bin(&[])).unwrap();
+    let mut console = Console::with_region(&rom, region);
+    console.power_on();
+    console.step_frame();
+    let start = console.cpu().clock_counter();
+    console.step_frame();
+    console.cpu().clock_counter() - start
+}
+
+#[test]
+fn cpu_to_ppu_clock_ratio() {
+    // NTSC: 341 * 262 / 3
+    assert_eq!(cpu_clocks_per_frame(Region::Ntsc), 29781);
+    // PAL: 341 * 312 / 3.2
+    let pal = cpu_clocks_per_frame(Region::Pal);
+    assert!(pal == 33247 || pal == 33248, "{}", pal);
+    // Dendy: 341 * 312 / 3
+    assert_eq!(cpu_clocks_per_frame(Region::Dendy), 35464);
+}
+
+#[test]
+fn pal_and_dendy_swap_red_and_green_emphasis() {
+    let red = MaskFlags::EMPHASIZE_RED.bits();
+    let green = MaskFlags::EMPHASIZE_GREEN.bits();
+    let blue = MaskFlags::EMPHASIZE_BLUE.bits();
+    let show = MaskFlags::SHOW_BG.bits();
+    assert_eq!(Region::Ntsc.ntsc_mask(red | show), red | show);
+    for region in [Region::Pal, Region::Dendy] {
+        assert_eq!(region.ntsc_mask(red | show), green | show);
+        assert_eq!(region.ntsc_mask(green | blue), red | blue);
+        assert_eq!(region.ntsc_mask(red | green), red | green);
+    }
+}
@@ -0,0 +1,98 @@
+//! シンボルファイルの読み込みと、ラベルの表示のテスト。
+
+mod common;
+
+use fami_rust::nes::debugger::Debugger;
+use fami_rust::nes::debugger::command::Command;
+use fami_rust::nes::debugger::symbols::{Symbol, SymbolTable};
+use fami_rust::Console;
+
+const CA65_DBG: &str = "\
+version\tmajor=2,minor=0
+info\tcsym=0,file=1,lib=0,line=0,mod=1,scope=1,seg=3,span=0,sym=4,type=0
+file\tid=0,name=\"main.s\",size=100,mtime=0x5F000000,mod=0
+seg\tid=0,name=\"HEADER\",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=0
+seg\tid=1,name=\"CODE\",start=0x00C000,size=0x0020,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+seg\tid=2,name=\"ZEROPAGE\",start=0x000010,size=0x0001,addrsize=zeropage,type=rw
+sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=1,type=lab
+sym\tid=1,name=\"sub\",addrsize=absolute,scope=0,def=2,val=0xC010,seg=1,type=lab
+sym\tid=2,name=\"counter\",addrsize=zeropage,scope=0,def=3,val=0x10,seg=2,type=lab
+sym\tid=3,name=\"BUTTON_A\",addrsize=zeropage,scope=0,def=4,val=0x80,type=equ
+";
+
+/// シンボルを読み込んだ、common::PROGRAM のリセット直後の最初の命令で止めたConsoleを作る。
+fn console() -> Console {
+    common::console_at_reset(|console, _| {
+        console.set_symbols(SymbolTable::parse_ca65_dbg(CA65_DBG).unwrap());
+    })
+}
+
+#[test]
+fn ca65_labels_have_prg_rom_offsets() {
+    let symbols = SymbolTable::parse_ca65_dbg(CA65_DBG).unwrap();
+    // 定数(equ)は取り込まない。
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.find("sub"), Some(&Symbol { name: "sub".to_string(), addr: Some(0xC010), prg_offset: Some(0x10) }));
+    assert_eq!(symbols.find("counter").unwrap().prg_offset, None);
+    assert_eq!(symbols.find("BUTTON_A"), None);
+}
+
+#[test]
+fn fceux_and_mesen_labels() {
+    let nl = SymbolTable::parse_fceux_nl("$C000#Reset#entry point\n$C010##comment only\n", Some(1)).unwrap();
+    assert_eq!(nl.symbols(), [Symbol { name: "Reset".to_string(), addr: Some(0xC000), prg_offset: Some(0x4000) }]);
+    let ram = SymbolTable::parse_fceux_nl("$0300/10#buffer#\n", None).unwrap();
+    assert_eq!(ram.find("buffer").unwrap().addr, Some(0x0300));
+
+    let mlb = SymbolTable::parse_mesen_mlb("P:4010:sub:called every frame\nR:0010:counter\nS:0000:save\nG:2000:PPUCTRL\nP:4020::a comment\n").unwrap();
+    assert_eq!(mlb.find("sub"), Some(&Symbol { name: "sub".to_string(), addr: None, prg_offset: Some(0x4010) }));
+    assert_eq!(mlb.find("save").unwrap().addr, Some(0x6000));
+    assert_eq!(mlb.find("PPUCTRL").unwrap().addr, Some(0x2000));
+    assert_eq!(mlb.len(), 4);
+
+    assert!(SymbolTable::parse_fceux_nl("C000#Reset#\n", None).is_err());
+}
+
+#[test]
+fn labels_follow_the_mapped_bank() {
+    let mut symbols = SymbolTable::parse_fceux_nl("$8000#bank0_start#\n", Some(0)).unwrap();
+    symbols.extend(SymbolTable::parse_fceux_nl("$8000#bank1_start#\n", Some(1)).unwrap());
+    symbols.extend(SymbolTable::parse_fceux_nl("$0010#counter#\n", None).unwrap());
+    assert_eq!(symbols.label_at(0x8000, Some(0x0000)), Some("bank0_start"));
+    assert_eq!(symbols.label_at(0x8000, Some(0x4000)), Some("bank1_start"));
+    // 別のバンクのラベルは使わない。
+    assert_eq!(symbols.label_at(0x8000, Some(0x8000)), None);
+    assert_eq!(symbols.label_at(0x0010, None), Some("counter"));
+
+    // バンク1が $C000 に見えている場合は、そのアドレスに解決する。
+    let mapping = |addr: u16| (addr >= 0xC000).then(|| addr as usize - 0xC000 + 0x4000);
+    assert_eq!(symbols.resolve("bank1_start", mapping), Some(0xC000));
+    assert_eq!(symbols.resolve_expr("counter+2", mapping), Some(0x0012));
+    assert_eq!(symbols.resolve_expr("$C000", mapping), Some(0xC000));
+    assert_eq!(symbols.resolve_expr("nowhere", mapping), None);
+}
+
+#[test]
+fn trace_and_disassembly_show_labels() {
+    let mut console = console();
+    console.step_instruction();
+    assert_eq!(console.trace_line().disasm, "JSR sub");
+    console.step_instruction();
+    assert_eq!(console.trace_line().disasm, "LDA counter = 00");
+    let texts: Vec<String> = console.disassemble(0xC006, 0xC008).into_iter().map(|line| line.text).collect();
+    assert_eq!(texts, ["STX counter", "JMP $C002"]);
+    // 16KBのPRG-ROMは $8000 にも見えている。
+    assert_eq!(console.label_at(0x8010), Some("sub"));
+}
+
+#[test]
+fn debugger_commands_accept_labels() {
+    let mut console = console();
+    let mut debugger = Debugger::new(&console);
+    let command = Command::parse_with("b sub+2", &|text| console.resolve_addr(text)).unwrap();
+    assert_eq!(command, Command::Break(0xC012, None));
+    let command = Command::parse_with("b sub", &|text| console.resolve_addr(text)).unwrap();
+    assert_eq!(debugger.execute(&mut console, &command), "breakpoint #1 at $C010 <sub>");
+    assert_eq!(debugger.execute(&mut console, &Command::Disasm(Some(0xC010), 1)), "sub:\nC010  A5 10     LDA counter");
+    assert!(Command::parse_with("b nowhere", &|text| console.resolve_addr(text)).is_err());
+}
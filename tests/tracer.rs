@@ -0,0 +1,112 @@
+//! 命令単位のトレースログのテスト。
+
+mod common;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use fami_rust::nes::cpu::trace::TraceFields;
+use fami_rust::nes::debugger::{Debugger, StopReason};
+use fami_rust::nes::debugger::tracer::{Tracer, Trigger};
+use fami_rust::Console;
+
+/// common::PROGRAM の、リセット直後の最初の命令で止めたConsoleを作る。
+fn console() -> Console {
+    common::console_at_reset(|_, _| {})
+}
+
+/// 書き込まれた内容を後から読める出力先。
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn pcs(lines: &[String]) -> Vec<&str> {
+    lines.iter().map(|line| &line[..4]).collect()
+}
+
+#[test]
+fn records_each_instruction_with_selected_fields() {
+    let mut console = console();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(Box::new(buffer.clone()));
+    tracer.set_fields(TraceFields::REGS | TraceFields::BANK);
+    console.set_tracer(Some(tracer));
+    for _ in 0..3 {
+        console.step_instruction();
+    }
+    assert_eq!(buffer.lines(), [
+        "C000  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD BANK:00",
+        "C002  20 10 C0  JSR $C010                       A:00 X:00 Y:00 P:26 SP:FD BANK:00",
+        "C010  A5 10     LDA $10 = 00                    A:00 X:00 Y:00 P:26 SP:FB BANK:00",
+    ]);
+
+    // 既定の項目は nestest.log と同じ書式。
+    let line = console.trace_line();
+    assert_eq!(line.format_with(TraceFields::NESTEST, None), line.to_string());
+    assert_eq!(line.format_with(TraceFields::empty(), None), "C012  60        RTS");
+}
+
+#[test]
+fn pc_range_and_triggers_filter_the_log() {
+    let mut console = console();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(Box::new(buffer.clone()));
+    tracer.set_pc_range(Some(0xC005..=0xC00F));
+    tracer.set_start(Some(Trigger::Pc(0xC010)));
+    tracer.set_stop(Some(Trigger::Pc(0xC008)));
+    tracer.set_banks(Some(vec![0]));
+    console.set_tracer(Some(tracer));
+    for _ in 0..20 {
+        console.step_instruction();
+    }
+    // サブルーチンに入ってから記録を始め、JMP に来たところで終える。
+    assert_eq!(pcs(&buffer.lines()), ["C005", "C006"]);
+
+    let resolve = |text: &str| u16::from_str_radix(text, 16).ok();
+    assert_eq!(Trigger::parse("frame:30", &resolve), Some(Trigger::Frame(30)));
+    assert_eq!(Trigger::parse("C008", &resolve), Some(Trigger::Pc(0xC008)));
+    assert_eq!(Trigger::parse("frame:x", &resolve), None);
+}
+
+#[test]
+fn ring_buffer_is_dumped_when_a_breakpoint_fires() {
+    let mut console = console();
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(Box::new(buffer.clone()));
+    tracer.set_fields(TraceFields::empty());
+    tracer.set_ring(Some(3));
+    console.set_tracer(Some(tracer));
+
+    let mut debugger = Debugger::new(&console);
+    let id = debugger.add_breakpoint(0xC008, None);
+    // 最初の命令はブレークポイントを見ずに実行する。
+    console.step_instruction();
+    assert!(buffer.lines().is_empty());
+    assert_eq!(debugger.run(&mut console, 1), StopReason::Breakpoint(id));
+    assert_eq!(pcs(&buffer.lines()), ["C012", "C005", "C006"]);
+    assert_eq!(console.tracer_mut().unwrap().ring_lines().count(), 0);
+    assert!(console.take_tracer().unwrap().finish().is_ok());
+}
+
+#[test]
+fn field_names() {
+    assert_eq!(TraceFields::from_names("regs,bank"), Some(TraceFields::REGS | TraceFields::BANK));
+    assert_eq!(TraceFields::from_names("none"), Some(TraceFields::empty()));
+    assert_eq!(TraceFields::from_names("regs,flags"), None);
+}
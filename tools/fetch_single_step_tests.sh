@@ -0,0 +1,14 @@
+#!/bin/sh
+# SingleStepTests (https://github.com/SingleStepTests/65x02) の nes6502 のテストベクタを取得する。
+# 取得したディレクトリを SINGLE_STEP_TESTS_DIR に指定して cargo test を実行すると、
+# tests/data/cpu の代わりに上流のテストベクタでCPUを検証できる。
+#
+# Usage: tools/fetch_single_step_tests.sh [DIR]   (既定: target/single_step_tests)
+set -eu
+
+dir=${1:-target/single_step_tests}
+if [ ! -d "$dir/.git" ]; then
+    git clone --depth 1 --filter=blob:none --sparse https://github.com/SingleStepTests/65x02 "$dir"
+fi
+git -C "$dir" sparse-checkout set nes6502/v1
+echo "SINGLE_STEP_TESTS_DIR=$dir/nes6502/v1 cargo test --release single_step"
@@ -0,0 +1,430 @@
+#!/usr/bin/env python3
+"""6502 (2A03) の命令単位のテストベクタを生成する。
+
+SingleStepTests/ProcessorTests (https://github.com/SingleStepTests/65x02) の
+nes6502 と同じ形式で、オペコードごとに tests/data/cpu/<opcode>.json を出力する。
+2A03 には10進モードが無いため、ADC/SBC は D フラグに関わらず2進で計算する。
+
+テスト自体はCPUの実装とは独立した、このスクリプト内の参照モデルを元に作る。
+上流の完全なテストデータで確かめる場合は、テスト実行時に
+環境変数 SINGLE_STEP_TESTS_DIR で nes6502/v1 ディレクトリを指定すること。
+
+Usage: tools/gen_cpu_tests.py [--count N] [--seed S] [--out DIR]
+"""
+
+import argparse
+import json
+import os
+import random
+
+C, Z, I, D, B, U, V, N = 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80
+
+
+class Bus:
+    """フラットな64KBのメモリ。未初期化の番地は乱数で埋め、初期状態として記録する。"""
+
+    def __init__(self, rng):
+        self.rng = rng
+        self.mem = {}
+        self.initial = {}
+        self.cycles = []
+
+    def fill(self, addr, val):
+        addr &= 0xFFFF
+        self.mem[addr] = val
+        self.initial[addr] = val
+
+    def value(self, addr):
+        addr &= 0xFFFF
+        if addr not in self.mem:
+            self.fill(addr, self.rng.randrange(256))
+        return self.mem[addr]
+
+    def read(self, addr):
+        val = self.value(addr)
+        self.cycles.append([addr & 0xFFFF, val, "read"])
+        return val
+
+    def write(self, addr, val):
+        addr &= 0xFFFF
+        # 書き込み先も初期状態に含める
+        self.value(addr)
+        self.mem[addr] = val
+        self.cycles.append([addr, val, "write"])
+
+
+class Cpu:
+    def __init__(self, bus, pc, s, a, x, y, p):
+        self.bus = bus
+        self.pc, self.s, self.a, self.x, self.y, self.p = pc, s, a, x, y, p
+
+    # --- helpers ---
+    def fetch(self):
+        val = self.bus.read(self.pc)
+        self.pc = (self.pc + 1) & 0xFFFF
+        return val
+
+    def set_nz(self, val):
+        self.p &= ~(N | Z) & 0xFF
+        if val == 0:
+            self.p |= Z
+        self.p |= val & N
+        return val
+
+    def flag(self, f, on):
+        if on:
+            self.p |= f
+        else:
+            self.p &= ~f & 0xFF
+
+    def push(self, val):
+        self.bus.write(0x100 | self.s, val)
+        self.s = (self.s - 1) & 0xFF
+
+    def stack_addr(self):
+        return 0x100 | self.s
+
+    # --- addressing (返り値: 実効アドレス) ---
+    def addr_zp(self):
+        return self.fetch()
+
+    def addr_zpi(self, index):
+        base = self.fetch()
+        self.bus.read(base)
+        return (base + index) & 0xFF
+
+    def addr_abs(self):
+        lo = self.fetch()
+        hi = self.fetch()
+        return (hi << 8) | lo
+
+    def addr_absi(self, index, kind):
+        lo = self.fetch()
+        hi = self.fetch()
+        addr = (((hi << 8) | lo) + index) & 0xFFFF
+        crossed = (lo + index) > 0xFF
+        if crossed or kind != "r":
+            self.bus.read((hi << 8) | ((lo + index) & 0xFF))
+        return addr
+
+    def addr_izx(self):
+        ptr = self.fetch()
+        self.bus.read(ptr)
+        ptr = (ptr + self.x) & 0xFF
+        lo = self.bus.read(ptr)
+        hi = self.bus.read((ptr + 1) & 0xFF)
+        return (hi << 8) | lo
+
+    def addr_izy(self, kind):
+        ptr = self.fetch()
+        lo = self.bus.read(ptr)
+        hi = self.bus.read((ptr + 1) & 0xFF)
+        addr = (((hi << 8) | lo) + self.y) & 0xFFFF
+        crossed = (lo + self.y) > 0xFF
+        if crossed or kind != "r":
+            self.bus.read((hi << 8) | ((lo + self.y) & 0xFF))
+        return addr
+
+    def resolve(self, mode, kind):
+        if mode == "zp":
+            return self.addr_zp()
+        if mode == "zpx":
+            return self.addr_zpi(self.x)
+        if mode == "zpy":
+            return self.addr_zpi(self.y)
+        if mode == "abs":
+            return self.addr_abs()
+        if mode == "absx":
+            return self.addr_absi(self.x, kind)
+        if mode == "absy":
+            return self.addr_absi(self.y, kind)
+        if mode == "izx":
+            return self.addr_izx()
+        if mode == "izy":
+            return self.addr_izy(kind)
+        raise ValueError(mode)
+
+    # --- ALU ---
+    def adc(self, val):
+        total = self.a + val + (self.p & C)
+        result = total & 0xFF
+        self.flag(V, ((self.a ^ result) & (val ^ result) & 0x80) != 0)
+        self.flag(C, total > 0xFF)
+        self.a = self.set_nz(result)
+
+    def compare(self, reg, val):
+        self.flag(C, reg >= val)
+        self.set_nz((reg - val) & 0xFF)
+
+    def asl(self, val):
+        self.flag(C, val & 0x80)
+        return self.set_nz((val << 1) & 0xFF)
+
+    def lsr(self, val):
+        self.flag(C, val & 0x01)
+        return self.set_nz(val >> 1)
+
+    def rol(self, val):
+        carry = self.p & C
+        self.flag(C, val & 0x80)
+        return self.set_nz(((val << 1) & 0xFF) | carry)
+
+    def ror(self, val):
+        carry = (self.p & C) << 7
+        self.flag(C, val & 0x01)
+        return self.set_nz((val >> 1) | carry)
+
+
+READ_OPS = {
+    "ORA": lambda c, v: setattr(c, "a", c.set_nz(c.a | v)),
+    "AND": lambda c, v: setattr(c, "a", c.set_nz(c.a & v)),
+    "EOR": lambda c, v: setattr(c, "a", c.set_nz(c.a ^ v)),
+    "ADC": lambda c, v: c.adc(v),
+    "SBC": lambda c, v: c.adc(v ^ 0xFF),
+    "CMP": lambda c, v: c.compare(c.a, v),
+    "CPX": lambda c, v: c.compare(c.x, v),
+    "CPY": lambda c, v: c.compare(c.y, v),
+    "LDA": lambda c, v: setattr(c, "a", c.set_nz(v)),
+    "LDX": lambda c, v: setattr(c, "x", c.set_nz(v)),
+    "LDY": lambda c, v: setattr(c, "y", c.set_nz(v)),
+    "BIT": lambda c, v: (c.flag(Z, (c.a & v) == 0), c.flag(N, v & N), c.flag(V, v & V)),
+    "NOP": lambda c, v: None,
+}
+
+WRITE_OPS = {
+    "STA": lambda c: c.a,
+    "STX": lambda c: c.x,
+    "STY": lambda c: c.y,
+}
+
+RMW_OPS = {
+    "ASL": Cpu.asl,
+    "LSR": Cpu.lsr,
+    "ROL": Cpu.rol,
+    "ROR": Cpu.ror,
+    "INC": lambda c, v: c.set_nz((v + 1) & 0xFF),
+    "DEC": lambda c, v: c.set_nz((v - 1) & 0xFF),
+}
+
+IMPLIED_OPS = {
+    "CLC": lambda c: c.flag(C, False),
+    "SEC": lambda c: c.flag(C, True),
+    "CLI": lambda c: c.flag(I, False),
+    "SEI": lambda c: c.flag(I, True),
+    "CLV": lambda c: c.flag(V, False),
+    "CLD": lambda c: c.flag(D, False),
+    "SED": lambda c: c.flag(D, True),
+    "TAX": lambda c: setattr(c, "x", c.set_nz(c.a)),
+    "TAY": lambda c: setattr(c, "y", c.set_nz(c.a)),
+    "TXA": lambda c: setattr(c, "a", c.set_nz(c.x)),
+    "TYA": lambda c: setattr(c, "a", c.set_nz(c.y)),
+    "TSX": lambda c: setattr(c, "x", c.set_nz(c.s)),
+    "TXS": lambda c: setattr(c, "s", c.x),
+    "INX": lambda c: setattr(c, "x", c.set_nz((c.x + 1) & 0xFF)),
+    "INY": lambda c: setattr(c, "y", c.set_nz((c.y + 1) & 0xFF)),
+    "DEX": lambda c: setattr(c, "x", c.set_nz((c.x - 1) & 0xFF)),
+    "DEY": lambda c: setattr(c, "y", c.set_nz((c.y - 1) & 0xFF)),
+    "NOP": lambda c: None,
+}
+
+BRANCHES = {
+    "BPL": lambda p: not (p & N),
+    "BMI": lambda p: bool(p & N),
+    "BVC": lambda p: not (p & V),
+    "BVS": lambda p: bool(p & V),
+    "BCC": lambda p: not (p & C),
+    "BCS": lambda p: bool(p & C),
+    "BNE": lambda p: not (p & Z),
+    "BEQ": lambda p: bool(p & Z),
+}
+
+# オペコード -> (ニーモニック, アドレッシングモード)
+OPCODES = {
+    0x00: ("BRK", "impl"), 0x01: ("ORA", "izx"), 0x05: ("ORA", "zp"), 0x06: ("ASL", "zp"),
+    0x08: ("PHP", "impl"), 0x09: ("ORA", "imm"), 0x0A: ("ASL", "acc"), 0x0D: ("ORA", "abs"),
+    0x0E: ("ASL", "abs"), 0x10: ("BPL", "rel"), 0x11: ("ORA", "izy"), 0x15: ("ORA", "zpx"),
+    0x16: ("ASL", "zpx"), 0x18: ("CLC", "impl"), 0x19: ("ORA", "absy"), 0x1D: ("ORA", "absx"),
+    0x1E: ("ASL", "absx"), 0x20: ("JSR", "abs"), 0x21: ("AND", "izx"), 0x24: ("BIT", "zp"),
+    0x25: ("AND", "zp"), 0x26: ("ROL", "zp"), 0x28: ("PLP", "impl"), 0x29: ("AND", "imm"),
+    0x2A: ("ROL", "acc"), 0x2C: ("BIT", "abs"), 0x2D: ("AND", "abs"), 0x2E: ("ROL", "abs"),
+    0x30: ("BMI", "rel"), 0x31: ("AND", "izy"), 0x35: ("AND", "zpx"), 0x36: ("ROL", "zpx"),
+    0x38: ("SEC", "impl"), 0x39: ("AND", "absy"), 0x3D: ("AND", "absx"), 0x3E: ("ROL", "absx"),
+    0x40: ("RTI", "impl"), 0x41: ("EOR", "izx"), 0x45: ("EOR", "zp"), 0x46: ("LSR", "zp"),
+    0x48: ("PHA", "impl"), 0x49: ("EOR", "imm"), 0x4A: ("LSR", "acc"), 0x4C: ("JMP", "abs"),
+    0x4D: ("EOR", "abs"), 0x4E: ("LSR", "abs"), 0x50: ("BVC", "rel"), 0x51: ("EOR", "izy"),
+    0x55: ("EOR", "zpx"), 0x56: ("LSR", "zpx"), 0x58: ("CLI", "impl"), 0x59: ("EOR", "absy"),
+    0x5D: ("EOR", "absx"), 0x5E: ("LSR", "absx"), 0x60: ("RTS", "impl"), 0x61: ("ADC", "izx"),
+    0x65: ("ADC", "zp"), 0x66: ("ROR", "zp"), 0x68: ("PLA", "impl"), 0x69: ("ADC", "imm"),
+    0x6A: ("ROR", "acc"), 0x6C: ("JMP", "ind"), 0x6D: ("ADC", "abs"), 0x6E: ("ROR", "abs"),
+    0x70: ("BVS", "rel"), 0x71: ("ADC", "izy"), 0x75: ("ADC", "zpx"), 0x76: ("ROR", "zpx"),
+    0x78: ("SEI", "impl"), 0x79: ("ADC", "absy"), 0x7D: ("ADC", "absx"), 0x7E: ("ROR", "absx"),
+    0x80: ("NOP", "imm"), 0x81: ("STA", "izx"), 0x82: ("NOP", "imm"), 0x84: ("STY", "zp"),
+    0x85: ("STA", "zp"), 0x86: ("STX", "zp"), 0x88: ("DEY", "impl"), 0x8A: ("TXA", "impl"),
+    0x8C: ("STY", "abs"), 0x8D: ("STA", "abs"), 0x8E: ("STX", "abs"), 0x90: ("BCC", "rel"),
+    0x91: ("STA", "izy"), 0x94: ("STY", "zpx"), 0x95: ("STA", "zpx"), 0x96: ("STX", "zpy"),
+    0x98: ("TYA", "impl"), 0x99: ("STA", "absy"), 0x9A: ("TXS", "impl"), 0x9D: ("STA", "absx"),
+    0xA0: ("LDY", "imm"), 0xA1: ("LDA", "izx"), 0xA2: ("LDX", "imm"), 0xA4: ("LDY", "zp"),
+    0xA5: ("LDA", "zp"), 0xA6: ("LDX", "zp"), 0xA8: ("TAY", "impl"), 0xA9: ("LDA", "imm"),
+    0xAA: ("TAX", "impl"), 0xAC: ("LDY", "abs"), 0xAD: ("LDA", "abs"), 0xAE: ("LDX", "abs"),
+    0xB0: ("BCS", "rel"), 0xB1: ("LDA", "izy"), 0xB4: ("LDY", "zpx"), 0xB5: ("LDA", "zpx"),
+    0xB6: ("LDX", "zpy"), 0xB8: ("CLV", "impl"), 0xB9: ("LDA", "absy"), 0xBA: ("TSX", "impl"),
+    0xBC: ("LDY", "absx"), 0xBD: ("LDA", "absx"), 0xBE: ("LDX", "absy"), 0xC0: ("CPY", "imm"),
+    0xC1: ("CMP", "izx"), 0xC4: ("CPY", "zp"), 0xC5: ("CMP", "zp"), 0xC6: ("DEC", "zp"),
+    0xC8: ("INY", "impl"), 0xC9: ("CMP", "imm"), 0xCA: ("DEX", "impl"), 0xCC: ("CPY", "abs"),
+    0xCD: ("CMP", "abs"), 0xCE: ("DEC", "abs"), 0xD0: ("BNE", "rel"), 0xD1: ("CMP", "izy"),
+    0xD5: ("CMP", "zpx"), 0xD6: ("DEC", "zpx"), 0xD8: ("CLD", "impl"), 0xD9: ("CMP", "absy"),
+    0xDD: ("CMP", "absx"), 0xDE: ("DEC", "absx"), 0xE0: ("CPX", "imm"), 0xE1: ("SBC", "izx"),
+    0xE4: ("CPX", "zp"), 0xE5: ("SBC", "zp"), 0xE6: ("INC", "zp"), 0xE8: ("INX", "impl"),
+    0xE9: ("SBC", "imm"), 0xEA: ("NOP", "impl"), 0xEB: ("SBC", "imm"), 0xEC: ("CPX", "abs"),
+    0xED: ("SBC", "abs"), 0xEE: ("INC", "abs"), 0xF0: ("BEQ", "rel"), 0xF1: ("SBC", "izy"),
+    0xF5: ("SBC", "zpx"), 0xF6: ("INC", "zpx"), 0xF8: ("SED", "impl"), 0xF9: ("SBC", "absy"),
+    0xFD: ("SBC", "absx"), 0xFE: ("INC", "absx"),
+}
+
+
+def execute(cpu, opcode):
+    name, mode = OPCODES[opcode]
+    bus = cpu.bus
+    cpu.fetch()
+
+    if name == "BRK":
+        cpu.fetch()
+        cpu.push(cpu.pc >> 8)
+        cpu.push(cpu.pc & 0xFF)
+        cpu.push(cpu.p | B | U)
+        cpu.p |= I
+        lo = bus.read(0xFFFE)
+        hi = bus.read(0xFFFF)
+        cpu.pc = (hi << 8) | lo
+    elif name == "JSR":
+        lo = cpu.fetch()
+        bus.read(cpu.stack_addr())
+        cpu.push(cpu.pc >> 8)
+        cpu.push(cpu.pc & 0xFF)
+        hi = cpu.fetch()
+        cpu.pc = (hi << 8) | lo
+    elif name == "RTS":
+        bus.read(cpu.pc)
+        bus.read(cpu.stack_addr())
+        cpu.s = (cpu.s + 1) & 0xFF
+        lo = bus.read(cpu.stack_addr())
+        cpu.s = (cpu.s + 1) & 0xFF
+        hi = bus.read(cpu.stack_addr())
+        cpu.pc = (hi << 8) | lo
+        bus.read(cpu.pc)
+        cpu.pc = (cpu.pc + 1) & 0xFFFF
+    elif name == "RTI":
+        bus.read(cpu.pc)
+        bus.read(cpu.stack_addr())
+        cpu.s = (cpu.s + 1) & 0xFF
+        cpu.p = (bus.read(cpu.stack_addr()) & ~B & 0xFF) | U
+        cpu.s = (cpu.s + 1) & 0xFF
+        lo = bus.read(cpu.stack_addr())
+        cpu.s = (cpu.s + 1) & 0xFF
+        hi = bus.read(cpu.stack_addr())
+        cpu.pc = (hi << 8) | lo
+    elif name in ("PHA", "PHP"):
+        bus.read(cpu.pc)
+        cpu.push(cpu.a if name == "PHA" else cpu.p | B | U)
+    elif name in ("PLA", "PLP"):
+        bus.read(cpu.pc)
+        bus.read(cpu.stack_addr())
+        cpu.s = (cpu.s + 1) & 0xFF
+        val = bus.read(cpu.stack_addr())
+        if name == "PLA":
+            cpu.a = cpu.set_nz(val)
+        else:
+            cpu.p = (val & ~B & 0xFF) | U
+    elif name == "JMP" and mode == "abs":
+        cpu.pc = cpu.addr_abs()
+    elif name == "JMP":
+        ptr = cpu.addr_abs()
+        lo = bus.read(ptr)
+        # 上位アドレスはページをまたがない
+        hi = bus.read((ptr & 0xFF00) | ((ptr + 1) & 0xFF))
+        cpu.pc = (hi << 8) | lo
+    elif name in BRANCHES:
+        offset = cpu.fetch()
+        if BRANCHES[name](cpu.p):
+            bus.read(cpu.pc)
+            target = (cpu.pc + (offset - 256 if offset & 0x80 else offset)) & 0xFFFF
+            if (target & 0xFF00) != (cpu.pc & 0xFF00):
+                bus.read((cpu.pc & 0xFF00) | (target & 0xFF))
+            cpu.pc = target
+    elif mode == "impl":
+        bus.read(cpu.pc)
+        IMPLIED_OPS[name](cpu)
+    elif mode == "acc":
+        bus.read(cpu.pc)
+        cpu.a = RMW_OPS[name](cpu, cpu.a)
+    elif mode == "imm":
+        READ_OPS[name](cpu, cpu.fetch())
+    elif name in READ_OPS:
+        addr = cpu.resolve(mode, "r")
+        READ_OPS[name](cpu, bus.read(addr))
+    elif name in WRITE_OPS:
+        addr = cpu.resolve(mode, "w")
+        bus.write(addr, WRITE_OPS[name](cpu))
+    elif name in RMW_OPS:
+        addr = cpu.resolve(mode, "m")
+        val = bus.read(addr)
+        bus.write(addr, val)
+        bus.write(addr, RMW_OPS[name](cpu, val))
+    else:
+        raise ValueError(name)
+
+
+def state(cpu, ram):
+    return {
+        "pc": cpu.pc, "s": cpu.s, "a": cpu.a, "x": cpu.x, "y": cpu.y, "p": cpu.p,
+        "ram": [[addr, ram[addr]] for addr in sorted(ram)],
+    }
+
+
+def generate(opcode, rng):
+    bus = Bus(rng)
+    pc = rng.randrange(0x10000)
+    # P のBフラグは実在しないので0、bit5 は常に1。
+    p = (rng.randrange(256) & ~B & 0xFF) | U
+    cpu = Cpu(bus, pc, rng.randrange(256), rng.randrange(256),
+              rng.randrange(256), rng.randrange(256), p)
+    bus.fill(pc, opcode)
+    if OPCODES[opcode] == ("JMP", "ind") and rng.randrange(2):
+        # ページ境界をまたぐポインタ (6502のバグ) を確実に含める
+        bus.fill(pc + 1, 0xFF)
+    initial_regs = dict(pc=cpu.pc, s=cpu.s, a=cpu.a, x=cpu.x, y=cpu.y, p=cpu.p)
+
+    execute(cpu, opcode)
+
+    initial = dict(initial_regs)
+    initial["ram"] = [[addr, bus.initial[addr]] for addr in sorted(bus.initial)]
+    name = " ".join("%02x" % bus.initial.get((pc + i) & 0xFFFF, 0) for i in range(3))
+    return {
+        "name": name,
+        "initial": initial,
+        "final": state(cpu, bus.mem),
+        "cycles": bus.cycles,
+    }
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--count", type=int, default=10)
+    parser.add_argument("--seed", type=int, default=6502)
+    parser.add_argument("--out", default=os.path.join(
+        os.path.dirname(__file__), "..", "tests", "data", "cpu"))
+    args = parser.parse_args()
+
+    os.makedirs(args.out, exist_ok=True)
+    for opcode in sorted(OPCODES):
+        rng = random.Random(args.seed * 256 + opcode)
+        tests = [generate(opcode, rng) for _ in range(args.count)]
+        path = os.path.join(args.out, "%02x.json" % opcode)
+        with open(path, "w") as f:
+            f.write("[\n")
+            f.write(",\n".join(json.dumps(t, separators=(",", ":")) for t in tests))
+            f.write("\n]\n")
+
+
+if __name__ == "__main__":
+    main()